@@ -0,0 +1,2 @@
+-- Custom attributes available to newsletter templates as merge fields
+Alter Table subscriptions Add Column attributes JSONB Not Null Default '{}'::jsonb;
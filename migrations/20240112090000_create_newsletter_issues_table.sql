@@ -0,0 +1,11 @@
+-- Persist every published issue for the public archive and feeds
+Create Table newsletter_issues(
+    newsletter_issue_id uuid Not Null,
+    slug Text Not Null Unique,
+    title Text Not Null,
+    html_content Text Not Null,
+    text_content Text Not Null,
+    published_at Timestamptz Not Null,
+    Primary Key (newsletter_issue_id)
+);
+Create Index newsletter_issues_published_at_idx On newsletter_issues (published_at Desc);
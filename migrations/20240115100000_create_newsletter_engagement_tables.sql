@@ -0,0 +1,24 @@
+-- Per-issue tracking switches
+Alter Table newsletter_issues Add Column track_opens Boolean Not Null Default false;
+Alter Table newsletter_issues Add Column track_clicks Boolean Not Null Default false;
+
+-- One row per subscriber an issue was delivered to
+Create Table newsletter_deliveries(
+    newsletter_issue_id uuid Not Null References newsletter_issues (newsletter_issue_id),
+    subscriber_id uuid Not Null References subscriptions (id),
+    delivered_at Timestamptz Not Null,
+    Primary Key (newsletter_issue_id, subscriber_id)
+);
+
+-- Opens and clicks recorded by the tracking endpoints
+Create Table newsletter_engagement_events(
+    id Bigint Generated Always As Identity,
+    newsletter_issue_id uuid Not Null References newsletter_issues (newsletter_issue_id),
+    subscriber_id uuid Not Null References subscriptions (id),
+    kind Text Not Null,
+    url Text Null,
+    occurred_at Timestamptz Not Null,
+    Primary Key (id)
+);
+Create Index newsletter_engagement_events_issue_idx
+    On newsletter_engagement_events (newsletter_issue_id, kind);
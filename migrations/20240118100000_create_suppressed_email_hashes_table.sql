@@ -0,0 +1,6 @@
+-- Fingerprints of erased subscribers, so that they are never subscribed again
+Create Table suppressed_email_hashes(
+    email_hash Text Not Null,
+    suppressed_at Timestamptz Not Null,
+    Primary Key (email_hash)
+);
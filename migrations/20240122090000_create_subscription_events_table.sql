@@ -0,0 +1,23 @@
+-- Append-only history of every subscription state change, used as proof of consent
+Create Table subscription_events(
+    id Bigint Generated Always As Identity,
+    subscriber_id uuid Not Null References subscriptions (id),
+    kind Text Not Null,
+    occurred_at Timestamptz Not Null,
+    ip_address Text Null,
+    user_agent Text Null,
+    source Text Not Null,
+    Primary Key (id)
+);
+Create Index subscription_events_subscriber_idx On subscription_events (subscriber_id, occurred_at);
+
+-- Rows are never rewritten; erasure is the only way they leave the table
+Create Function reject_subscription_event_updates() Returns Trigger As $$
+Begin
+    Raise Exception 'subscription_events is append-only';
+End;
+$$ Language plpgsql;
+
+Create Trigger subscription_events_append_only
+    Before Update On subscription_events
+    For Each Row Execute Function reject_subscription_event_updates();
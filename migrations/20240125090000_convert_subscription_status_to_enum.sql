@@ -0,0 +1,9 @@
+-- Restrict subscription statuses to the states the application knows about
+Create Type subscription_status As Enum (
+    'pending_confirmation',
+    'confirmed',
+    'unsubscribed',
+    'bounced'
+);
+Alter Table subscriptions
+    Alter Column status Type subscription_status Using status::subscription_status;
@@ -0,0 +1,11 @@
+-- Addresses and whole domains that must never receive an email from us
+Create Table suppressions(
+    id Bigint Generated Always As Identity,
+    kind Text Not Null Check (kind In ('address', 'domain')),
+    value Text Not Null,
+    reason Text Not Null Check (reason In ('unsubscribed', 'bounced', 'complained', 'manual')),
+    note Text Null,
+    created_at Timestamptz Not Null,
+    Primary Key (id),
+    Unique (kind, value)
+);
@@ -0,0 +1,76 @@
+-- Tell addresses apart by their canonical form rather than as typed.
+-- Existing rows are normalized by lowercasing; internationalised domains stored before this
+-- migration keep their Unicode form until the subscriber is updated.
+Alter Table subscriptions Add Column email_normalized Text Null;
+Update subscriptions Set email_normalized = Lower(Trim(email));
+
+-- Merge duplicates into the oldest subscription of each address
+Create Temporary Table subscription_merges As
+Select
+    id As duplicate_id,
+    First_Value(id) Over (
+        Partition By email_normalized Order By subscribed_at, id
+    ) As survivor_id
+From subscriptions;
+Delete From subscription_merges Where duplicate_id = survivor_id;
+
+-- Keep the most restrictive status: bounced > unsubscribed > confirmed > pending_confirmation
+Update subscriptions s
+Set status = merged.status
+From (
+    Select m.survivor_id, Max(d.status) As status
+    From subscription_merges m
+    Join subscriptions d On d.id In (m.duplicate_id, m.survivor_id)
+    Group By m.survivor_id
+) merged
+Where s.id = merged.survivor_id;
+
+-- Attributes of the surviving row win over those of its duplicates
+Update subscriptions s
+Set attributes = merged.attributes || s.attributes
+From (
+    Select m.survivor_id, Jsonb_Object_Agg(a.key, a.value) As attributes
+    From subscription_merges m
+    Join subscriptions d On d.id = m.duplicate_id
+    Cross Join Jsonb_Each(d.attributes) a
+    Group By m.survivor_id
+) merged
+Where s.id = merged.survivor_id;
+
+Update subscription_tokens t
+Set subscriber_id = m.survivor_id
+From subscription_merges m
+Where t.subscriber_id = m.duplicate_id;
+
+-- The history is otherwise append-only, but it has to follow the merged subscriber
+Alter Table subscription_events Disable Trigger subscription_events_append_only;
+Update subscription_events e
+Set subscriber_id = m.survivor_id
+From subscription_merges m
+Where e.subscriber_id = m.duplicate_id;
+Alter Table subscription_events Enable Trigger subscription_events_append_only;
+
+Update newsletter_engagement_events e
+Set subscriber_id = m.survivor_id
+From subscription_merges m
+Where e.subscriber_id = m.duplicate_id;
+
+-- Deliveries are unique per issue and subscriber, keep the earliest one
+Insert Into newsletter_deliveries (newsletter_issue_id, subscriber_id, delivered_at)
+Select d.newsletter_issue_id, m.survivor_id, Min(d.delivered_at)
+From newsletter_deliveries d
+Join subscription_merges m On d.subscriber_id = m.duplicate_id
+Group By d.newsletter_issue_id, m.survivor_id
+On Conflict (newsletter_issue_id, subscriber_id) Do Nothing;
+Delete From newsletter_deliveries d
+Using subscription_merges m
+Where d.subscriber_id = m.duplicate_id;
+
+Delete From subscriptions s
+Using subscription_merges m
+Where s.id = m.duplicate_id;
+Drop Table subscription_merges;
+
+Alter Table subscriptions Alter Column email_normalized Set Not Null;
+Alter Table subscriptions Add Constraint subscriptions_email_normalized_key Unique (email_normalized);
+Alter Table subscriptions Drop Constraint subscriptions_email_key;
@@ -0,0 +1,7 @@
+-- Request counters shared by every replica when rate limits are stored in Postgres
+Create Table rate_limit_buckets(
+    key Text Not Null,
+    window_started_at Timestamptz Not Null,
+    hits Integer Not Null,
+    Primary Key (key)
+);
@@ -0,0 +1,2 @@
+-- Pending subscribers get a single reminder before they are cleaned up
+Alter Table subscriptions Add Column confirmation_reminder_sent_at Timestamptz Null;
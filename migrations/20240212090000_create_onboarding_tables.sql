@@ -0,0 +1,20 @@
+-- The onboarding sequence, each email going out `delay_days` after a subscriber confirms
+Create Table onboarding_emails(
+    id uuid Not Null,
+    delay_days Integer Not Null Check (delay_days >= 0),
+    subject Text Not Null,
+    html_body Text Not Null,
+    text_body Text Not Null,
+    created_at Timestamptz Not Null,
+    Primary Key (id)
+);
+
+-- The sequence scheduled for each subscriber when they confirmed
+Create Table onboarding_deliveries(
+    subscriber_id uuid Not Null References subscriptions (id),
+    onboarding_email_id uuid Not Null References onboarding_emails (id) On Delete Cascade,
+    confirmed_at Timestamptz Not Null,
+    sent_at Timestamptz Null,
+    Primary Key (subscriber_id, onboarding_email_id)
+);
+Create Index onboarding_deliveries_unsent_idx On onboarding_deliveries (confirmed_at) Where sent_at Is Null;
@@ -0,0 +1,5 @@
+-- Confirmation links expire, so tokens need to know when they were issued.
+-- Existing tokens start their lifetime now rather than expiring on deploy.
+Alter Table subscription_tokens Add Column created_at Timestamptz Null;
+Update subscription_tokens Set created_at = Now();
+Alter Table subscription_tokens Alter Column created_at Set Not Null;
@@ -0,0 +1,14 @@
+-- How each subscriber's consent was collected: `double` (confirmed by email),
+-- `single` (confirmed on signup) or `imported` (asserted by an imported file)
+Alter Table subscriptions Add Column opt_in_mode Text Null;
+Update subscriptions s
+Set opt_in_mode = Case
+    When Exists (
+        Select 1 From subscription_events e
+        Where e.subscriber_id = s.id
+            And e.kind = 'subscribed'
+            And (e.source = 'POST /admin/subscribers/import' Or e.source Like 'import-subscribers %')
+    ) Then 'imported'
+    Else 'double'
+End;
+Alter Table subscriptions Alter Column opt_in_mode Set Not Null;
@@ -0,0 +1,20 @@
+-- Emails written in the same transaction as the change that triggers them, delivered by the
+-- outbox dispatcher once committed
+Create Table email_outbox(
+    id uuid Not Null,
+    subscriber_id uuid Null References subscriptions (id),
+    kind Text Not Null,
+    recipient Text Not Null,
+    subject Text Not Null,
+    html_body Text Not Null,
+    text_body Text Not Null,
+    created_at Timestamptz Not Null,
+    attempts Integer Not Null Default 0,
+    next_attempt_at Timestamptz Not Null,
+    last_error Text Null,
+    -- Set once the email was sent, or given up on; `outcome` says which
+    processed_at Timestamptz Null,
+    outcome Text Null,
+    Primary Key (id)
+);
+Create Index email_outbox_pending_idx On email_outbox (next_attempt_at) Where processed_at Is Null;
@@ -0,0 +1,5 @@
+-- Email hashes are now keyed (HMAC-SHA256), the plain SHA-256 ones could be reversed with a list
+-- of likely addresses. They cannot be re-keyed without the addresses, so they are dropped: the
+-- erased subscribers can sign up again, which still takes confirming their address.
+Delete From suppressed_email_hashes;
+Delete From rate_limit_buckets Where key Like 'email:%';
@@ -0,0 +1,6 @@
+-- Buckets are counted against different windows, each row records when its own ends so that
+-- expired ones can be deleted. Existing rows get the longest window we configure by default.
+Alter Table rate_limit_buckets Add Column expires_at Timestamptz Null;
+Update rate_limit_buckets Set expires_at = window_started_at + Interval '1 day';
+Alter Table rate_limit_buckets Alter Column expires_at Set Not Null;
+Create Index rate_limit_buckets_expires_at_idx On rate_limit_buckets (expires_at);
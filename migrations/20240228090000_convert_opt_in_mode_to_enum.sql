@@ -0,0 +1,8 @@
+-- Restrict opt-in modes to the ones the application knows about, like statuses
+Create Type opt_in_mode As Enum (
+    'double',
+    'single',
+    'imported'
+);
+Alter Table subscriptions
+    Alter Column opt_in_mode Type opt_in_mode Using opt_in_mode::opt_in_mode;
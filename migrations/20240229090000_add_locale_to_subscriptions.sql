@@ -0,0 +1,3 @@
+-- The locale of the confirmation email chosen at signup, for the emails that follow it.
+-- Null for subscribers who signed up before, or were imported: they get the default locale.
+Alter Table subscriptions Add Column locale Text Null;
@@ -0,0 +1,13 @@
+-- One row per subscriber an issue still has to be delivered to, drained by the delivery worker
+Create Table newsletter_delivery_queue(
+    newsletter_issue_id uuid Not Null References newsletter_issues (newsletter_issue_id),
+    subscriber_id uuid Not Null References subscriptions (id),
+    attempts Integer Not Null Default 0,
+    next_attempt_at Timestamptz Not Null,
+    Primary Key (newsletter_issue_id, subscriber_id)
+);
+Create Index newsletter_delivery_queue_next_attempt_at_idx
+    On newsletter_delivery_queue (next_attempt_at);
+
+-- Lets a client retry a publish without creating a second issue
+Alter Table newsletter_issues Add Column idempotency_key Text Null Unique;
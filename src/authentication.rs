@@ -0,0 +1,82 @@
+use actix_web::http::header::{HeaderMap, AUTHORIZATION};
+use anyhow::Context;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+
+use crate::configuration::{AdminSettings, WebhookSettings};
+
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+/// Extract the credentials of the `Basic` scheme from the `Authorization` header
+pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    let header_value = headers
+        .get(AUTHORIZATION)
+        .context("The 'Authorization' header was missing.")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string.")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was not 'Basic'.")?;
+    let decoded_bytes = STANDARD
+        .decode(base64encoded_segment)
+        .context("Failed to base64-decode 'Basic' credentials.")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credential string is not valid UTF8.")?;
+
+    let (username, password) = decoded_credentials
+        .split_once(':')
+        .context("A password must be provided in 'Basic' auth.")?;
+    Ok(Credentials {
+        username: username.to_string(),
+        password: Secret::new(password.to_string()),
+    })
+}
+
+/// Check the credentials against the configured admin account
+pub fn validate_admin_credentials(
+    credentials: &Credentials,
+    admin: &AdminSettings,
+) -> Result<(), anyhow::Error> {
+    check_credentials(credentials, &admin.username, &admin.password)
+}
+
+/// Check the credentials against the ones the email provider calls our webhooks with
+pub fn validate_webhook_credentials(
+    credentials: &Credentials,
+    webhook: &WebhookSettings,
+) -> Result<(), anyhow::Error> {
+    check_credentials(credentials, &webhook.username, &webhook.password)
+}
+
+fn check_credentials(
+    credentials: &Credentials,
+    username: &str,
+    password: &Secret<String>,
+) -> Result<(), anyhow::Error> {
+    let valid_username = constant_time_eq(&credentials.username, username);
+    let valid_password = constant_time_eq(
+        credentials.password.expose_secret(),
+        password.expose_secret(),
+    );
+    if valid_username & valid_password {
+        Ok(())
+    } else {
+        anyhow::bail!("Invalid username or password.")
+    }
+}
+
+/// Compare without returning early, so that timing reveals nothing about `expected`.
+///
+/// Digests are compared rather than the strings themselves, which also hides the length.
+fn constant_time_eq(given: &str, expected: &str) -> bool {
+    Sha256::digest(given.as_bytes())
+        .iter()
+        .zip(Sha256::digest(expected.as_bytes()).iter())
+        .fold(0, |difference, (a, b)| difference | (a ^ b))
+        == 0
+}
@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use anyhow::Context;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use rand::{thread_rng, Rng};
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+
+use crate::configuration::{
+    CaptchaSettings, CaptchaVerifierKind, HttpCaptchaSettings, ProofOfWorkSettings,
+};
+
+/// Checks the token a client submits with the subscribe form to prove it is not a bot
+#[async_trait::async_trait]
+pub trait CaptchaVerifier: Send + Sync {
+    /// `Ok(false)` for a wrong, expired or replayed token, errors are reserved for outages
+    async fn verify(&self, token: &str, remote_ip: Option<&str>) -> Result<bool, anyhow::Error>;
+
+    /// A challenge to solve before submitting the form, for verifiers that issue their own
+    fn issue_challenge(&self) -> Option<ProofOfWorkChallenge> {
+        None
+    }
+}
+
+/// `None` when the subscribe form does not require a CAPTCHA
+pub fn captcha_verifier(settings: &CaptchaSettings) -> Option<Box<dyn CaptchaVerifier>> {
+    match settings.verifier {
+        CaptchaVerifierKind::Disabled => None,
+        CaptchaVerifierKind::Http => Some(Box::new(HttpCaptchaVerifier::new(&settings.http))),
+        CaptchaVerifierKind::ProofOfWork => {
+            Some(Box::new(ProofOfWorkVerifier::new(&settings.proof_of_work)))
+        }
+    }
+}
+
+/// Verifies tokens against an hCaptcha or Cloudflare Turnstile style `siteverify` endpoint
+pub struct HttpCaptchaVerifier {
+    http_client: Client,
+    verify_url: String,
+    secret_key: Secret<String>,
+}
+
+#[derive(serde::Serialize)]
+struct SiteVerifyRequest<'a> {
+    secret: &'a str,
+    response: &'a str,
+    #[serde(rename = "remoteip", skip_serializing_if = "Option::is_none")]
+    remote_ip: Option<&'a str>,
+}
+
+#[derive(serde::Deserialize)]
+struct SiteVerifyResponse {
+    success: bool,
+}
+
+impl HttpCaptchaVerifier {
+    pub fn new(settings: &HttpCaptchaSettings) -> Self {
+        let http_client = Client::builder()
+            .timeout(settings.timeout())
+            .build()
+            .unwrap();
+        Self {
+            http_client,
+            verify_url: settings.verify_url.clone(),
+            secret_key: settings.secret_key.clone(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl CaptchaVerifier for HttpCaptchaVerifier {
+    #[tracing::instrument(name = "Verify a CAPTCHA token with the provider", skip(self, token))]
+    async fn verify(&self, token: &str, remote_ip: Option<&str>) -> Result<bool, anyhow::Error> {
+        let request = SiteVerifyRequest {
+            secret: self.secret_key.expose_secret(),
+            response: token,
+            remote_ip,
+        };
+        let response: SiteVerifyResponse = self
+            .http_client
+            .post(&self.verify_url)
+            .form(&request)
+            .send()
+            .await
+            .context("Failed to reach the CAPTCHA provider.")?
+            .error_for_status()
+            .context("The CAPTCHA provider failed to verify the token.")?
+            .json()
+            .await
+            .context("The CAPTCHA provider returned an unexpected response.")?;
+        Ok(response.success)
+    }
+}
+
+/// What a client must solve: find a `nonce` such that the SHA-256 of `<challenge>:<nonce>`
+/// starts with `difficulty` zero bits, then submit `<challenge>:<nonce>` as the token
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ProofOfWorkChallenge {
+    pub challenge: String,
+    pub difficulty: u32,
+}
+
+/// Self-hosted proof of work, needing no third party.
+///
+/// Challenges are signed rather than stored. Solved challenges are remembered in memory
+/// until they expire so that a solution cannot be replayed on the same replica.
+pub struct ProofOfWorkVerifier {
+    difficulty: u32,
+    challenge_ttl_seconds: i64,
+    signing_key: Secret<String>,
+    spent_challenges: Mutex<HashMap<String, i64>>,
+}
+
+impl ProofOfWorkVerifier {
+    pub fn new(settings: &ProofOfWorkSettings) -> Self {
+        Self {
+            difficulty: settings.difficulty,
+            challenge_ttl_seconds: settings.challenge_ttl_seconds as i64,
+            signing_key: settings.signing_key.clone(),
+            spent_challenges: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The timestamp the challenge was issued at, if we signed it with the current difficulty
+    fn issued_at(&self, challenge: &str) -> Option<i64> {
+        let (payload, signature) = challenge.rsplit_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        self.mac(payload).verify_slice(&signature).ok()?;
+        let (issued_at, _salt) = payload.split_once('.')?;
+        issued_at.parse().ok()
+    }
+
+    /// Signing the difficulty too invalidates outstanding challenges when it is raised
+    fn mac(&self, payload: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.signing_key.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size.");
+        mac.update(format!("{}.{}", payload, self.difficulty).as_bytes());
+        mac
+    }
+}
+
+#[async_trait::async_trait]
+impl CaptchaVerifier for ProofOfWorkVerifier {
+    #[tracing::instrument(name = "Verify a proof of work", skip(self, token))]
+    async fn verify(&self, token: &str, _remote_ip: Option<&str>) -> Result<bool, anyhow::Error> {
+        let Some((challenge, _nonce)) = token.rsplit_once(':') else {
+            return Ok(false);
+        };
+        let Some(issued_at) = self.issued_at(challenge) else {
+            return Ok(false);
+        };
+        let now = Utc::now().timestamp();
+        if issued_at > now || now - issued_at > self.challenge_ttl_seconds {
+            return Ok(false);
+        }
+        if leading_zero_bits(&Sha256::digest(token)) < self.difficulty {
+            return Ok(false);
+        }
+
+        let mut spent_challenges = self.spent_challenges.lock().unwrap();
+        spent_challenges.retain(|_, issued_at| now - *issued_at <= self.challenge_ttl_seconds);
+        Ok(spent_challenges
+            .insert(challenge.to_owned(), issued_at)
+            .is_none())
+    }
+
+    fn issue_challenge(&self) -> Option<ProofOfWorkChallenge> {
+        let salt: [u8; 16] = thread_rng().gen();
+        let payload = format!(
+            "{}.{}",
+            Utc::now().timestamp(),
+            URL_SAFE_NO_PAD.encode(salt)
+        );
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(&payload).finalize().into_bytes());
+        Some(ProofOfWorkChallenge {
+            challenge: format!("{}.{}", payload, signature),
+            difficulty: self.difficulty,
+        })
+    }
+}
+
+/// Reference solver, doing what the script on the subscribe form does
+pub fn solve_proof_of_work(challenge: &ProofOfWorkChallenge) -> String {
+    (0u64..)
+        .map(|nonce| format!("{}:{}", challenge.challenge, nonce))
+        .find(|token| leading_zero_bits(&Sha256::digest(token)) >= challenge.difficulty)
+        .expect("Some nonce always solves the challenge.")
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        leading_zero_bits, solve_proof_of_work, CaptchaVerifier, HttpCaptchaVerifier,
+        ProofOfWorkVerifier,
+    };
+    use crate::configuration::{HttpCaptchaSettings, ProofOfWorkSettings};
+    use claims::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+    use sha2::{Digest, Sha256};
+    use wiremock::matchers::{any, body_string_contains, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn proof_of_work_verifier(challenge_ttl_seconds: u64) -> ProofOfWorkVerifier {
+        ProofOfWorkVerifier::new(&ProofOfWorkSettings {
+            difficulty: 8,
+            challenge_ttl_seconds,
+            signing_key: Secret::new("signing-key".into()),
+        })
+    }
+
+    fn http_verifier(verify_url: String) -> HttpCaptchaVerifier {
+        HttpCaptchaVerifier::new(&HttpCaptchaSettings {
+            verify_url,
+            secret_key: Secret::new("secret-key".into()),
+            timeout_milliseconds: 200,
+        })
+    }
+
+    #[test]
+    fn leading_zero_bits_span_bytes() {
+        assert_eq!(leading_zero_bits(&[0, 0b0001_0000, 0]), 11);
+        assert_eq!(leading_zero_bits(&[0xff]), 0);
+    }
+
+    #[tokio::test]
+    async fn a_solved_challenge_is_accepted_once() {
+        let verifier = proof_of_work_verifier(600);
+        let token = solve_proof_of_work(&verifier.issue_challenge().unwrap());
+
+        assert_ok_eq!(verifier.verify(&token, None).await, true);
+        assert_ok_eq!(verifier.verify(&token, None).await, false);
+    }
+
+    #[tokio::test]
+    async fn an_unsolved_challenge_is_rejected() {
+        let verifier = proof_of_work_verifier(600);
+        let challenge = verifier.issue_challenge().unwrap();
+        let token = (0u64..)
+            .map(|nonce| format!("{}:{}", challenge.challenge, nonce))
+            .find(|token| leading_zero_bits(&Sha256::digest(token)) < 8)
+            .unwrap();
+
+        assert_ok_eq!(verifier.verify(&token, None).await, false);
+    }
+
+    #[tokio::test]
+    async fn challenges_we_did_not_sign_are_rejected() {
+        let verifier = proof_of_work_verifier(600);
+        let mut challenge = verifier.issue_challenge().unwrap();
+        challenge.challenge = format!("1{}", challenge.challenge);
+        let token = solve_proof_of_work(&challenge);
+
+        assert_ok_eq!(verifier.verify(&token, None).await, false);
+    }
+
+    #[tokio::test]
+    async fn expired_challenges_are_rejected() {
+        let verifier = proof_of_work_verifier(0);
+        let token = solve_proof_of_work(&verifier.issue_challenge().unwrap());
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+        assert_ok_eq!(verifier.verify(&token, None).await, false);
+    }
+
+    #[tokio::test]
+    async fn the_http_verifier_forwards_the_token_and_reads_the_verdict() {
+        let mock_server = MockServer::start().await;
+        let verifier = http_verifier(mock_server.uri());
+        Mock::given(method("POST"))
+            .and(body_string_contains("secret=secret-key"))
+            .and(body_string_contains("response=good-token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "success": true
+            })))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_string_contains("response=bad-token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "success": false,
+                "error-codes": ["invalid-input-response"]
+            })))
+            .mount(&mock_server)
+            .await;
+
+        assert_ok_eq!(verifier.verify("good-token", Some("127.0.0.1")).await, true);
+        assert_ok_eq!(verifier.verify("bad-token", None).await, false);
+    }
+
+    #[tokio::test]
+    async fn the_http_verifier_fails_if_the_provider_is_down() {
+        let mock_server = MockServer::start().await;
+        let verifier = http_verifier(mock_server.uri());
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+
+        assert_err!(verifier.verify("good-token", None).await);
+    }
+}
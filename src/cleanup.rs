@@ -0,0 +1,239 @@
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::configuration::{PendingCleanupSettings, Settings};
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName, SubscriptionStatus};
+use crate::email_templates::ConfirmationEmailTemplates;
+use crate::routes::{
+    delete_subscriber, enqueue_confirmation_email, generate_subscription_token, store_token,
+};
+use crate::shutdown::ShutdownSignal;
+use crate::startup::get_connection_pool;
+
+/// Subscribers are locked, reminded or deleted, and committed this many at a time
+const BATCH_SIZE: i64 = 100;
+
+/// What one pass of the cleanup did
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CleanupReport {
+    pub reminded: u64,
+    pub deleted: u64,
+}
+
+/// Periodically reminds subscribers who did not confirm their address, then deletes them.
+///
+/// Every replica runs its own worker: rows are claimed with `For Update Skip Locked`, so two
+/// workers never remind or delete the same subscriber. Reminders are written to the outbox
+/// along with the claim, the outbox dispatcher sends them.
+pub struct PendingSubscriberCleanup {
+    db_pool: PgPool,
+    base_url: String,
+    templates: ConfirmationEmailTemplates,
+    link_ttl: chrono::Duration,
+    settings: PendingCleanupSettings,
+}
+
+impl PendingSubscriberCleanup {
+    pub fn build(configuration: &Settings) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            db_pool: get_connection_pool(&configuration.database),
+            base_url: configuration.application.base_url.clone(),
+            templates: ConfirmationEmailTemplates::load(&configuration.confirmation_email)?,
+            link_ttl: configuration.confirmation_link.ttl(),
+            settings: configuration.pending_cleanup.clone(),
+        })
+    }
+
+    /// Runs until shutdown is triggered, finishing the pass in progress if any
+    pub async fn run_until_stopped(self, mut shutdown: ShutdownSignal) {
+        let mut interval = tokio::time::interval(self.settings.interval());
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.triggered() => return,
+            }
+            match self.run_once().await {
+                Ok(report) => tracing::info!(
+                    reminded = report.reminded,
+                    deleted = report.deleted,
+                    "Cleaned up pending subscribers"
+                ),
+                Err(e) => tracing::error!(
+                    error.cause_chain = ?e,
+                    "Failed to clean up pending subscribers"
+                ),
+            }
+        }
+    }
+
+    #[tracing::instrument(name = "Clean up pending subscribers", skip(self))]
+    pub async fn run_once(&self) -> Result<CleanupReport, anyhow::Error> {
+        let reminded = self.send_reminders().await?;
+        let deleted = self.delete_stale_subscribers().await?;
+        Ok(CleanupReport { reminded, deleted })
+    }
+
+    async fn send_reminders(&self) -> Result<u64, anyhow::Error> {
+        let now = Utc::now();
+        let remind_before = now - self.settings.remind_after();
+        // Those are deleted in the same pass, a reminder would only point to a dead link
+        let delete_before = now - self.settings.delete_after();
+        let mut reminded = 0;
+        loop {
+            let mut txn = self
+                .db_pool
+                .begin()
+                .await
+                .context("Failed to acquire a Postgres connection from the pool")?;
+            let subscribers = sqlx::query!(
+                r#"
+                Select id, email, name, locale
+                From subscriptions
+                Where status = $1
+                    And subscribed_at <= $2
+                    And subscribed_at > $3
+                    And confirmation_reminder_sent_at Is Null
+                Order By subscribed_at
+                Limit $4
+                For Update Skip Locked
+                "#,
+                SubscriptionStatus::PendingConfirmation as SubscriptionStatus,
+                remind_before,
+                delete_before,
+                BATCH_SIZE
+            )
+            .fetch_all(&mut *txn)
+            .await
+            .context("Failed to fetch the subscribers to remind.")?;
+            let claimed = subscribers.len() as i64;
+
+            for subscriber in subscribers {
+                let new_subscriber = match (
+                    SubscriberEmail::parse(subscriber.email),
+                    SubscriberName::parse(subscriber.name),
+                ) {
+                    (Ok(email), Ok(name)) => Some(NewSubscriber { email, name }),
+                    // Stored before the current validation rules, it will be deleted in due course
+                    (email, name) => {
+                        tracing::warn!(
+                            subscriber_id = %subscriber.id,
+                            error = ?email.err().or(name.err()),
+                            "Not reminding a subscriber with invalid details"
+                        );
+                        None
+                    }
+                };
+                if let Some(new_subscriber) = new_subscriber {
+                    let token = confirmation_token(&mut txn, subscriber.id, self.link_ttl).await?;
+                    // Suppressed addresses are skipped by the outbox dispatcher
+                    enqueue_confirmation_email(
+                        &mut txn,
+                        subscriber.id,
+                        &new_subscriber,
+                        &self.base_url,
+                        &token,
+                        self.templates.select(subscriber.locale.as_deref(), None),
+                    )
+                    .await
+                    .context("Failed to write a confirmation reminder to the outbox.")?;
+                    reminded += 1;
+                }
+                mark_reminded(&mut txn, subscriber.id).await?;
+            }
+            txn.commit()
+                .await
+                .context("Failed to commit SQL transaction to remind pending subscribers.")?;
+            if claimed < BATCH_SIZE {
+                return Ok(reminded);
+            }
+        }
+    }
+
+    async fn delete_stale_subscribers(&self) -> Result<u64, anyhow::Error> {
+        let delete_before = Utc::now() - self.settings.delete_after();
+        let mut deleted = 0;
+        loop {
+            let mut txn = self
+                .db_pool
+                .begin()
+                .await
+                .context("Failed to acquire a Postgres connection from the pool")?;
+            let subscriber_ids = sqlx::query_scalar!(
+                r#"
+                Select id
+                From subscriptions
+                Where status = $1 And subscribed_at <= $2
+                Limit $3
+                For Update Skip Locked
+                "#,
+                SubscriptionStatus::PendingConfirmation as SubscriptionStatus,
+                delete_before,
+                BATCH_SIZE
+            )
+            .fetch_all(&mut *txn)
+            .await
+            .context("Failed to fetch the stale pending subscribers.")?;
+            let claimed = subscriber_ids.len() as i64;
+            for subscriber_id in subscriber_ids {
+                delete_subscriber(&mut txn, subscriber_id)
+                    .await
+                    .context("Failed to delete a stale pending subscriber.")?;
+            }
+            txn.commit()
+                .await
+                .context("Failed to commit SQL transaction to delete pending subscribers.")?;
+            deleted += claimed as u64;
+            if claimed < BATCH_SIZE {
+                return Ok(deleted);
+            }
+        }
+    }
+}
+
+/// The token from the original confirmation email while it is valid, so that either email works
+async fn confirmation_token(
+    txn: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    link_ttl: chrono::Duration,
+) -> Result<String, anyhow::Error> {
+    let token = sqlx::query_scalar!(
+        r#"
+        Select subscription_token
+        From subscription_tokens
+        Where subscriber_id = $1 And created_at > $2
+        Order By created_at Desc
+        Limit 1
+        "#,
+        subscriber_id,
+        Utc::now() - link_ttl
+    )
+    .fetch_optional(&mut **txn)
+    .await
+    .context("Failed to fetch the confirmation token.")?;
+    if let Some(token) = token {
+        return Ok(token);
+    }
+    let token = generate_subscription_token();
+    store_token(txn, subscriber_id, &token)
+        .await
+        .context("Failed to store a new confirmation token.")?;
+    Ok(token)
+}
+
+async fn mark_reminded(
+    txn: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"Update subscriptions Set confirmation_reminder_sent_at = $2 Where id = $1"#,
+        subscriber_id,
+        Utc::now()
+    );
+    txn.execute(query)
+        .await
+        .context("Failed to record that the reminder was sent.")?;
+    Ok(())
+}
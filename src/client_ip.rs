@@ -0,0 +1,117 @@
+use std::net::IpAddr;
+
+use actix_web::{web, HttpRequest};
+
+/// Proxies, e.g. our load balancer, whose `X-Forwarded-For` header we believe.
+///
+/// Anybody else can put whatever they like in that header, so for them the address of the
+/// connection is all we know.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Vec<IpAddr>);
+
+impl TrustedProxies {
+    pub fn new(addresses: Vec<IpAddr>) -> Self {
+        Self(addresses)
+    }
+
+    fn contains(&self, address: &IpAddr) -> bool {
+        self.0.contains(address)
+    }
+
+    /// The address of whoever sent `request`.
+    ///
+    /// Each proxy appends the address it received the request from to `X-Forwarded-For`, so
+    /// the header is read from the right, skipping our proxies: the entries further left were
+    /// written by the client and can be forged.
+    pub fn client_ip(&self, request: &HttpRequest) -> Option<IpAddr> {
+        let mut client = request.peer_addr()?.ip();
+        if !self.contains(&client) {
+            return Some(client);
+        }
+        let forwarded: Vec<&str> = request
+            .headers()
+            .get_all("X-Forwarded-For")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+        for hop in forwarded.into_iter().rev() {
+            match hop.trim().parse() {
+                Ok(address) => client = address,
+                // Anything unparsable was not written by a proxy of ours, stop trusting there
+                Err(_) => break,
+            }
+            if !self.contains(&client) {
+                break;
+            }
+        }
+        Some(client)
+    }
+}
+
+/// [`TrustedProxies::client_ip`] with the proxies registered on the application
+pub fn client_ip(request: &HttpRequest) -> Option<IpAddr> {
+    match request.app_data::<web::Data<TrustedProxies>>() {
+        Some(trusted_proxies) => trusted_proxies.client_ip(request),
+        None => TrustedProxies::default().client_ip(request),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TrustedProxies;
+    use actix_web::test::TestRequest;
+    use std::net::{IpAddr, SocketAddr};
+
+    const PROXY: &str = "10.0.0.1";
+
+    fn request(peer: &str, forwarded_for: Option<&str>) -> actix_web::HttpRequest {
+        let peer: IpAddr = peer.parse().unwrap();
+        let mut request = TestRequest::default().peer_addr(SocketAddr::new(peer, 443));
+        if let Some(forwarded_for) = forwarded_for {
+            request = request.insert_header(("X-Forwarded-For", forwarded_for));
+        }
+        request.to_http_request()
+    }
+
+    fn trusted() -> TrustedProxies {
+        TrustedProxies::new(vec![PROXY.parse().unwrap()])
+    }
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn the_header_is_ignored_from_untrusted_peers() {
+        let request = request("203.0.113.7", Some("198.51.100.1"));
+        assert_eq!(trusted().client_ip(&request), ip("203.0.113.7"));
+        assert_eq!(
+            TrustedProxies::default().client_ip(&request),
+            ip("203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn trusted_proxies_tell_us_the_client_address() {
+        let request = request(PROXY, Some("198.51.100.1"));
+        assert_eq!(trusted().client_ip(&request), ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn addresses_prepended_by_the_client_are_ignored() {
+        let request = request(PROXY, Some("192.0.2.99, 198.51.100.1"));
+        assert_eq!(trusted().client_ip(&request), ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn chains_of_trusted_proxies_are_skipped() {
+        let request = request(PROXY, Some("198.51.100.1, 10.0.0.1"));
+        assert_eq!(trusted().client_ip(&request), ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn a_trusted_proxy_without_the_header_is_the_client() {
+        let request = request(PROXY, None);
+        assert_eq!(trusted().client_ip(&request), ip(PROXY));
+    }
+}
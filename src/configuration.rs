@@ -6,6 +6,8 @@ use sqlx::{
 };
 
 use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::suppression::EmailHasher;
 
 pub enum Environment {
     Local,
@@ -14,9 +16,40 @@ pub enum Environment {
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
+    pub admin: AdminSettings,
+    pub webhook: WebhookSettings,
     pub application: ApplicationSettings,
     pub database: DatabaseSettings,
     pub email_client: EmailClientSettings,
+    pub newsletter: NewsletterSettings,
+    pub confirmation_email: ConfirmationEmailSettings,
+    pub confirmation_link: ConfirmationLinkSettings,
+    pub signup_policy: SignupPolicySettings,
+    pub rate_limit: RateLimitSettings,
+    pub captcha: CaptchaSettings,
+    pub tracking: TrackingSettings,
+    pub suppression: SuppressionSettings,
+    pub pending_cleanup: PendingCleanupSettings,
+    pub onboarding: OnboardingSettings,
+    pub email_outbox: EmailOutboxSettings,
+    pub newsletter_delivery: NewsletterDeliverySettings,
+    pub shutdown: ShutdownSettings,
+    pub readiness: ReadinessSettings,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct AdminSettings {
+    /// Basic auth credentials for the admin endpoints
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct WebhookSettings {
+    /// Basic auth credentials the email provider calls our webhooks with, set in their URL.
+    /// Kept apart from the admin ones so that the provider never holds those.
+    pub username: String,
+    pub password: Secret<String>,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -44,6 +77,211 @@ pub struct EmailClientSettings {
     pub base_url: String,
     pub sender_email: String,
     pub timeout_milliseconds: u64,
+    /// Whether the provider delivers to addresses with non-ASCII local parts
+    pub supports_smtputf8: bool,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct NewsletterSettings {
+    /// Shown on the public archive and in the feeds
+    pub title: String,
+    /// HTML layout wrapping issues authored in Markdown
+    pub layout_path: String,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct ConfirmationEmailSettings {
+    /// Directory holding one sub-directory of templates per locale
+    pub templates_directory: String,
+    pub default_locale: String,
+}
+
+/// How long confirmation links work, and where following one leads.
+///
+/// Without a redirect URL for an outcome, we render a page ourselves.
+#[derive(serde::Deserialize, Clone)]
+pub struct ConfirmationLinkSettings {
+    pub ttl_hours: u64,
+    pub confirmed_redirect_url: Option<String>,
+    pub already_confirmed_redirect_url: Option<String>,
+    pub invalid_redirect_url: Option<String>,
+    pub expired_redirect_url: Option<String>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SignupPolicySettings {
+    /// File listing disposable email domains, one per line
+    pub disposable_domains_path: String,
+    /// Refuse shared mailboxes such as `postmaster@` or `noreply@`
+    pub reject_role_accounts: bool,
+    /// When not empty, only these domains may subscribe
+    pub allowed_domains: Vec<String>,
+    pub denied_domains: Vec<String>,
+    pub opt_in: OptInMode,
+}
+
+/// Whether new subscribers have to confirm their address before receiving anything
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OptInMode {
+    /// Send a confirmation email and wait for the subscriber to follow its link
+    #[default]
+    Double,
+    /// Confirm subscribers as soon as they sign up
+    Single,
+}
+
+impl std::str::FromStr for OptInMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "double" => Ok(OptInMode::Double),
+            "single" => Ok(OptInMode::Single),
+            other => Err(format!(
+                "{} is not a supported opt-in mode. Use either `double` or `single`.",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct RateLimitSettings {
+    pub enabled: bool,
+    /// Postgres shares the counters between replicas, memory is enough for a single one
+    pub store: RateLimitStoreKind,
+    pub per_ip: RateLimit,
+    pub per_email: RateLimit,
+    /// Our load balancers, the only peers whose `X-Forwarded-For` is believed
+    pub trusted_proxies: Vec<std::net::IpAddr>,
+    /// How often expired windows are deleted from the store
+    pub prune_interval_seconds: u64,
+    /// The memory store forgets the windows closest to their end past this many clients
+    pub max_memory_keys: usize,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitStoreKind {
+    Memory,
+    Postgres,
+}
+
+/// At most `max_requests` in any window of `window_seconds`
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+pub struct RateLimit {
+    pub max_requests: u32,
+    pub window_seconds: u64,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct CaptchaSettings {
+    /// Which verifier the subscribe form requires a token for, if any
+    pub verifier: CaptchaVerifierKind,
+    pub http: HttpCaptchaSettings,
+    pub proof_of_work: ProofOfWorkSettings,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptchaVerifierKind {
+    Disabled,
+    Http,
+    ProofOfWork,
+}
+
+/// A third party verifying tokens with an hCaptcha/Turnstile-style `siteverify` endpoint
+#[derive(serde::Deserialize, Clone)]
+pub struct HttpCaptchaSettings {
+    pub verify_url: String,
+    pub secret_key: Secret<String>,
+    pub timeout_milliseconds: u64,
+}
+
+/// Self-hosted challenges that cost the client some CPU time to solve
+#[derive(serde::Deserialize, Clone)]
+pub struct ProofOfWorkSettings {
+    /// Leading zero bits required in the solution hash, each one doubles the work
+    pub difficulty: u32,
+    pub challenge_ttl_seconds: u64,
+    /// Key used to sign challenges, so that we do not have to store them
+    pub signing_key: Secret<String>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct TrackingSettings {
+    /// Global switch for open and click tracking, for privacy-sensitive deployments
+    pub enabled: bool,
+    /// Key used to sign the tracking links embedded in newsletter issues
+    pub signing_key: Secret<String>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SuppressionSettings {
+    /// Key for the fingerprints of erased addresses. Changing it forgets every erasure.
+    pub email_hash_key: Secret<String>,
+}
+
+impl SuppressionSettings {
+    pub fn email_hasher(&self) -> EmailHasher {
+        EmailHasher::new(self.email_hash_key.clone())
+    }
+}
+
+/// Reminding, then forgetting, subscribers who never confirmed
+#[derive(serde::Deserialize, Clone)]
+pub struct PendingCleanupSettings {
+    pub enabled: bool,
+    /// How often each replica looks for stale pending subscribers
+    pub interval_seconds: u64,
+    /// Send the one reminder this long after subscribing
+    pub remind_after_days: u32,
+    /// Delete pending subscribers and their tokens this long after subscribing
+    pub delete_after_days: u32,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct OnboardingSettings {
+    pub enabled: bool,
+    /// How often each replica looks for onboarding emails that are due
+    pub poll_interval_seconds: u64,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct EmailOutboxSettings {
+    pub enabled: bool,
+    /// How often each replica looks for emails that could not be sent straight away
+    pub poll_interval_seconds: u64,
+    /// Doubled after every failed attempt
+    pub retry_backoff_seconds: u64,
+    /// Attempts after which an email is given up on
+    pub max_attempts: i32,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct NewsletterDeliverySettings {
+    pub enabled: bool,
+    /// How often each replica looks for newsletter issues left to deliver
+    pub poll_interval_seconds: u64,
+    /// Doubled after every failed attempt
+    pub retry_backoff_seconds: u64,
+    /// Attempts after which a subscriber's copy of an issue is given up on
+    pub max_attempts: i32,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct ShutdownSettings {
+    /// How long in-flight requests and background workers get to finish once asked to stop
+    pub timeout_seconds: u64,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct ReadinessSettings {
+    /// Also check that the email provider answers and accepts our token
+    pub probe_email_provider: bool,
+    /// Each dependency counts as down if it takes longer than this to answer
+    pub timeout_milliseconds: u64,
 }
 
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
@@ -127,6 +365,18 @@ impl TryFrom<String> for Environment {
 }
 
 impl EmailClientSettings {
+    pub fn client(self) -> EmailClient {
+        let sender_email = self.sender().expect("Invalid sender email address.");
+        let timeout = self.timeout();
+        EmailClient::new(
+            self.authorization_token,
+            self.base_url,
+            sender_email,
+            timeout,
+            self.supports_smtputf8,
+        )
+    }
+
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
@@ -135,3 +385,83 @@ impl EmailClientSettings {
         std::time::Duration::from_millis(self.timeout_milliseconds)
     }
 }
+
+impl HttpCaptchaSettings {
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+impl ConfirmationLinkSettings {
+    pub fn ttl(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.ttl_hours as i64)
+    }
+}
+
+impl PendingCleanupSettings {
+    pub fn interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.interval_seconds)
+    }
+
+    pub fn remind_after(&self) -> chrono::Duration {
+        chrono::Duration::days(self.remind_after_days.into())
+    }
+
+    pub fn delete_after(&self) -> chrono::Duration {
+        chrono::Duration::days(self.delete_after_days.into())
+    }
+}
+
+impl OnboardingSettings {
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.poll_interval_seconds)
+    }
+}
+
+impl EmailOutboxSettings {
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.poll_interval_seconds)
+    }
+
+    /// How long to wait before the next attempt, after `attempts` failed ones
+    pub fn retry_backoff(&self, attempts: i32) -> chrono::Duration {
+        let factor = 2i64.saturating_pow(attempts.saturating_sub(1).clamp(0, 16) as u32);
+        chrono::Duration::seconds((self.retry_backoff_seconds as i64).saturating_mul(factor))
+    }
+}
+
+impl NewsletterDeliverySettings {
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.poll_interval_seconds)
+    }
+
+    /// How long to wait before the next attempt, after `attempts` failed ones
+    pub fn retry_backoff(&self, attempts: i32) -> chrono::Duration {
+        let factor = 2i64.saturating_pow(attempts.saturating_sub(1).clamp(0, 16) as u32);
+        chrono::Duration::seconds((self.retry_backoff_seconds as i64).saturating_mul(factor))
+    }
+}
+
+impl ShutdownSettings {
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.timeout_seconds)
+    }
+}
+
+impl ReadinessSettings {
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+impl RateLimitSettings {
+    pub fn prune_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.prune_interval_seconds)
+    }
+}
+
+impl RateLimit {
+    pub fn window(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.window_seconds as i64)
+    }
+}
@@ -1,7 +1,11 @@
 mod new_subscriber;
+mod opt_in;
 mod subscriber_email;
 mod subscriber_name;
+mod subscription_status;
 
 pub use new_subscriber::NewSubscriber;
+pub use opt_in::OptIn;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use subscription_status::{IllegalTransition, SubscriptionStatus};
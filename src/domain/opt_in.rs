@@ -0,0 +1,39 @@
+use crate::configuration::OptInMode;
+
+/// How a subscriber's consent was collected, as stored on their subscription
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "opt_in_mode", rename_all = "snake_case")]
+pub enum OptIn {
+    /// Confirmed by following the link of the confirmation email
+    Double,
+    /// Confirmed on signup
+    Single,
+    /// Asserted by an imported file
+    Imported,
+}
+
+impl OptIn {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OptIn::Double => "double",
+            OptIn::Single => "single",
+            OptIn::Imported => "imported",
+        }
+    }
+}
+
+impl From<OptInMode> for OptIn {
+    fn from(mode: OptInMode) -> Self {
+        match mode {
+            OptInMode::Double => OptIn::Double,
+            OptInMode::Single => OptIn::Single,
+        }
+    }
+}
+
+impl std::fmt::Display for OptIn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -1,16 +1,105 @@
-use validator::validate_email;
+use unicode_normalization::UnicodeNormalization;
+
+/// Characters allowed in a dot-atom local part besides letters and digits (RFC 5322)
+const LOCAL_PART_SYMBOLS: &str = "!#$%&'*+-/=?^_`{|}~";
 
 #[derive(Debug, Clone)]
 pub struct SubscriberEmail(String);
 
 impl SubscriberEmail {
+    /// Accepts internationalised addresses (RFC 6531): UTF-8 local parts and IDN domains.
+    ///
+    /// Addresses are stored in Unicode normalization form C, so that the same address typed
+    /// on different keyboards compares equal.
     pub fn parse(s: String) -> Result<SubscriberEmail, String> {
-        if validate_email(&s) {
+        let s: String = s.trim().nfc().collect();
+        if is_valid(&s) {
             Ok(Self(s))
         } else {
             Err(format!("{} is not a valid subscriber email.", s))
         }
     }
+
+    /// The canonical form used to tell whether two addresses belong to the same person:
+    /// lowercased, with internationalised domains converted to punycode.
+    ///
+    /// Local parts are case-sensitive on paper, but no mainstream provider treats them so.
+    /// The address as typed is still the one we send emails to.
+    pub fn normalized(&self) -> String {
+        normalize(&self.0).expect("A parsed email can always be normalized.")
+    }
+
+    /// The normalized domain, e.g. `xn--bcher-kva.example` for `bücher.example`
+    pub fn normalized_domain(&self) -> String {
+        let normalized = self.normalized();
+        let (_, domain) = normalized
+            .rsplit_once('@')
+            .expect("A parsed email always contains an @.");
+        domain.to_string()
+    }
+
+    /// Whether delivering to this address needs a mail server that supports SMTPUTF8.
+    ///
+    /// IDN domains can always be spelled in punycode, non-ASCII local parts cannot.
+    pub fn requires_smtputf8(&self) -> bool {
+        !self.local_part().is_ascii()
+    }
+
+    /// The address to hand over to our email provider: the local part as typed and the
+    /// domain in punycode, so that only the local part may need SMTPUTF8.
+    pub fn smtp_address(&self) -> String {
+        let normalized_domain = self.normalized_domain();
+        format!("{}@{}", self.local_part(), normalized_domain)
+    }
+
+    fn local_part(&self) -> &str {
+        let (local_part, _) = self
+            .0
+            .rsplit_once('@')
+            .expect("A parsed email always contains an @.");
+        local_part
+    }
+}
+
+fn is_valid(email: &str) -> bool {
+    match email.rsplit_once('@') {
+        Some((local_part, domain)) => is_valid_local_part(local_part) && is_valid_domain(domain),
+        None => false,
+    }
+}
+
+/// A dot-atom, where atoms may also contain any non-ASCII letter or digit (RFC 6531)
+fn is_valid_local_part(local_part: &str) -> bool {
+    local_part.len() <= 64
+        && local_part.split('.').all(|atom| {
+            !atom.is_empty()
+                && atom.chars().all(|c| {
+                    c.is_ascii_alphanumeric()
+                        || LOCAL_PART_SYMBOLS.contains(c)
+                        || (!c.is_ascii() && c.is_alphanumeric())
+                })
+        })
+}
+
+/// A host name, checked on its punycode form
+fn is_valid_domain(domain: &str) -> bool {
+    let Ok(domain) = idna::domain_to_ascii(domain) else {
+        return false;
+    };
+    !domain.is_empty()
+        && domain.len() <= 253
+        && domain.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+fn normalize(email: &str) -> Option<String> {
+    let (local_part, domain) = email.trim().rsplit_once('@')?;
+    let domain = idna::domain_to_ascii(domain).ok()?;
+    Some(format!("{}@{}", local_part.to_lowercase(), domain))
 }
 
 impl AsRef<str> for SubscriberEmail {
@@ -28,7 +117,7 @@ impl std::fmt::Display for SubscriberEmail {
 #[cfg(test)]
 mod tests {
     use super::SubscriberEmail;
-    use claims::assert_err;
+    use claims::{assert_err, assert_ok};
     use fake::faker::internet::en::SafeEmail;
     use fake::Fake;
     use rand::rngs::StdRng;
@@ -52,6 +141,77 @@ mod tests {
         assert_err!(SubscriberEmail::parse(email));
     }
 
+    #[test]
+    fn surrounding_whitespace_is_trimmed() {
+        let email = SubscriberEmail::parse(" ursula@domain.com\n".to_string());
+        assert_ok!(&email);
+        assert_eq!(email.unwrap().as_ref(), "ursula@domain.com");
+    }
+
+    #[test]
+    fn differently_cased_addresses_share_a_normalized_form() {
+        let a = SubscriberEmail::parse("Alice@Example.com".to_string()).unwrap();
+        let b = SubscriberEmail::parse("alice@example.COM".to_string()).unwrap();
+        assert_eq!(a.normalized(), "alice@example.com");
+        assert_eq!(a.normalized(), b.normalized());
+        // The address is still sent to as typed
+        assert_eq!(a.as_ref(), "Alice@Example.com");
+    }
+
+    #[test]
+    fn internationalised_domains_are_normalized_to_punycode() {
+        let email = SubscriberEmail::parse("ursula@Bücher.example".to_string()).unwrap();
+        assert_eq!(email.normalized(), "ursula@xn--bcher-kva.example");
+        assert_eq!(email.normalized_domain(), "xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn non_ascii_local_parts_are_accepted() {
+        assert_ok!(SubscriberEmail::parse("山田太郎@例え.jp".to_string()));
+        assert_ok!(SubscriberEmail::parse(
+            "jürgen.müller@bücher.de".to_string()
+        ));
+    }
+
+    #[test]
+    fn malformed_local_parts_are_rejected() {
+        for email in [
+            "ursula..le.guin@domain.com",
+            ".ursula@domain.com",
+            "ur sula@domain.com",
+        ] {
+            assert_err!(SubscriberEmail::parse(email.to_string()));
+        }
+    }
+
+    #[test]
+    fn malformed_domains_are_rejected() {
+        for email in [
+            "ursula@-domain.com",
+            "ursula@domain..com",
+            "ursula@dom_ain.com",
+        ] {
+            assert_err!(SubscriberEmail::parse(email.to_string()));
+        }
+    }
+
+    #[test]
+    fn only_non_ascii_local_parts_require_smtputf8() {
+        let japanese = SubscriberEmail::parse("山田太郎@例え.jp".to_string()).unwrap();
+        let german_domain = SubscriberEmail::parse("ursula@bücher.de".to_string()).unwrap();
+        assert!(japanese.requires_smtputf8());
+        assert!(!german_domain.requires_smtputf8());
+        assert_eq!(german_domain.smtp_address(), "ursula@xn--bcher-kva.de");
+        assert_eq!(japanese.smtp_address(), "山田太郎@xn--r8jz45g.jp");
+    }
+
+    #[test]
+    fn composed_and_decomposed_forms_are_the_same_address() {
+        let composed = SubscriberEmail::parse("j\u{fc}rgen@domain.de".to_string()).unwrap();
+        let decomposed = SubscriberEmail::parse("ju\u{308}rgen@domain.de".to_string()).unwrap();
+        assert_eq!(composed.as_ref(), decomposed.as_ref());
+    }
+
     #[derive(Debug, Clone)]
     struct ValidEmailFixture(pub String);
 
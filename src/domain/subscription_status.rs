@@ -0,0 +1,96 @@
+/// Where a subscription is in its lifecycle, stored as the `subscription_status` Postgres enum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "subscription_status", rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    PendingConfirmation,
+    Confirmed,
+    Unsubscribed,
+    Bounced,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("A subscription cannot move from {from} to {to}.")]
+pub struct IllegalTransition {
+    pub from: SubscriptionStatus,
+    pub to: SubscriptionStatus,
+}
+
+impl SubscriptionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionStatus::PendingConfirmation => "pending_confirmation",
+            SubscriptionStatus::Confirmed => "confirmed",
+            SubscriptionStatus::Unsubscribed => "unsubscribed",
+            SubscriptionStatus::Bounced => "bounced",
+        }
+    }
+
+    /// Move to the `to` status, if the lifecycle allows it.
+    ///
+    /// Staying in the same status is always allowed so that repeated clicks on a link are harmless.
+    pub fn transition(
+        self,
+        to: SubscriptionStatus,
+    ) -> Result<SubscriptionStatus, IllegalTransition> {
+        use SubscriptionStatus::*;
+        let allowed = self == to
+            || matches!(
+                (self, to),
+                (PendingConfirmation, Confirmed | Unsubscribed | Bounced)
+                    | (Confirmed, Unsubscribed | Bounced)
+                    | (Bounced, Unsubscribed)
+            );
+        if allowed {
+            Ok(to)
+        } else {
+            Err(IllegalTransition { from: self, to })
+        }
+    }
+}
+
+impl std::fmt::Display for SubscriptionStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriptionStatus::*;
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn a_pending_subscription_can_be_confirmed() {
+        assert_ok_eq!(PendingConfirmation.transition(Confirmed), Confirmed);
+    }
+
+    #[test]
+    fn confirming_twice_is_allowed() {
+        assert_ok_eq!(Confirmed.transition(Confirmed), Confirmed);
+    }
+
+    #[test]
+    fn any_active_subscription_can_be_unsubscribed() {
+        for from in [PendingConfirmation, Confirmed, Bounced] {
+            assert_ok_eq!(from.transition(Unsubscribed), Unsubscribed);
+        }
+    }
+
+    #[test]
+    fn an_unsubscribed_subscription_cannot_be_confirmed() {
+        assert_err!(Unsubscribed.transition(Confirmed));
+    }
+
+    #[test]
+    fn a_subscription_cannot_go_back_to_pending() {
+        for from in [Confirmed, Unsubscribed, Bounced] {
+            assert_err!(from.transition(PendingConfirmation));
+        }
+    }
+
+    #[test]
+    fn a_bounced_address_cannot_be_confirmed() {
+        assert_err!(Bounced.transition(Confirmed));
+    }
+}
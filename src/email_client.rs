@@ -9,6 +9,15 @@ pub struct EmailClient {
     http_client: Client,
     base_url: String,
     sender: SubscriberEmail,
+    supports_smtputf8: bool,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SendEmailError {
+    #[error("{0} can only be reached through a provider that supports SMTPUTF8.")]
+    Smtputf8NotSupported(SubscriberEmail),
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
 }
 
 #[derive(serde::Serialize)]
@@ -20,27 +29,60 @@ struct SendEmailRequest<'a> {
     subject: &'a str,
     html_body: &'a str,
     text_body: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    headers: &'a [EmailHeader<'a>],
+}
+
+/// An extra header on an outgoing email, e.g. `List-Unsubscribe`
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct EmailHeader<'a> {
+    pub name: &'a str,
+    pub value: &'a str,
 }
 
 impl EmailClient {
+    /// Whether our email provider can deliver to `recipient` at all
+    pub fn can_deliver_to(&self, recipient: &SubscriberEmail) -> bool {
+        self.supports_smtputf8 || !recipient.requires_smtputf8()
+    }
+
     pub async fn send_email(
         &self,
         recipient: &SubscriberEmail,
         subject: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<(), reqwest::Error> {
+    ) -> Result<(), SendEmailError> {
+        self.send_email_with_headers(recipient, subject, html_content, text_content, &[])
+            .await
+    }
+
+    pub async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader<'_>],
+    ) -> Result<(), SendEmailError> {
+        if !self.can_deliver_to(recipient) {
+            return Err(SendEmailError::Smtputf8NotSupported(recipient.clone()));
+        }
         let email_base_url = Url::parse(&self.base_url).expect("Error fetching email base url.");
         let url = email_base_url
             .join("/email")
             .expect("Error computing email API url.");
 
+        // Spell internationalised domains in punycode, which every provider understands
+        let recipient = recipient.smtp_address();
         let payload = SendEmailRequest {
             from: self.sender.as_ref(),
-            to: recipient.as_ref(),
+            to: &recipient,
             subject,
             html_body: html_content,
             text_body: text_content,
+            headers,
         };
 
         let builder = self
@@ -57,11 +99,31 @@ impl EmailClient {
         Ok(())
     }
 
+    /// Fetch our server's details from Postmark, checking it is up and accepts our token
+    pub async fn probe(&self) -> Result<(), reqwest::Error> {
+        let email_base_url = Url::parse(&self.base_url).expect("Error fetching email base url.");
+        let url = email_base_url
+            .join("/server")
+            .expect("Error computing email API url.");
+        self.http_client
+            .get(url)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .header("Accept", "application/json")
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
     pub fn new(
         authorization_token: Secret<String>,
         base_url: String,
         sender: SubscriberEmail,
         timeout: std::time::Duration,
+        supports_smtputf8: bool,
     ) -> Self {
         let email_request_client = Client::builder().timeout(timeout).build().unwrap();
         Self {
@@ -69,6 +131,7 @@ impl EmailClient {
             http_client: email_request_client,
             base_url,
             sender,
+            supports_smtputf8,
         }
     }
 }
@@ -76,13 +139,13 @@ impl EmailClient {
 #[cfg(test)]
 mod tests {
     use crate::domain::SubscriberEmail;
-    use crate::email_client::EmailClient;
+    use crate::email_client::{EmailClient, EmailHeader, SendEmailError};
     use claims::{assert_err, assert_ok};
     use fake::faker::internet::en::SafeEmail;
     use fake::faker::lorem::en::{Paragraph, Sentence};
     use fake::{Fake, Faker};
     use secrecy::Secret;
-    use wiremock::matchers::{any, header, header_exists, method, path};
+    use wiremock::matchers::{any, body_partial_json, header, header_exists, method, path};
     use wiremock::Request;
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
@@ -130,6 +193,7 @@ mod tests {
             base_url,
             email(),
             std::time::Duration::from_millis(200),
+            false,
         )
     }
 
@@ -162,6 +226,39 @@ mod tests {
         // Assertions are being done by the WireMock server here by setting up givens and expect clauses
     }
 
+    #[tokio::test]
+    async fn send_email_with_headers_passes_them_to_the_provider() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email"))
+            .and(body_partial_json(serde_json::json!({
+                "Headers": [{"Name": "List-Unsubscribe-Post", "Value": "List-Unsubscribe=One-Click"}]
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email_with_headers(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                &[EmailHeader {
+                    name: "List-Unsubscribe-Post",
+                    value: "List-Unsubscribe=One-Click",
+                }],
+            )
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
     #[tokio::test]
     async fn send_email_succeeds_if_the_server_returns_200() {
         // Arrange
@@ -239,4 +336,74 @@ mod tests {
         // Assert
         assert_err!(response);
     }
+
+    #[tokio::test]
+    async fn send_email_refuses_addresses_requiring_smtputf8_if_unsupported() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let subscriber_email = SubscriberEmail::parse("山田太郎@例え.jp".into()).unwrap();
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let response = email_client
+            .send_email(&subscriber_email, &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert!(matches!(
+            response,
+            Err(SendEmailError::Smtputf8NotSupported(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn send_email_spells_internationalised_domains_in_punycode() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let subscriber_email = SubscriberEmail::parse("ursula@bücher.de".into()).unwrap();
+
+        Mock::given(body_partial_json(
+            serde_json::json!({ "To": "ursula@xn--bcher-kva.de" }),
+        ))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+        // Act
+        let response = email_client
+            .send_email(&subscriber_email, &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(response);
+    }
+
+    #[tokio::test]
+    async fn probe_fails_if_the_provider_rejects_our_token() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(path("/server"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(401))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client.probe().await;
+
+        // Assert
+        assert_err!(outcome);
+    }
 }
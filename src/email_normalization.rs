@@ -0,0 +1,76 @@
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+
+/// What happened to the addresses stored before internationalised domains were normalized
+#[derive(Debug, Default, serde::Serialize)]
+pub struct NormalizationReport {
+    pub normalized: u64,
+    /// Subscribers whose normalized address belongs to another subscriber already, e.g. one
+    /// who signed up again with the same address before the backfill. Left as they are, to be
+    /// merged or removed by hand.
+    pub conflicts: Vec<Uuid>,
+    /// Subscribers whose stored address is not valid anymore, left as they are
+    pub invalid: Vec<Uuid>,
+}
+
+/// Normalize the addresses the `email_normalized` migration could only lowercase.
+///
+/// SQL cannot convert internationalised domains to punycode, so the backfill is done here with
+/// [`SubscriberEmail::normalized`], the same normalization used at signup. It is idempotent:
+/// only addresses whose domain is still not ASCII are looked at.
+#[tracing::instrument(name = "Normalize stored email addresses", skip(db_pool))]
+pub async fn normalize_stored_emails(
+    db_pool: &PgPool,
+) -> Result<NormalizationReport, anyhow::Error> {
+    // Non-ASCII characters take more than one byte, a normalized domain never has any
+    let rows = sqlx::query!(
+        r#"
+        Select id, email
+        From subscriptions
+        Where Octet_Length(Split_Part(email_normalized, '@', 2))
+            <> Char_Length(Split_Part(email_normalized, '@', 2))
+        "#
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to fetch the addresses to normalize.")?;
+
+    let mut report = NormalizationReport::default();
+    for row in rows {
+        let email = match SubscriberEmail::parse(row.email) {
+            Ok(email) => email,
+            Err(e) => {
+                tracing::warn!(subscriber_id = %row.id, error = %e, "Cannot normalize an address");
+                report.invalid.push(row.id);
+                continue;
+            }
+        };
+        let updated = sqlx::query!(
+            r#"
+            Update subscriptions
+            Set email_normalized = $2
+            Where id = $1
+                And Not Exists (Select 1 From subscriptions Where email_normalized = $2)
+            "#,
+            row.id,
+            email.normalized()
+        )
+        .execute(db_pool)
+        .await
+        .context("Failed to store a normalized address.")?
+        .rows_affected();
+        if updated == 0 {
+            tracing::warn!(
+                subscriber_id = %row.id,
+                "Another subscriber already has this normalized address"
+            );
+            report.conflicts.push(row.id);
+        } else {
+            report.normalized += 1;
+        }
+    }
+    Ok(report)
+}
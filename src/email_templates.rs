@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::configuration::ConfirmationEmailSettings;
+use crate::template::{Template, TemplateContext, TemplateFormat};
+
+/// The subject and bodies of the confirmation email for a single locale
+#[derive(Debug, Clone)]
+pub struct ConfirmationEmailTemplate {
+    subject: Template,
+    html_body: Template,
+    text_body: Template,
+}
+
+/// A confirmation email ready to be handed to the `EmailClient`
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+}
+
+impl ConfirmationEmailTemplate {
+    /// Load `subject.txt`, `body.html` and `body.txt` from `directory`
+    fn load(directory: &Path) -> Result<Self, anyhow::Error> {
+        let read = |file_name: &str, format: TemplateFormat| {
+            let path = directory.join(file_name);
+            let source = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let template = Template::parse(source.trim_end(), format)
+                .with_context(|| format!("Invalid template at {}", path.display()))?;
+            Ok::<_, anyhow::Error>((path, template))
+        };
+
+        let (_, subject) = read("subject.txt", TemplateFormat::Text)?;
+        let (html_path, html_body) = read("body.html", TemplateFormat::Html)?;
+        let (text_path, text_body) = read("body.txt", TemplateFormat::Text)?;
+
+        // An email without the link would leave the subscriber unable to confirm
+        for (path, template) in [(html_path, &html_body), (text_path, &text_body)] {
+            if !template.variables().any(|v| v == "confirmation_link") {
+                anyhow::bail!(
+                    "{} does not use the `{{{{ confirmation_link }}}}` variable.",
+                    path.display()
+                );
+            }
+        }
+
+        Ok(Self {
+            subject,
+            html_body,
+            text_body,
+        })
+    }
+
+    pub fn render(&self, name: &str, email: &str, confirmation_link: &str) -> RenderedEmail {
+        let mut context = TemplateContext::new();
+        context
+            .insert("name", name)
+            .insert("email", email)
+            .insert("confirmation_link", confirmation_link);
+        RenderedEmail {
+            subject: self.subject.render(&context),
+            html_body: self.html_body.render(&context),
+            text_body: self.text_body.render(&context),
+        }
+    }
+}
+
+/// Confirmation email templates for every configured locale.
+///
+/// Each sub-directory of the templates directory is a locale (e.g. `en`, `de`, `pt-br`).
+#[derive(Debug, Clone)]
+pub struct ConfirmationEmailTemplates {
+    default_locale: String,
+    locales: HashMap<String, ConfirmationEmailTemplate>,
+}
+
+impl ConfirmationEmailTemplates {
+    /// Load and validate every locale, failing if any of them is broken
+    pub fn load(settings: &ConfirmationEmailSettings) -> Result<Self, anyhow::Error> {
+        let directory = Path::new(&settings.templates_directory);
+        let entries = std::fs::read_dir(directory).with_context(|| {
+            format!(
+                "Failed to read the confirmation email templates at {}",
+                directory.display()
+            )
+        })?;
+
+        let mut locales = HashMap::new();
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let locale = entry.file_name().to_string_lossy().to_lowercase();
+            let template = ConfirmationEmailTemplate::load(&entry.path())
+                .with_context(|| format!("Invalid confirmation email for locale `{}`", locale))?;
+            locales.insert(locale, template);
+        }
+
+        let default_locale = settings.default_locale.to_lowercase();
+        if !locales.contains_key(&default_locale) {
+            anyhow::bail!(
+                "No confirmation email templates found for the default locale `{}` in {}",
+                default_locale,
+                directory.display()
+            );
+        }
+
+        Ok(Self {
+            default_locale,
+            locales,
+        })
+    }
+
+    /// Pick the template for the explicitly `requested` locale if we have it, then for the best
+    /// match in the `Accept-Language` header, falling back to the default locale.
+    pub fn select(
+        &self,
+        requested: Option<&str>,
+        accept_language: Option<&str>,
+    ) -> &ConfirmationEmailTemplate {
+        &self.locales[self.select_locale(requested, accept_language)]
+    }
+
+    /// The locale [`select`](Self::select) picks, to be stored for the emails that follow
+    pub fn select_locale(&self, requested: Option<&str>, accept_language: Option<&str>) -> &str {
+        requested
+            .into_iter()
+            .chain(
+                accept_language
+                    .map(parse_accept_language)
+                    .unwrap_or_default(),
+            )
+            .find_map(|locale| self.find(locale))
+            .unwrap_or(&self.default_locale)
+    }
+
+    /// Look up a locale, falling back from a regional variant (`de-AT`) to its language (`de`)
+    fn find(&self, locale: &str) -> Option<&str> {
+        let locale = locale.trim().to_lowercase().replace('_', "-");
+        let language = locale.split('-').next().unwrap_or_default();
+        let found = [locale.as_str(), language]
+            .into_iter()
+            .find_map(|candidate| self.locales.get_key_value(candidate))
+            .map(|(locale, _)| locale.as_str());
+        found
+    }
+}
+
+/// The language ranges of an `Accept-Language` header, most preferred first
+fn parse_accept_language(header: &str) -> Vec<&str> {
+    let mut ranges: Vec<(&str, f32)> = header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let range = parts.next()?.trim();
+            let quality = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .map(|q| q.trim().parse().unwrap_or(0.0))
+                .unwrap_or(1.0);
+            (!range.is_empty() && range != "*" && quality > 0.0).then_some((range, quality))
+        })
+        .collect();
+    // A stable sort keeps the header order between ranges of equal quality
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranges.into_iter().map(|(range, _)| range).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_accept_language, ConfirmationEmailTemplates};
+    use crate::configuration::ConfirmationEmailSettings;
+    use claims::{assert_err, assert_ok};
+    use std::path::PathBuf;
+
+    fn settings(templates_directory: &str) -> ConfirmationEmailSettings {
+        ConfirmationEmailSettings {
+            templates_directory: templates_directory.into(),
+            default_locale: "en".into(),
+        }
+    }
+
+    fn templates() -> ConfirmationEmailTemplates {
+        ConfirmationEmailTemplates::load(&settings("configuration/templates/confirmation")).unwrap()
+    }
+
+    fn subject(templates: &ConfirmationEmailTemplates, r: Option<&str>, a: Option<&str>) -> String {
+        templates.select(r, a).render("", "", "").subject
+    }
+
+    /// Write a single locale into a fresh temporary templates directory
+    fn write_templates(subject: &str, html: &str, text: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let locale = directory.join("en");
+        std::fs::create_dir_all(&locale).unwrap();
+        std::fs::write(locale.join("subject.txt"), subject).unwrap();
+        std::fs::write(locale.join("body.html"), html).unwrap();
+        std::fs::write(locale.join("body.txt"), text).unwrap();
+        directory
+    }
+
+    #[test]
+    fn the_bundled_templates_are_valid() {
+        assert_ok!(ConfirmationEmailTemplates::load(&settings(
+            "configuration/templates/confirmation"
+        )));
+    }
+
+    #[test]
+    fn a_broken_template_is_rejected() {
+        let directory =
+            write_templates("Welcome", "{{ confirmation_link", "{{ confirmation_link }}");
+        assert_err!(ConfirmationEmailTemplates::load(&settings(
+            directory.to_str().unwrap()
+        )));
+    }
+
+    #[test]
+    fn a_template_without_the_confirmation_link_is_rejected() {
+        let directory = write_templates("Welcome", "Hello!", "{{ confirmation_link }}");
+        assert_err!(ConfirmationEmailTemplates::load(&settings(
+            directory.to_str().unwrap()
+        )));
+    }
+
+    #[test]
+    fn a_missing_default_locale_is_rejected() {
+        let directory = write_templates(
+            "Welcome",
+            "{{ confirmation_link }}",
+            "{{ confirmation_link }}",
+        );
+        let mut settings = settings(directory.to_str().unwrap());
+        settings.default_locale = "fr".into();
+        assert_err!(ConfirmationEmailTemplates::load(&settings));
+    }
+
+    #[test]
+    fn the_requested_locale_wins_over_accept_language() {
+        let templates = templates();
+        assert_eq!(subject(&templates, Some("de"), Some("en")), "Willkommen");
+    }
+
+    #[test]
+    fn regional_variants_fall_back_to_their_language() {
+        let templates = templates();
+        assert_eq!(subject(&templates, Some("de-AT"), None), "Willkommen");
+        assert_eq!(subject(&templates, Some("de_CH"), None), "Willkommen");
+    }
+
+    #[test]
+    fn accept_language_is_used_when_no_locale_is_requested() {
+        let templates = templates();
+        assert_eq!(
+            subject(&templates, None, Some("fr-FR, de;q=0.8, en;q=0.5")),
+            "Willkommen"
+        );
+    }
+
+    #[test]
+    fn unknown_locales_fall_back_to_the_default() {
+        let templates = templates();
+        assert_eq!(subject(&templates, Some("ja"), Some("fr")), "Welcome");
+        assert_eq!(subject(&templates, None, None), "Welcome");
+    }
+
+    #[test]
+    fn accept_language_is_sorted_by_quality() {
+        assert_eq!(
+            parse_accept_language("en;q=0.5, de, fr;q=0.9, *;q=0.1, ja;q=0"),
+            vec!["de", "fr", "en"]
+        );
+    }
+}
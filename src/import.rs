@@ -0,0 +1,769 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use chrono::Utc;
+use csv_core::ReadRecordResult;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::configuration::OptInMode;
+use crate::domain::{NewSubscriber, OptIn, SubscriberEmail, SubscriberName, SubscriptionStatus};
+use crate::email_templates::ConfirmationEmailTemplate;
+use crate::onboarding::schedule_onboardings;
+use crate::routes::{enqueue_confirmation_email, generate_subscription_token};
+use crate::subscription_events::SubscriptionEventKind;
+use crate::suppression::{suppressed_addresses, EmailHasher};
+
+/// Rows are validated one by one but written to Postgres in batches of this size
+const BATCH_SIZE: usize = 500;
+
+/// What to do with rows whose address already belongs to a subscriber
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateMode {
+    /// Leave the existing subscriber untouched
+    #[default]
+    Skip,
+    /// Take the name from the file, and confirm pending subscribers who gave their consent, or
+    /// all of them in single opt-in imports
+    Update,
+}
+
+impl std::str::FromStr for DuplicateMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(DuplicateMode::Skip),
+            "update" => Ok(DuplicateMode::Update),
+            other => Err(format!(
+                "{} is not a supported duplicate mode. Use either `skip` or `update`.",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ImportError {
+    #[error("{0}")]
+    InvalidHeader(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// A row that was not imported, and why
+#[derive(Debug, serde::Serialize)]
+pub struct RowError {
+    /// 1 for the first row after the header
+    pub row: u64,
+    pub email: String,
+    pub error: String,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct ImportReport {
+    pub imported: u64,
+    /// Imported without consent, they were sent a confirmation email like any new subscriber
+    pub confirmation_requested: u64,
+    pub updated: u64,
+    /// Already subscribed with the same details, in update mode
+    pub unchanged: u64,
+    pub skipped: u64,
+    pub failed: u64,
+    pub errors: Vec<RowError>,
+}
+
+impl ImportReport {
+    /// The per-row errors as a CSV file, for people to fix and re-import
+    pub fn errors_csv(&self) -> String {
+        let mut csv = String::from("row,email,error\n");
+        for error in &self.errors {
+            csv.push_str(&format!(
+                "{},{},{}\n",
+                error.row,
+                csv_field(&error.email),
+                csv_field(&error.error)
+            ));
+        }
+        csv
+    }
+
+    fn skip(&mut self, row: u64, email: &str, error: impl Into<String>) {
+        self.skipped += 1;
+        self.errors.push(RowError {
+            row,
+            email: email.to_owned(),
+            error: error.into(),
+        });
+    }
+
+    fn fail(&mut self, row: u64, email: &str, error: impl Into<String>) {
+        self.failed += 1;
+        self.errors.push(RowError {
+            row,
+            email: email.to_owned(),
+            error: error.into(),
+        });
+    }
+}
+
+/// Quote a field if it would otherwise break the CSV row
+pub(crate) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Where the columns we care about are, read from the header row
+struct Columns {
+    email: usize,
+    name: usize,
+    consent: Option<usize>,
+}
+
+impl Columns {
+    fn from_header(header: &[String]) -> Result<Self, ImportError> {
+        let position = |column: &str| {
+            header
+                .iter()
+                .position(|h| h.trim().eq_ignore_ascii_case(column))
+        };
+        let required = |column: &str| {
+            position(column).ok_or_else(|| {
+                ImportError::InvalidHeader(format!("The CSV header has no `{}` column.", column))
+            })
+        };
+        Ok(Self {
+            email: required("email")?,
+            name: required("name")?,
+            consent: position("consent"),
+        })
+    }
+}
+
+/// A valid row, waiting for its batch to be written
+struct ImportRow {
+    row: u64,
+    email: SubscriberEmail,
+    name: SubscriberName,
+    opt_in: OptIn,
+}
+
+impl ImportRow {
+    fn status(&self) -> SubscriptionStatus {
+        match self.opt_in {
+            OptIn::Double => SubscriptionStatus::PendingConfirmation,
+            OptIn::Single | OptIn::Imported => SubscriptionStatus::Confirmed,
+        }
+    }
+}
+
+/// Whether the file asserts that the subscriber already agreed to receive the newsletter
+fn parse_consent(value: &str) -> Result<bool, String> {
+    match value.trim().to_lowercase().as_str() {
+        "true" | "yes" | "y" | "1" => Ok(true),
+        "false" | "no" | "n" | "0" | "" => Ok(false),
+        other => Err(format!(
+            "{} is not a valid consent, use `yes` or `no`.",
+            other
+        )),
+    }
+}
+
+/// Imports subscribers from a CSV file fed in chunks, so that uploads are never held in memory.
+///
+/// The file needs an `email` and a `name` column. Subscribers are confirmed when an optional
+/// `consent` column asserts they opted in with our previous provider, or when the import is in
+/// single opt-in mode, e.g. for a list of employees. The others are left pending, with a
+/// confirmation email written to the outbox for the running application to send. Suppressed
+/// addresses are never imported.
+pub struct SubscriberImport<'a> {
+    db_pool: &'a PgPool,
+    email_hasher: EmailHasher,
+    base_url: &'a str,
+    template: &'a ConfirmationEmailTemplate,
+    mode: DuplicateMode,
+    /// For the rows without consent
+    opt_in: OptInMode,
+    /// Recorded on the subscription events, e.g. the endpoint or the file name
+    source: String,
+    parser: CsvParser,
+    columns: Option<Columns>,
+    row: u64,
+    /// Normalized addresses seen so far, with their row, to catch duplicates within the file
+    seen: HashMap<String, u64>,
+    batch: Vec<ImportRow>,
+    report: ImportReport,
+}
+
+impl<'a> SubscriberImport<'a> {
+    pub fn new(
+        db_pool: &'a PgPool,
+        email_hasher: EmailHasher,
+        base_url: &'a str,
+        template: &'a ConfirmationEmailTemplate,
+        mode: DuplicateMode,
+        opt_in: OptInMode,
+        source: String,
+    ) -> Self {
+        Self {
+            db_pool,
+            email_hasher,
+            base_url,
+            template,
+            mode,
+            opt_in,
+            source,
+            parser: CsvParser::default(),
+            columns: None,
+            row: 0,
+            seen: HashMap::new(),
+            batch: Vec::with_capacity(BATCH_SIZE),
+            report: ImportReport::default(),
+        }
+    }
+
+    pub async fn feed(&mut self, chunk: &[u8]) -> Result<(), ImportError> {
+        for record in self.parser.parse(chunk) {
+            self.handle_record(record).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn finish(mut self) -> Result<ImportReport, ImportError> {
+        for record in self.parser.finish() {
+            self.handle_record(record).await?;
+        }
+        if self.columns.is_none() {
+            return Err(ImportError::InvalidHeader("The CSV file is empty.".into()));
+        }
+        self.flush().await?;
+        Ok(self.report)
+    }
+
+    async fn handle_record(
+        &mut self,
+        record: Result<Vec<String>, String>,
+    ) -> Result<(), ImportError> {
+        let Some(columns) = &self.columns else {
+            let header = record.map_err(ImportError::InvalidHeader)?;
+            self.columns = Some(Columns::from_header(&header)?);
+            return Ok(());
+        };
+        self.row += 1;
+        let row = self.row;
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                self.report.fail(row, "", e);
+                return Ok(());
+            }
+        };
+        // Tolerate the blank lines spreadsheets like to leave at the end
+        if record.iter().all(|field| field.trim().is_empty()) {
+            self.row -= 1;
+            return Ok(());
+        }
+        let field = |index: usize| record.get(index).cloned().unwrap_or_default();
+        let raw_email = field(columns.email);
+        let raw_name = field(columns.name);
+        let consent = columns
+            .consent
+            .map(|index| parse_consent(&field(index)))
+            .unwrap_or(Ok(false));
+
+        let parsed = (
+            SubscriberEmail::parse(raw_email.clone()),
+            SubscriberName::parse(raw_name.trim().to_owned()),
+            consent,
+        );
+        let (email, name, consent) = match parsed {
+            (Ok(email), Ok(name), Ok(consent)) => (email, name, consent),
+            (email, name, consent) => {
+                let errors: Vec<String> = [email.err(), name.err(), consent.err()]
+                    .into_iter()
+                    .flatten()
+                    .collect();
+                self.report.fail(row, &raw_email, errors.join(" "));
+                return Ok(());
+            }
+        };
+        if let Some(first_row) = self.seen.get(&email.normalized()) {
+            self.report.skip(
+                row,
+                &raw_email,
+                format!("Duplicate of row {} of the file.", first_row),
+            );
+            return Ok(());
+        }
+        self.seen.insert(email.normalized(), row);
+
+        // The file's word for it, otherwise what was chosen for this import
+        let opt_in = if consent {
+            OptIn::Imported
+        } else {
+            self.opt_in.into()
+        };
+        self.batch.push(ImportRow {
+            row,
+            email,
+            name,
+            opt_in,
+        });
+        if self.batch.len() >= BATCH_SIZE {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Import a batch of subscribers", skip(self))]
+    async fn flush(&mut self) -> Result<(), ImportError> {
+        let batch = std::mem::take(&mut self.batch);
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let emails: Vec<SubscriberEmail> = batch.iter().map(|row| row.email.clone()).collect();
+        let suppressed = suppressed_addresses(self.db_pool, &self.email_hasher, &emails)
+            .await
+            .context("Failed to check the batch against the suppression list.")?;
+        let (suppressed, batch): (Vec<_>, Vec<_>) = batch
+            .into_iter()
+            .partition(|row| suppressed.contains(&row.email.normalized()));
+        for row in suppressed {
+            self.report
+                .skip(row.row, row.email.as_ref(), "The address is suppressed.");
+        }
+
+        let mut txn = self
+            .db_pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?;
+        let existing = lock_existing_subscribers(&mut txn, &batch)
+            .await
+            .context("Failed to look up existing subscribers.")?;
+
+        let mut inserts = vec![];
+        let mut updates = vec![];
+        let mut events = vec![];
+        for row in batch {
+            match existing.get(&row.email.normalized()) {
+                None => inserts.push(row),
+                Some(_) if self.mode == DuplicateMode::Skip => {
+                    self.report
+                        .skip(row.row, row.email.as_ref(), "Already subscribed.");
+                }
+                Some(existing) => {
+                    let confirm = row.status() == SubscriptionStatus::Confirmed
+                        && existing.status == SubscriptionStatus::PendingConfirmation;
+                    if !confirm && row.name.as_ref() == existing.name {
+                        self.report.unchanged += 1;
+                        continue;
+                    }
+                    let (status, opt_in) = if confirm {
+                        events.push((existing.id, SubscriptionEventKind::Confirmed));
+                        (SubscriptionStatus::Confirmed, Some(row.opt_in))
+                    } else {
+                        (existing.status, None)
+                    };
+                    updates.push(SubscriberUpdate {
+                        id: existing.id,
+                        name: row.name,
+                        status,
+                        opt_in,
+                    });
+                    self.report.updated += 1;
+                }
+            }
+        }
+
+        let inserted = insert_subscribers(&mut txn, &inserts)
+            .await
+            .context("Failed to insert the imported subscribers.")?;
+        let mut to_confirm = vec![];
+        for row in inserts {
+            match inserted.get(&row.email.normalized()) {
+                Some(id) => {
+                    events.push((*id, SubscriptionEventKind::Subscribed));
+                    if row.status() == SubscriptionStatus::Confirmed {
+                        events.push((*id, SubscriptionEventKind::Confirmed));
+                    } else {
+                        to_confirm.push((*id, row));
+                    }
+                    self.report.imported += 1;
+                }
+                // Subscribed through the form since we looked
+                None => self
+                    .report
+                    .skip(row.row, row.email.as_ref(), "Already subscribed."),
+            }
+        }
+        self.report.confirmation_requested += self
+            .request_confirmations(&mut txn, to_confirm)
+            .await
+            .context("Failed to ask the imported subscribers to confirm.")?;
+        update_subscribers(&mut txn, &updates)
+            .await
+            .context("Failed to update the imported subscribers.")?;
+        record_import_events(&mut txn, &events, &self.source)
+            .await
+            .context("Failed to record the subscription events of the import.")?;
+        // Like subscribers confirming through the link
+        let confirmed: Vec<Uuid> = events
+            .iter()
+            .filter(|(_, kind)| *kind == SubscriptionEventKind::Confirmed)
+            .map(|(id, _)| *id)
+            .collect();
+        schedule_onboardings(&mut txn, &confirmed)
+            .await
+            .context("Failed to schedule the onboarding of the imported subscribers.")?;
+        txn.commit()
+            .await
+            .context("Failed to commit SQL transaction to import subscribers.")?;
+        Ok(())
+    }
+
+    /// Store a token for each new pending subscriber, and write their confirmation email to
+    /// the outbox along with them
+    async fn request_confirmations(
+        &self,
+        txn: &mut Transaction<'_, Postgres>,
+        rows: Vec<(Uuid, ImportRow)>,
+    ) -> Result<u64, sqlx::Error> {
+        if rows.is_empty() {
+            return Ok(0);
+        }
+        let tokens: Vec<String> = rows.iter().map(|_| generate_subscription_token()).collect();
+        let subscriber_ids: Vec<Uuid> = rows.iter().map(|(id, _)| *id).collect();
+        txn.execute(sqlx::query!(
+            r#"
+            Insert Into subscription_tokens (subscription_token, subscriber_id, created_at)
+            Select subscription_token, subscriber_id, $3
+            From Unnest($1::text[], $2::uuid[]) As t(subscription_token, subscriber_id)
+            "#,
+            &tokens,
+            &subscriber_ids,
+            Utc::now()
+        ))
+        .await?;
+        for ((subscriber_id, row), token) in rows.into_iter().zip(&tokens) {
+            let new_subscriber = NewSubscriber {
+                email: row.email,
+                name: row.name,
+            };
+            enqueue_confirmation_email(
+                txn,
+                subscriber_id,
+                &new_subscriber,
+                self.base_url,
+                token,
+                self.template,
+            )
+            .await?;
+        }
+        Ok(tokens.len() as u64)
+    }
+}
+
+/// A subscriber already in the database, with the address of a row of the file
+struct ExistingSubscriber {
+    id: Uuid,
+    name: String,
+    status: SubscriptionStatus,
+}
+
+/// What an update-mode import changes on an existing subscriber
+struct SubscriberUpdate {
+    id: Uuid,
+    name: SubscriberName,
+    status: SubscriptionStatus,
+    /// Only changed when the file confirms a pending subscriber
+    opt_in: Option<OptIn>,
+}
+
+/// Existing subscribers among the batch, by normalized address
+async fn lock_existing_subscribers(
+    txn: &mut Transaction<'_, Postgres>,
+    batch: &[ImportRow],
+) -> Result<HashMap<String, ExistingSubscriber>, sqlx::Error> {
+    let addresses: Vec<String> = batch.iter().map(|row| row.email.normalized()).collect();
+    let rows = sqlx::query!(
+        r#"
+        Select id, email_normalized, name, status As "status: SubscriptionStatus"
+        From subscriptions
+        Where email_normalized = Any($1)
+        For Update
+        "#,
+        &addresses
+    )
+    .fetch_all(&mut **txn)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let existing = ExistingSubscriber {
+                id: row.id,
+                name: row.name,
+                status: row.status,
+            };
+            (row.email_normalized, existing)
+        })
+        .collect())
+}
+
+/// Insert new subscribers, returning the ids of those who were not already there
+async fn insert_subscribers(
+    txn: &mut Transaction<'_, Postgres>,
+    rows: &[ImportRow],
+) -> Result<HashMap<String, Uuid>, sqlx::Error> {
+    let ids: Vec<Uuid> = rows.iter().map(|_| Uuid::new_v4()).collect();
+    let emails: Vec<String> = rows.iter().map(|row| row.email.to_string()).collect();
+    let addresses: Vec<String> = rows.iter().map(|row| row.email.normalized()).collect();
+    let names: Vec<String> = rows
+        .iter()
+        .map(|row| row.name.as_ref().to_owned())
+        .collect();
+    let statuses: Vec<String> = rows
+        .iter()
+        .map(|row| row.status().as_str().to_owned())
+        .collect();
+    let opt_ins: Vec<String> = rows
+        .iter()
+        .map(|row| row.opt_in.as_str().to_owned())
+        .collect();
+    let inserted = sqlx::query!(
+        r#"
+        Insert Into subscriptions (
+            id, email, email_normalized, name, subscribed_at, status, opt_in_mode
+        )
+        Select
+            id, email, email_normalized, name, $7,
+            status::subscription_status, opt_in_mode::opt_in_mode
+        From Unnest($1::uuid[], $2::text[], $3::text[], $4::text[], $5::text[], $6::text[])
+            As t(id, email, email_normalized, name, status, opt_in_mode)
+        On Conflict (email_normalized) Do Nothing
+        Returning id, email_normalized
+        "#,
+        &ids,
+        &emails,
+        &addresses,
+        &names,
+        &statuses,
+        &opt_ins,
+        Utc::now()
+    )
+    .fetch_all(&mut **txn)
+    .await?;
+    Ok(inserted
+        .into_iter()
+        .map(|row| (row.email_normalized, row.id))
+        .collect())
+}
+
+async fn update_subscribers(
+    txn: &mut Transaction<'_, Postgres>,
+    updates: &[SubscriberUpdate],
+) -> Result<(), sqlx::Error> {
+    if updates.is_empty() {
+        return Ok(());
+    }
+    let ids: Vec<Uuid> = updates.iter().map(|update| update.id).collect();
+    let names: Vec<String> = updates
+        .iter()
+        .map(|update| update.name.as_ref().to_owned())
+        .collect();
+    let statuses: Vec<String> = updates
+        .iter()
+        .map(|update| update.status.as_str().to_owned())
+        .collect();
+    let opt_ins: Vec<Option<String>> = updates
+        .iter()
+        .map(|update| update.opt_in.map(|opt_in| opt_in.as_str().to_owned()))
+        .collect();
+    txn.execute(sqlx::query!(
+        r#"
+        Update subscriptions
+        Set
+            name = u.name,
+            status = u.status::subscription_status,
+            opt_in_mode = Coalesce(u.opt_in_mode::opt_in_mode, subscriptions.opt_in_mode)
+        From Unnest($1::uuid[], $2::text[], $3::text[], $4::text[])
+            As u(id, name, status, opt_in_mode)
+        Where subscriptions.id = u.id
+        "#,
+        &ids,
+        &names,
+        &statuses,
+        &opt_ins as &[Option<String>]
+    ))
+    .await?;
+    Ok(())
+}
+
+async fn record_import_events(
+    txn: &mut Transaction<'_, Postgres>,
+    events: &[(Uuid, SubscriptionEventKind)],
+    source: &str,
+) -> Result<(), sqlx::Error> {
+    if events.is_empty() {
+        return Ok(());
+    }
+    let subscriber_ids: Vec<Uuid> = events.iter().map(|(id, _)| *id).collect();
+    let kinds: Vec<String> = events
+        .iter()
+        .map(|(_, kind)| kind.as_str().to_owned())
+        .collect();
+    txn.execute(sqlx::query!(
+        r#"
+        Insert Into subscription_events (subscriber_id, kind, occurred_at, source)
+        Select subscriber_id, kind, $3, $4
+        From Unnest($1::uuid[], $2::text[]) With Ordinality As e(subscriber_id, kind, position)
+        Order By position
+        "#,
+        &subscriber_ids,
+        &kinds,
+        Utc::now(),
+        source
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Incremental CSV parsing (RFC 4180), fed arbitrary chunks of the file
+struct CsvParser {
+    reader: csv_core::Reader,
+    output: Vec<u8>,
+    output_len: usize,
+    ends: Vec<usize>,
+    ends_len: usize,
+}
+
+impl Default for CsvParser {
+    fn default() -> Self {
+        Self {
+            reader: csv_core::Reader::new(),
+            output: vec![0; 1024],
+            output_len: 0,
+            ends: vec![0; 16],
+            ends_len: 0,
+        }
+    }
+}
+
+impl CsvParser {
+    /// The records completed by `input`, the rest is kept until the next chunk
+    fn parse(&mut self, mut input: &[u8]) -> Vec<Result<Vec<String>, String>> {
+        let mut records = vec![];
+        loop {
+            let (result, read, written, ends_written) = self.reader.read_record(
+                input,
+                &mut self.output[self.output_len..],
+                &mut self.ends[self.ends_len..],
+            );
+            input = &input[read..];
+            // Field ends are counted from the start of the record, across calls
+            self.output_len += written;
+            self.ends_len += ends_written;
+            match result {
+                ReadRecordResult::InputEmpty => return records,
+                ReadRecordResult::OutputFull => self.output.resize(self.output.len() * 2, 0),
+                ReadRecordResult::OutputEndsFull => self.ends.resize(self.ends.len() * 2, 0),
+                ReadRecordResult::Record => records.push(self.take_record()),
+                ReadRecordResult::End => return records,
+            }
+        }
+    }
+
+    /// The last record, when the file does not end with a new line
+    fn finish(&mut self) -> Vec<Result<Vec<String>, String>> {
+        self.parse(&[])
+    }
+
+    fn take_record(&mut self) -> Result<Vec<String>, String> {
+        let mut start = 0;
+        let fields = self.ends[..self.ends_len]
+            .iter()
+            .map(|&end| {
+                let field = std::str::from_utf8(&self.output[start..end])
+                    .map(str::to_owned)
+                    .map_err(|_| "The row is not valid UTF-8.".to_string());
+                start = end;
+                field
+            })
+            .collect();
+        self.output_len = 0;
+        self.ends_len = 0;
+        fields
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_consent, CsvParser, ImportReport, RowError};
+    use claims::{assert_err, assert_ok_eq};
+
+    fn parse_in_chunks(csv: &str, chunk_size: usize) -> Vec<Vec<String>> {
+        let mut parser = CsvParser::default();
+        let mut records = vec![];
+        for chunk in csv.as_bytes().chunks(chunk_size) {
+            records.extend(parser.parse(chunk));
+        }
+        records.extend(parser.finish());
+        records.into_iter().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn records_can_span_chunks() {
+        let csv = "email,name\r\nursula@domain.com,\"Le Guin, Ursula\"\nalice@example.com,\"Alice\nLiddell\"";
+        for chunk_size in [1, 2, 7, 1024] {
+            assert_eq!(
+                parse_in_chunks(csv, chunk_size),
+                vec![
+                    vec!["email", "name"],
+                    vec!["ursula@domain.com", "Le Guin, Ursula"],
+                    vec!["alice@example.com", "Alice\nLiddell"],
+                ],
+                "Failed with chunks of {} bytes",
+                chunk_size
+            );
+        }
+    }
+
+    #[test]
+    fn long_fields_grow_the_buffers() {
+        let name = "a".repeat(5000);
+        let header: Vec<String> = (0..40).map(|i| format!("column{}", i)).collect();
+        let csv = format!("{}\n{}\n", header.join(","), name);
+        let records = parse_in_chunks(&csv, 100);
+        assert_eq!(records[0].len(), 40);
+        assert_eq!(records[1], vec![name]);
+    }
+
+    #[test]
+    fn consent_accepts_the_usual_spellings() {
+        assert_ok_eq!(parse_consent("Yes"), true);
+        assert_ok_eq!(parse_consent("1"), true);
+        assert_ok_eq!(parse_consent(""), false);
+        assert_ok_eq!(parse_consent("no"), false);
+        assert_err!(parse_consent("maybe"));
+    }
+
+    #[test]
+    fn the_error_report_is_valid_csv() {
+        let report = ImportReport {
+            errors: vec![RowError {
+                row: 3,
+                email: "not, an email".into(),
+                error: "Says \"hi\"".into(),
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            report.errors_csv(),
+            "row,email,error\n3,\"not, an email\",\"Says \"\"hi\"\"\"\n"
+        );
+    }
+}
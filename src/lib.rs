@@ -1,6 +1,24 @@
+pub mod authentication;
+pub mod captcha;
+pub mod cleanup;
+pub mod client_ip;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod email_normalization;
+pub mod email_templates;
+pub mod import;
+pub mod markdown;
+pub mod newsletter_delivery;
+pub mod onboarding;
+pub mod outbox;
+pub mod rate_limit;
 pub mod routes;
+pub mod shutdown;
+pub mod signup_policy;
 pub mod startup;
+pub mod subscription_events;
+pub mod suppression;
 pub mod telemetry;
+pub mod template;
+pub mod tracking;
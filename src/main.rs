@@ -1,16 +1,140 @@
-use zero2prod::configuration::get_configuration;
-use zero2prod::startup::Application;
+use std::io::Write;
+
+use anyhow::Context;
+use tokio::io::AsyncReadExt;
+use zero2prod::configuration::{get_configuration, OptInMode};
+use zero2prod::email_normalization::normalize_stored_emails;
+use zero2prod::email_templates::ConfirmationEmailTemplates;
+use zero2prod::import::{DuplicateMode, SubscriberImport};
+use zero2prod::startup::{get_connection_pool, Application};
+use zero2prod::suppression::EmailHasher;
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
+const USAGE: &str = "Usage: zero2prod [import-subscribers <file.csv> [--mode skip|update] \
+    [--opt-in double|single] [--report <errors.csv>] | normalize-emails]";
+
 #[tokio::main]
-async fn main() -> Result<(), std::io::Error> {
+async fn main() -> anyhow::Result<()> {
     let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
     init_subscriber(subscriber);
 
     // Panic if we cannot read configuration
     let configuration = get_configuration().expect("Failed to read configuration");
 
-    let application = Application::build(configuration).await?;
-    application.run_until_stopped().await?;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        None => {
+            let application = Application::build(configuration).await?;
+            application.run_until_stopped().await?;
+            // Don't lose the last log lines when the process exits
+            std::io::stdout().flush()?;
+        }
+        Some("import-subscribers") => {
+            let db_pool = get_connection_pool(&configuration.database);
+            let email_hasher = configuration.suppression.email_hasher();
+            let templates = ConfirmationEmailTemplates::load(&configuration.confirmation_email)?;
+            import_subscribers(
+                &db_pool,
+                email_hasher,
+                &configuration.application.base_url,
+                &templates,
+                &args[1..],
+            )
+            .await?;
+        }
+        Some("normalize-emails") => {
+            let db_pool = get_connection_pool(&configuration.database);
+            normalize_emails(&db_pool).await?;
+        }
+        Some(_) => anyhow::bail!(USAGE),
+    }
+    Ok(())
+}
+
+/// Import subscribers from a CSV file, e.g. when migrating from another newsletter provider
+async fn import_subscribers(
+    db_pool: &sqlx::PgPool,
+    email_hasher: EmailHasher,
+    base_url: &str,
+    templates: &ConfirmationEmailTemplates,
+    args: &[String],
+) -> anyhow::Result<()> {
+    let mut path = None;
+    let mut mode = DuplicateMode::default();
+    let mut opt_in = OptInMode::default();
+    let mut report_path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mode" => {
+                let value = args.next().context(USAGE)?;
+                mode = value.parse().map_err(anyhow::Error::msg)?;
+            }
+            "--opt-in" => {
+                let value = args.next().context(USAGE)?;
+                opt_in = value.parse().map_err(anyhow::Error::msg)?;
+            }
+            "--report" => report_path = Some(args.next().context(USAGE)?),
+            _ if path.is_none() => path = Some(arg),
+            _ => anyhow::bail!(USAGE),
+        }
+    }
+    let path = path.context(USAGE)?;
+
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("Failed to open {}.", path))?;
+    let mut import = SubscriberImport::new(
+        db_pool,
+        email_hasher,
+        base_url,
+        templates.select(None, None),
+        mode,
+        opt_in,
+        format!("import-subscribers {}", path),
+    );
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .await
+            .with_context(|| format!("Failed to read {}.", path))?;
+        if read == 0 {
+            break;
+        }
+        import.feed(&buffer[..read]).await?;
+    }
+    let report = import.finish().await?;
+
+    println!(
+        "Imported {}, updated {}, skipped {}, failed {}.",
+        report.imported, report.updated, report.skipped, report.failed
+    );
+    match report_path {
+        Some(report_path) => tokio::fs::write(report_path, report.errors_csv())
+            .await
+            .with_context(|| format!("Failed to write the report to {}.", report_path))?,
+        None => {
+            for error in &report.errors {
+                println!("Row {} ({}): {}", error.row, error.email, error.error);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Normalize the addresses stored before internationalised domains were, run once after deploying
+async fn normalize_emails(db_pool: &sqlx::PgPool) -> anyhow::Result<()> {
+    let report = normalize_stored_emails(db_pool).await?;
+    println!("Normalized {} addresses.", report.normalized);
+    for id in &report.conflicts {
+        println!(
+            "Subscriber {}: another subscriber has the same address.",
+            id
+        );
+    }
+    for id in &report.invalid {
+        println!("Subscriber {}: the stored address is not valid.", id);
+    }
     Ok(())
 }
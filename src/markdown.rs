@@ -0,0 +1,356 @@
+use std::path::Path;
+
+use anyhow::Context;
+use pulldown_cmark::{html, Event, HeadingLevel, Options, Parser, Tag};
+
+use crate::template::{Template, TemplateFormat};
+
+/// Inline styles applied to the HTML produced from Markdown.
+///
+/// Many email clients strip `<style>` blocks, so every element carries its own styling.
+const INLINE_STYLES: &[(&str, &str)] = &[
+    ("h1", "margin: 0 0 16px; font-size: 26px; line-height: 1.3;"),
+    (
+        "h2",
+        "margin: 24px 0 12px; font-size: 22px; line-height: 1.3;",
+    ),
+    (
+        "h3",
+        "margin: 20px 0 8px; font-size: 18px; line-height: 1.3;",
+    ),
+    ("p", "margin: 0 0 16px; font-size: 16px; line-height: 1.5;"),
+    ("a", "color: #1a73e8; text-decoration: underline;"),
+    ("ul", "margin: 0 0 16px; padding-left: 24px;"),
+    ("ol", "margin: 0 0 16px; padding-left: 24px;"),
+    ("li", "margin: 0 0 4px; line-height: 1.5;"),
+    (
+        "blockquote",
+        "margin: 0 0 16px; padding-left: 12px; border-left: 4px solid #dddddd; color: #555555;",
+    ),
+    (
+        "pre",
+        "margin: 0 0 16px; padding: 12px; background-color: #f4f4f4; overflow-x: auto;",
+    ),
+    (
+        "code",
+        "font-family: Menlo, Consolas, monospace; font-size: 14px;",
+    ),
+    ("img", "max-width: 100%; height: auto; border: 0;"),
+    (
+        "hr",
+        "margin: 24px 0; border: 0; border-top: 1px solid #dddddd;",
+    ),
+];
+
+/// The HTML and plain text parts rendered from a single Markdown source
+pub struct RenderedMarkdown {
+    pub html: String,
+    pub text: String,
+}
+
+/// An HTML document wrapping the content of every Markdown newsletter issue.
+///
+/// The layout is itself a template: `{{ content }}` marks where the issue goes, while any other
+/// placeholder (e.g. `{{ unsubscribe_url }}`) is left in place to be rendered per recipient.
+#[derive(Debug, Clone)]
+pub struct EmailLayout {
+    header: String,
+    footer: String,
+}
+
+impl EmailLayout {
+    pub fn parse(source: &str) -> Result<EmailLayout, String> {
+        let template = Template::parse(source, TemplateFormat::Html)
+            .map_err(|e| format!("The email layout is not a valid template: {}", e))?;
+        let content_placeholders = template.variables().filter(|v| *v == "content").count();
+        if content_placeholders != 1 {
+            return Err(format!(
+                "The email layout must contain `{{{{ content }}}}` exactly once, found it {} times.",
+                content_placeholders
+            ));
+        }
+
+        let (start, end) = find_placeholder(source, "content")
+            .expect("A validated layout contains a content placeholder.");
+        Ok(Self {
+            header: source[..start].to_string(),
+            footer: source[end..].to_string(),
+        })
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<EmailLayout, anyhow::Error> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read the email layout at {}", path.display()))?;
+        Self::parse(&source)
+            .map_err(|e| anyhow::anyhow!(e))
+            .with_context(|| format!("Invalid email layout at {}", path.display()))
+    }
+
+    pub fn wrap(&self, content: &str) -> String {
+        format!("{}{}{}", self.header, content, self.footer)
+    }
+}
+
+/// Render Markdown into a sanitized, email-safe HTML part wrapped in `layout` and a plain
+/// text part listing every link as a numbered footnote.
+pub fn render_markdown(markdown: &str, layout: &EmailLayout) -> RenderedMarkdown {
+    let mut unsafe_html = String::new();
+    html::push_html(
+        &mut unsafe_html,
+        Parser::new_ext(markdown, markdown_options()),
+    );
+
+    let sanitized = ammonia::clean(&restore_escaped_placeholders(&unsafe_html));
+    let html = layout.wrap(&inline_styles(&sanitized));
+
+    RenderedMarkdown {
+        html,
+        text: render_text(markdown),
+    }
+}
+
+fn markdown_options() -> Options {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TABLES);
+    options
+}
+
+/// Render Markdown as readable plain text, replacing links with `[n]` footnote markers
+fn render_text(markdown: &str) -> String {
+    let mut text = String::new();
+    let mut links: Vec<String> = Vec::new();
+    // Ordered lists carry their next item number, unordered lists `None`
+    let mut lists: Vec<Option<u64>> = Vec::new();
+
+    for event in Parser::new_ext(markdown, markdown_options()) {
+        match event {
+            Event::Start(Tag::Heading(level, _, _)) => {
+                let depth = match level {
+                    HeadingLevel::H1 => 1,
+                    HeadingLevel::H2 => 2,
+                    _ => 3,
+                };
+                text.push_str(&"#".repeat(depth));
+                text.push(' ');
+            }
+            Event::Start(Tag::List(start)) => {
+                if !lists.is_empty() {
+                    text.push('\n');
+                }
+                lists.push(start);
+            }
+            Event::Start(Tag::Item) => {
+                text.push_str(&"  ".repeat(lists.len().saturating_sub(1)));
+                match lists.last_mut() {
+                    Some(Some(number)) => {
+                        text.push_str(&format!("{}. ", number));
+                        *number += 1;
+                    }
+                    _ => text.push_str("- "),
+                }
+            }
+            Event::Start(Tag::BlockQuote) => text.push_str("> "),
+            Event::Start(Tag::Link(_, destination, _)) => links.push(destination.to_string()),
+            Event::Start(Tag::Image(_, destination, _)) => {
+                links.push(destination.to_string());
+                text.push_str("[image: ");
+            }
+            Event::End(Tag::Link(..)) => text.push_str(&format!(" [{}]", links.len())),
+            Event::End(Tag::Image(..)) => text.push_str(&format!("] [{}]", links.len())),
+            Event::End(Tag::Paragraph)
+            | Event::End(Tag::Heading(..))
+            | Event::End(Tag::CodeBlock(_))
+            | Event::End(Tag::BlockQuote)
+            | Event::End(Tag::Table(_)) => {
+                trim_trailing_whitespace(&mut text);
+                text.push_str("\n\n");
+            }
+            Event::End(Tag::Item) | Event::End(Tag::TableRow) | Event::End(Tag::TableHead) => {
+                trim_trailing_whitespace(&mut text);
+                text.push('\n');
+            }
+            Event::End(Tag::TableCell) => text.push_str(" | "),
+            Event::End(Tag::List(_)) => {
+                lists.pop();
+                if lists.is_empty() {
+                    text.push('\n');
+                }
+            }
+            Event::Text(t) | Event::Code(t) => text.push_str(&t),
+            Event::SoftBreak => text.push(' '),
+            Event::HardBreak => text.push('\n'),
+            Event::Rule => text.push_str("----------\n\n"),
+            _ => {}
+        }
+    }
+
+    trim_trailing_whitespace(&mut text);
+    if !links.is_empty() {
+        text.push_str("\n\nLinks:\n");
+        for (i, link) in links.iter().enumerate() {
+            text.push_str(&format!("[{}] {}\n", i + 1, link));
+        }
+        trim_trailing_whitespace(&mut text);
+    }
+    text
+}
+
+fn trim_trailing_whitespace(text: &mut String) {
+    while text.ends_with('\n') || text.ends_with(' ') {
+        text.pop();
+    }
+}
+
+/// Add the inline style for each element we know about to its opening tag.
+///
+/// The input is the output of `ammonia`, so any `<` outside a tag has already been escaped.
+fn inline_styles(html: &str) -> String {
+    let mut styled = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        styled.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let name_end = rest[1..]
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .map(|i| i + 1)
+            .unwrap_or(rest.len());
+        let tag_end = find_tag_end(rest).unwrap_or(rest.len());
+        styled.push_str(&rest[..name_end]);
+        if let Some((_, style)) = INLINE_STYLES.iter().find(|(t, _)| *t == &rest[1..name_end]) {
+            styled.push_str(&format!(" style=\"{}\"", style));
+        }
+        styled.push_str(&rest[name_end..tag_end]);
+        rest = &rest[tag_end..];
+    }
+    styled.push_str(rest);
+    styled
+}
+
+/// The byte offset just past the `>` closing the tag at the start of `html`, skipping quoted
+/// attribute values
+fn find_tag_end(html: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in html.char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '>') => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// `pulldown-cmark` percent-encodes braces in link destinations, which would hide
+/// `[unsubscribe]({{ unsubscribe_url }})` from the template engine. Put them back.
+fn restore_escaped_placeholders(html: &str) -> String {
+    let mut restored = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find("%7B%7B") {
+        restored.push_str(&rest[..start]);
+        let after_open = &rest[start + 6..];
+        match after_open.find("%7D%7D") {
+            Some(end) => {
+                let name = after_open[..end].replace("%20", " ");
+                restored.push_str(&format!("{{{{{}}}}}", name));
+                rest = &after_open[end + 6..];
+            }
+            None => {
+                restored.push_str("%7B%7B");
+                rest = after_open;
+            }
+        }
+    }
+    restored.push_str(rest);
+    restored
+}
+
+/// Locate the byte range of the `{{ name }}` placeholder, ignoring inner whitespace
+fn find_placeholder(source: &str, name: &str) -> Option<(usize, usize)> {
+    let mut offset = 0;
+    while let Some(open) = source[offset..].find("{{") {
+        let start = offset + open;
+        let close = source[start..].find("}}")? + start;
+        if source[start + 2..close].trim() == name {
+            return Some((start, close + 2));
+        }
+        offset = close + 2;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_markdown, EmailLayout};
+    use claims::assert_err;
+
+    fn layout() -> EmailLayout {
+        EmailLayout::parse("<body>{{ content }}<a href=\"{{ unsubscribe_url }}\">Bye</a></body>")
+            .unwrap()
+    }
+
+    #[test]
+    fn a_layout_without_a_content_placeholder_is_rejected() {
+        assert_err!(EmailLayout::parse("<body></body>"));
+    }
+
+    #[test]
+    fn a_layout_with_two_content_placeholders_is_rejected() {
+        assert_err!(EmailLayout::parse("{{ content }}{{content}}"));
+    }
+
+    #[test]
+    fn an_invalid_layout_template_is_rejected() {
+        assert_err!(EmailLayout::parse("{{ content }} {{ unsubscribe_url"));
+    }
+
+    #[test]
+    fn markdown_is_wrapped_in_the_layout_with_inline_styles() {
+        let rendered = render_markdown("Hello **{{ name }}**!", &layout());
+        assert_eq!(
+            rendered.html,
+            "<body><p style=\"margin: 0 0 16px; font-size: 16px; line-height: 1.5;\">\
+            Hello <strong>{{ name }}</strong>!</p>\n\
+            <a href=\"{{ unsubscribe_url }}\">Bye</a></body>"
+        );
+    }
+
+    #[test]
+    fn unsafe_html_is_removed() {
+        let rendered = render_markdown(
+            "Hi <script>alert('pwned')</script><img src=x onerror=alert(1)>",
+            &layout(),
+        );
+        assert!(!rendered.html.contains("<script"));
+        assert!(!rendered.html.contains("onerror"));
+    }
+
+    #[test]
+    fn placeholders_in_link_destinations_survive_rendering() {
+        let rendered = render_markdown("[Leave]({{unsubscribe_url}})", &layout());
+        assert!(rendered.html.contains("href=\"{{unsubscribe_url}}\""));
+        assert!(rendered.text.contains("[1] {{unsubscribe_url}}"));
+    }
+
+    #[test]
+    fn links_become_footnotes_in_the_text_part() {
+        let rendered = render_markdown(
+            "# Issue 1\n\nRead [the post](https://example.com/post) and \
+            [the docs](https://example.com/docs).\n\n- one\n- two",
+            &layout(),
+        );
+        assert_eq!(
+            rendered.text,
+            "# Issue 1\n\n\
+            Read the post [1] and the docs [2].\n\n\
+            - one\n\
+            - two\n\n\
+            Links:\n\
+            [1] https://example.com/post\n\
+            [2] https://example.com/docs"
+        );
+    }
+}
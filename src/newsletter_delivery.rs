@@ -0,0 +1,461 @@
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::configuration::{NewsletterDeliverySettings, Settings, TrackingSettings};
+use crate::domain::{SubscriberEmail, SubscriptionStatus};
+use crate::email_client::{EmailClient, EmailHeader};
+use crate::shutdown::ShutdownSignal;
+use crate::startup::get_connection_pool;
+use crate::suppression::{is_suppressed, EmailHasher};
+use crate::template::{escape_html, Template, TemplateContext, TemplateFormat};
+use crate::tracking::{add_open_pixel, rewrite_links, TrackingToken};
+
+/// Queue an issue for every confirmed subscriber who has not received it yet, returning how many.
+///
+/// Publishing the same issue again, e.g. on a retry, only reaches the subscribers it missed.
+#[tracing::instrument(name = "Queue newsletter deliveries", skip(txn))]
+pub async fn enqueue_deliveries(
+    txn: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<u64, sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        Insert Into newsletter_delivery_queue (newsletter_issue_id, subscriber_id, next_attempt_at)
+        Select $1, s.id, $3
+        From subscriptions s
+        Where s.status = $2
+            -- Keep in sync with `suppression::is_suppressed`
+            And Not Exists (
+                Select 1 From suppressions x
+                Where (x.kind = 'address' And x.value = s.email_normalized)
+                    Or (x.kind = 'domain' And x.value = Split_Part(s.email_normalized, '@', 2))
+            )
+            And Not Exists (
+                Select 1 From newsletter_deliveries d
+                Where d.newsletter_issue_id = $1 And d.subscriber_id = s.id
+            )
+        On Conflict (newsletter_issue_id, subscriber_id) Do Nothing
+        "#,
+        newsletter_issue_id,
+        SubscriptionStatus::Confirmed as SubscriptionStatus,
+        Utc::now()
+    );
+    Ok(txn.execute(query).await?.rows_affected())
+}
+
+/// What happened to a queued delivery on this attempt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DeliveryOutcome {
+    Sent,
+    /// The subscriber cannot or should not receive the issue anymore
+    Skipped,
+    /// Will be retried after a backoff
+    Failed,
+    /// Failed too many times, it will not be retried
+    Abandoned,
+}
+
+struct QueuedDelivery {
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    attempts: i32,
+    title: String,
+    slug: String,
+    html_content: String,
+    text_content: String,
+    track_opens: bool,
+    track_clicks: bool,
+    email: String,
+    name: String,
+    status: SubscriptionStatus,
+    attributes: serde_json::Value,
+    subscription_token: Option<String>,
+}
+
+/// Delivers published newsletter issues, one subscriber at a time.
+///
+/// Every replica runs its own worker. Each delivery is claimed with `For Update Skip Locked` and
+/// committed on its own, so that on shutdown the one in flight is either finished or rolled back,
+/// releasing its claim for the next worker. An issue is only sent twice to the same subscriber
+/// if we crash between sending it and committing.
+pub struct NewsletterDeliveryWorker {
+    db_pool: PgPool,
+    email_client: EmailClient,
+    email_hasher: EmailHasher,
+    base_url: String,
+    tracking_settings: TrackingSettings,
+    settings: NewsletterDeliverySettings,
+}
+
+impl NewsletterDeliveryWorker {
+    pub fn build(configuration: &Settings) -> Self {
+        Self {
+            db_pool: get_connection_pool(&configuration.database),
+            email_client: configuration.email_client.clone().client(),
+            email_hasher: configuration.suppression.email_hasher(),
+            base_url: configuration.application.base_url.clone(),
+            tracking_settings: configuration.tracking.clone(),
+            settings: configuration.newsletter_delivery.clone(),
+        }
+    }
+
+    pub async fn run_until_stopped(self, mut shutdown: ShutdownSignal) {
+        let mut interval = tokio::time::interval(self.settings.poll_interval());
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.triggered() => return,
+            }
+            match self.deliver_due(Some(&shutdown)).await {
+                Ok(0) => {}
+                Ok(sent) => tracing::info!(sent, "Delivered newsletter issues"),
+                Err(e) => tracing::error!(
+                    error.cause_chain = ?e,
+                    "Failed to deliver newsletter issues"
+                ),
+            }
+        }
+    }
+
+    /// Attempt every due delivery, returning how many issues were sent
+    #[tracing::instrument(name = "Deliver due newsletter issues", skip(self))]
+    pub async fn run_once(&self) -> Result<u64, anyhow::Error> {
+        self.deliver_due(None).await
+    }
+
+    async fn deliver_due(&self, shutdown: Option<&ShutdownSignal>) -> Result<u64, anyhow::Error> {
+        let mut sent = 0;
+        // Failed deliveries are pushed back, so every iteration makes progress
+        while !shutdown.is_some_and(|shutdown| shutdown.is_triggered()) {
+            match self.deliver_next().await? {
+                Some(DeliveryOutcome::Sent) => sent += 1,
+                Some(_) => {}
+                None => break,
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Claim, attempt and settle the next due delivery, if there is one
+    async fn deliver_next(&self) -> Result<Option<DeliveryOutcome>, anyhow::Error> {
+        let mut txn = self
+            .db_pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?;
+        let delivery = sqlx::query_as!(
+            QueuedDelivery,
+            r#"
+            Select
+                q.newsletter_issue_id,
+                q.subscriber_id,
+                q.attempts,
+                i.title,
+                i.slug,
+                i.html_content,
+                i.text_content,
+                i.track_opens,
+                i.track_clicks,
+                s.email,
+                s.name,
+                s.status As "status: SubscriptionStatus",
+                s.attributes,
+                (
+                    Select t.subscription_token
+                    From subscription_tokens t
+                    Where t.subscriber_id = s.id
+                    Limit 1
+                ) As subscription_token
+            From newsletter_delivery_queue q
+            Join newsletter_issues i On i.newsletter_issue_id = q.newsletter_issue_id
+            Join subscriptions s On s.id = q.subscriber_id
+            Where q.next_attempt_at <= $1
+            Order By q.next_attempt_at
+            Limit 1
+            For Update Of q Skip Locked
+            "#,
+            Utc::now()
+        )
+        .fetch_optional(&mut *txn)
+        .await
+        .context("Failed to fetch the next due newsletter delivery.")?;
+        let Some(delivery) = delivery else {
+            return Ok(None);
+        };
+
+        let outcome = self.attempt(&delivery).await?;
+        let now = Utc::now();
+        if outcome == DeliveryOutcome::Failed {
+            let attempts = delivery.attempts + 1;
+            let query = sqlx::query!(
+                r#"
+                Update newsletter_delivery_queue
+                Set attempts = $3, next_attempt_at = $4
+                Where newsletter_issue_id = $1 And subscriber_id = $2
+                "#,
+                delivery.newsletter_issue_id,
+                delivery.subscriber_id,
+                attempts,
+                now + self.settings.retry_backoff(attempts)
+            );
+            txn.execute(query)
+                .await
+                .context("Failed to record a newsletter delivery attempt.")?;
+        } else {
+            if outcome == DeliveryOutcome::Sent {
+                let query = sqlx::query!(
+                    r#"
+                    Insert Into newsletter_deliveries (newsletter_issue_id, subscriber_id, delivered_at)
+                    Values ($1, $2, $3)
+                    On Conflict (newsletter_issue_id, subscriber_id) Do Nothing
+                    "#,
+                    delivery.newsletter_issue_id,
+                    delivery.subscriber_id,
+                    now
+                );
+                txn.execute(query)
+                    .await
+                    .context("Failed to record the delivery of a newsletter issue.")?;
+            }
+            let query = sqlx::query!(
+                r#"
+                Delete From newsletter_delivery_queue
+                Where newsletter_issue_id = $1 And subscriber_id = $2
+                "#,
+                delivery.newsletter_issue_id,
+                delivery.subscriber_id
+            );
+            txn.execute(query)
+                .await
+                .context("Failed to remove a delivery from the newsletter queue.")?;
+        }
+        txn.commit()
+            .await
+            .context("Failed to commit SQL transaction to deliver a newsletter issue.")?;
+        Ok(Some(outcome))
+    }
+
+    async fn attempt(&self, delivery: &QueuedDelivery) -> Result<DeliveryOutcome, anyhow::Error> {
+        // Unsubscribed since the issue was published
+        if delivery.status != SubscriptionStatus::Confirmed {
+            return Ok(DeliveryOutcome::Skipped);
+        }
+        let email = match SubscriberEmail::parse(delivery.email.clone()) {
+            Ok(email) => email,
+            Err(e) => {
+                tracing::warn!(
+                    subscriber_id = %delivery.subscriber_id,
+                    error = %e,
+                    "Skipping a confirmed subscriber. Their stored contact details are invalid"
+                );
+                return Ok(DeliveryOutcome::Skipped);
+            }
+        };
+        if !self.email_client.can_deliver_to(&email) {
+            tracing::warn!(
+                subscriber_id = %delivery.subscriber_id,
+                "Skipping a confirmed subscriber. Their address requires SMTPUTF8, \
+                which our email provider does not support"
+            );
+            return Ok(DeliveryOutcome::Skipped);
+        }
+        if is_suppressed(&self.db_pool, &self.email_hasher, &email)
+            .await
+            .context("Failed to check the suppression list.")?
+        {
+            return Ok(DeliveryOutcome::Skipped);
+        }
+
+        // Both templates were validated when the issue was published
+        let templates =
+            Template::parse(&delivery.html_content, TemplateFormat::Html).and_then(|html| {
+                Template::parse(&delivery.text_content, TemplateFormat::Text)
+                    .map(|text| (html, text))
+            });
+        let (html_template, text_template) = match templates {
+            Ok(templates) => templates,
+            Err(e) => {
+                tracing::error!(
+                    newsletter_issue_id = %delivery.newsletter_issue_id,
+                    error = %e,
+                    "Skipping a newsletter issue with an invalid template"
+                );
+                return Ok(DeliveryOutcome::Skipped);
+            }
+        };
+
+        let subscriber = ConfirmedSubscriber {
+            email,
+            name: &delivery.name,
+            attributes: &delivery.attributes,
+            subscription_token: delivery.subscription_token.as_deref(),
+        };
+        let web_version_url = format!("{}/archive/{}", self.base_url, delivery.slug);
+        let context = subscriber.template_context(&self.base_url);
+        let html_body = self.add_tracking(
+            &add_html_web_version_link(&html_template.render(&context), &web_version_url),
+            delivery,
+        );
+        let text_body =
+            add_text_web_version_link(&text_template.render(&context), &web_version_url);
+        // One-click unsubscribe from the mail client (RFC 8058), a POST to the same link
+        let list_unsubscribe = subscriber
+            .unsubscribe_url(&self.base_url)
+            .map(|url| format!("<{}>", url));
+        let headers = match &list_unsubscribe {
+            Some(list_unsubscribe) => vec![
+                EmailHeader {
+                    name: "List-Unsubscribe",
+                    value: list_unsubscribe,
+                },
+                EmailHeader {
+                    name: "List-Unsubscribe-Post",
+                    value: "List-Unsubscribe=One-Click",
+                },
+            ],
+            None => vec![],
+        };
+
+        let result = self
+            .email_client
+            .send_email_with_headers(
+                &subscriber.email,
+                &delivery.title,
+                &html_body,
+                &text_body,
+                &headers,
+            )
+            .await;
+        let attempts = delivery.attempts + 1;
+        Ok(match result {
+            Ok(()) => DeliveryOutcome::Sent,
+            Err(e) if attempts >= self.settings.max_attempts => {
+                tracing::error!(
+                    newsletter_issue_id = %delivery.newsletter_issue_id,
+                    subscriber_id = %delivery.subscriber_id,
+                    error.cause_chain = ?e,
+                    "Giving up on delivering a newsletter issue"
+                );
+                DeliveryOutcome::Abandoned
+            }
+            Err(e) => {
+                tracing::warn!(
+                    newsletter_issue_id = %delivery.newsletter_issue_id,
+                    subscriber_id = %delivery.subscriber_id,
+                    attempts,
+                    error.cause_chain = ?e,
+                    "Failed to deliver a newsletter issue"
+                );
+                DeliveryOutcome::Failed
+            }
+        })
+    }
+
+    /// Rewrite links through the click tracker and add the open pixel, as requested for the issue
+    fn add_tracking(&self, html: &str, delivery: &QueuedDelivery) -> String {
+        let signing_key = &self.tracking_settings.signing_key;
+        let mut html = html.to_string();
+        if delivery.track_clicks {
+            // Following the unsubscribe link should never be reported as engagement
+            let unsubscribe_url = format!("{}/subscriptions/unsubscribe", self.base_url);
+            html = rewrite_links(&html, |url| {
+                if url.starts_with(&unsubscribe_url) {
+                    return None;
+                }
+                let token = TrackingToken {
+                    newsletter_issue_id: delivery.newsletter_issue_id,
+                    subscriber_id: delivery.subscriber_id,
+                    url: Some(url.to_string()),
+                };
+                Some(format!(
+                    "{}/t/c/{}",
+                    self.base_url,
+                    token.encode(signing_key)
+                ))
+            });
+        }
+        if delivery.track_opens {
+            let token = TrackingToken {
+                newsletter_issue_id: delivery.newsletter_issue_id,
+                subscriber_id: delivery.subscriber_id,
+                url: None,
+            };
+            html = add_open_pixel(
+                &html,
+                &format!("{}/t/o/{}", self.base_url, token.encode(signing_key)),
+            );
+        }
+        html
+    }
+}
+
+/// Insert the "view in browser" link at the top of the email, inside `<body>` when there is one
+fn add_html_web_version_link(html: &str, web_version_url: &str) -> String {
+    let link = format!(
+        "<p style=\"margin: 0 0 16px; font-size: 12px; text-align: center;\">\
+        <a href=\"{}\">View this issue in your browser</a></p>\n",
+        escape_html(web_version_url)
+    );
+    let body_start = html
+        .to_ascii_lowercase()
+        .find("<body")
+        .and_then(|start| html[start..].find('>').map(|end| start + end + 1));
+    match body_start {
+        Some(position) => format!("{}\n{}{}", &html[..position], link, &html[position..]),
+        None => format!("{}{}", link, html),
+    }
+}
+
+fn add_text_web_version_link(text: &str, web_version_url: &str) -> String {
+    format!(
+        "View this issue in your browser: {}\n\n{}",
+        web_version_url, text
+    )
+}
+
+struct ConfirmedSubscriber<'a> {
+    email: SubscriberEmail,
+    name: &'a str,
+    attributes: &'a serde_json::Value,
+    subscription_token: Option<&'a str>,
+}
+
+impl ConfirmedSubscriber<'_> {
+    /// Build the merge fields available to a newsletter template for this subscriber.
+    ///
+    /// Custom attributes are exposed under their own names, but never shadow the built-in fields.
+    fn template_context(&self, base_url: &str) -> TemplateContext {
+        let mut context = TemplateContext::new();
+        if let serde_json::Value::Object(attributes) = self.attributes {
+            for (key, value) in attributes {
+                let value = match value {
+                    serde_json::Value::Null => String::new(),
+                    serde_json::Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                context.insert(key.as_str(), value);
+            }
+        }
+
+        context
+            .insert("name", self.name)
+            .insert("email", self.email.as_ref())
+            .insert(
+                "unsubscribe_url",
+                self.unsubscribe_url(base_url).unwrap_or_default(),
+            );
+        context
+    }
+
+    fn unsubscribe_url(&self, base_url: &str) -> Option<String> {
+        self.subscription_token.map(|token| {
+            format!(
+                "{}/subscriptions/unsubscribe?subscription_token={}",
+                base_url, token
+            )
+        })
+    }
+}
@@ -0,0 +1,293 @@
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::configuration::{OnboardingSettings, Settings};
+use crate::domain::{SubscriberEmail, SubscriptionStatus};
+use crate::email_client::EmailClient;
+use crate::shutdown::ShutdownSignal;
+use crate::startup::get_connection_pool;
+use crate::suppression::{is_suppressed, EmailHasher};
+use crate::template::{Template, TemplateContext, TemplateFormat};
+
+/// Due emails are claimed, sent and committed this many at a time
+const BATCH_SIZE: i64 = 100;
+
+/// Schedule the whole onboarding sequence for a subscriber who just confirmed.
+///
+/// Emails added to the sequence later are only sent to subscribers confirming after that.
+#[tracing::instrument(name = "Schedule the onboarding sequence", skip(txn))]
+pub async fn schedule_onboarding(
+    txn: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        Insert Into onboarding_deliveries (subscriber_id, onboarding_email_id, confirmed_at)
+        Select $1, id, $2
+        From onboarding_emails
+        On Conflict (subscriber_id, onboarding_email_id) Do Nothing
+        "#,
+        subscriber_id,
+        Utc::now()
+    );
+    txn.execute(query).await?;
+    Ok(())
+}
+
+/// [`schedule_onboarding`] for a batch of subscribers, e.g. those confirmed by an import
+#[tracing::instrument(name = "Schedule the onboarding sequences", skip_all)]
+pub async fn schedule_onboardings(
+    txn: &mut Transaction<'_, Postgres>,
+    subscriber_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    if subscriber_ids.is_empty() {
+        return Ok(());
+    }
+    let query = sqlx::query!(
+        r#"
+        Insert Into onboarding_deliveries (subscriber_id, onboarding_email_id, confirmed_at)
+        Select s.id, e.id, $2
+        From Unnest($1::uuid[]) As s(id)
+        Cross Join onboarding_emails e
+        On Conflict (subscriber_id, onboarding_email_id) Do Nothing
+        "#,
+        subscriber_ids,
+        Utc::now()
+    );
+    txn.execute(query).await?;
+    Ok(())
+}
+
+/// Drop the onboarding emails a subscriber has not received yet
+#[tracing::instrument(name = "Cancel the onboarding sequence", skip(txn))]
+pub async fn cancel_onboarding(
+    txn: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"Delete From onboarding_deliveries Where subscriber_id = $1 And sent_at Is Null"#,
+        subscriber_id
+    );
+    txn.execute(query).await?;
+    Ok(())
+}
+
+/// Drops a single email of the sequence for one subscriber, e.g. when it cannot be rendered
+async fn cancel_delivery(
+    txn: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    onboarding_email_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        Delete From onboarding_deliveries
+        Where subscriber_id = $1 And onboarding_email_id = $2 And sent_at Is Null
+        "#,
+        subscriber_id,
+        onboarding_email_id
+    );
+    txn.execute(query).await?;
+    Ok(())
+}
+
+/// Sends the onboarding emails that are due.
+///
+/// Every replica runs its own worker: deliveries are claimed with `For Update Skip Locked`,
+/// so an email is never sent twice.
+pub struct OnboardingWorker {
+    db_pool: PgPool,
+    email_client: EmailClient,
+    email_hasher: EmailHasher,
+    base_url: String,
+    settings: OnboardingSettings,
+}
+
+impl OnboardingWorker {
+    pub fn build(configuration: &Settings) -> Self {
+        Self {
+            db_pool: get_connection_pool(&configuration.database),
+            email_client: configuration.email_client.clone().client(),
+            email_hasher: configuration.suppression.email_hasher(),
+            base_url: configuration.application.base_url.clone(),
+            settings: configuration.onboarding.clone(),
+        }
+    }
+
+    pub async fn run_until_stopped(self, mut shutdown: ShutdownSignal) {
+        let mut interval = tokio::time::interval(self.settings.poll_interval());
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.triggered() => return,
+            }
+            match self.run_once().await {
+                Ok(0) => {}
+                Ok(sent) => tracing::info!(sent, "Sent onboarding emails"),
+                Err(e) => tracing::error!(
+                    error.cause_chain = ?e,
+                    "Failed to send onboarding emails"
+                ),
+            }
+        }
+    }
+
+    /// Send every due onboarding email, returning how many left
+    #[tracing::instrument(name = "Send due onboarding emails", skip(self))]
+    pub async fn run_once(&self) -> Result<u64, anyhow::Error> {
+        let mut sent = 0;
+        loop {
+            let mut txn = self
+                .db_pool
+                .begin()
+                .await
+                .context("Failed to acquire a Postgres connection from the pool")?;
+            let deliveries = sqlx::query!(
+                r#"
+                Select
+                    d.subscriber_id,
+                    d.onboarding_email_id,
+                    s.email,
+                    s.name,
+                    e.subject,
+                    e.html_body,
+                    e.text_body,
+                    (
+                        Select t.subscription_token
+                        From subscription_tokens t
+                        Where t.subscriber_id = s.id
+                        Limit 1
+                    ) As subscription_token
+                From onboarding_deliveries d
+                Join onboarding_emails e On e.id = d.onboarding_email_id
+                Join subscriptions s On s.id = d.subscriber_id
+                Where d.sent_at Is Null
+                    And s.status = $1
+                    And d.confirmed_at + Make_Interval(days => e.delay_days) <= $2
+                Order By d.confirmed_at, e.delay_days
+                Limit $3
+                For Update Of d Skip Locked
+                "#,
+                SubscriptionStatus::Confirmed as SubscriptionStatus,
+                Utc::now(),
+                BATCH_SIZE
+            )
+            .fetch_all(&mut *txn)
+            .await
+            .context("Failed to fetch the due onboarding emails.")?;
+            let claimed = deliveries.len() as i64;
+
+            let mut handled = 0;
+            for delivery in deliveries {
+                let email = match SubscriberEmail::parse(delivery.email) {
+                    Ok(email) => email,
+                    Err(e) => {
+                        tracing::warn!(
+                            subscriber_id = %delivery.subscriber_id,
+                            error = %e,
+                            "Cancelling the onboarding of a subscriber with an invalid address"
+                        );
+                        cancel_onboarding(&mut txn, delivery.subscriber_id)
+                            .await
+                            .context("Failed to cancel the onboarding sequence.")?;
+                        handled += 1;
+                        continue;
+                    }
+                };
+                if !self.email_client.can_deliver_to(&email)
+                    || is_suppressed(&self.db_pool, &self.email_hasher, &email)
+                        .await
+                        .context("Failed to check the suppression list.")?
+                {
+                    cancel_onboarding(&mut txn, delivery.subscriber_id)
+                        .await
+                        .context("Failed to cancel the onboarding sequence.")?;
+                    handled += 1;
+                    continue;
+                }
+
+                let mut context = TemplateContext::new();
+                context
+                    .insert("name", delivery.name)
+                    .insert("email", email.as_ref())
+                    .insert(
+                        "unsubscribe_url",
+                        delivery
+                            .subscription_token
+                            .map(|token| {
+                                format!(
+                                    "{}/subscriptions/unsubscribe?subscription_token={}",
+                                    self.base_url, token
+                                )
+                            })
+                            .unwrap_or_default(),
+                    );
+                // Templates are validated when admins save them, but an older one may not pass
+                // anymore: it would fail the same way on every run, so it is given up on.
+                let templates = Template::parse(&delivery.html_body, TemplateFormat::Html)
+                    .and_then(|html| {
+                        Template::parse(&delivery.text_body, TemplateFormat::Text)
+                            .map(|text| (html, text))
+                    });
+                let (html_body, text_body) = match templates {
+                    Ok((html, text)) => (html.render(&context), text.render(&context)),
+                    Err(e) => {
+                        tracing::error!(
+                            subscriber_id = %delivery.subscriber_id,
+                            onboarding_email_id = %delivery.onboarding_email_id,
+                            error = %e,
+                            "Skipping an onboarding email with an invalid template"
+                        );
+                        cancel_delivery(
+                            &mut txn,
+                            delivery.subscriber_id,
+                            delivery.onboarding_email_id,
+                        )
+                        .await
+                        .context("Failed to cancel an onboarding email.")?;
+                        handled += 1;
+                        continue;
+                    }
+                };
+
+                if let Err(e) = self
+                    .email_client
+                    .send_email(&email, &delivery.subject, &html_body, &text_body)
+                    .await
+                {
+                    // Try again on the next run
+                    tracing::warn!(
+                        subscriber_id = %delivery.subscriber_id,
+                        error.cause_chain = ?e,
+                        "Failed to send an onboarding email"
+                    );
+                    continue;
+                }
+                let query = sqlx::query!(
+                    r#"
+                    Update onboarding_deliveries
+                    Set sent_at = $3
+                    Where subscriber_id = $1 And onboarding_email_id = $2
+                    "#,
+                    delivery.subscriber_id,
+                    delivery.onboarding_email_id,
+                    Utc::now()
+                );
+                txn.execute(query)
+                    .await
+                    .context("Failed to record an onboarding email as sent.")?;
+                sent += 1;
+                handled += 1;
+            }
+            txn.commit()
+                .await
+                .context("Failed to commit SQL transaction to send onboarding emails.")?;
+            // Stop when nothing is due anymore, or when only failing emails are left
+            if claimed < BATCH_SIZE || handled == 0 {
+                return Ok(sent);
+            }
+        }
+    }
+}
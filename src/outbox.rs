@@ -0,0 +1,329 @@
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::configuration::{EmailOutboxSettings, Settings};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::email_templates::RenderedEmail;
+use crate::shutdown::ShutdownSignal;
+use crate::startup::get_connection_pool;
+use crate::subscription_events::{record_subscription_event, EventSource, SubscriptionEventKind};
+use crate::suppression::{is_suppressed, EmailHasher};
+
+/// Due emails are claimed, sent and committed this many at a time
+const BATCH_SIZE: i64 = 100;
+
+/// Why an email was written to the outbox, deciding what is recorded once it is sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutboxEmailKind {
+    Confirmation,
+}
+
+impl OutboxEmailKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutboxEmailKind::Confirmation => "confirmation",
+        }
+    }
+}
+
+/// Write an email to the outbox, to be sent once `txn` commits
+#[tracing::instrument(name = "Write an email to the outbox", skip(txn, recipient, email))]
+pub async fn enqueue_email(
+    txn: &mut Transaction<'_, Postgres>,
+    subscriber_id: Option<Uuid>,
+    kind: OutboxEmailKind,
+    recipient: &SubscriberEmail,
+    email: &RenderedEmail,
+) -> Result<Uuid, sqlx::Error> {
+    let id = Uuid::new_v4();
+    let now = Utc::now();
+    let query = sqlx::query!(
+        r#"
+        Insert Into email_outbox (
+            id, subscriber_id, kind, recipient, subject, html_body, text_body,
+            created_at, next_attempt_at
+        )
+        Values ($1, $2, $3, $4, $5, $6, $7, $8, $8)
+        "#,
+        id,
+        subscriber_id,
+        kind.as_str(),
+        recipient.as_ref(),
+        email.subject,
+        email.html_body,
+        email.text_body,
+        now
+    );
+    txn.execute(query).await?;
+    Ok(id)
+}
+
+/// What happened to an outbox email on this attempt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DeliveryOutcome {
+    Sent,
+    Suppressed,
+    /// Will be retried after a backoff
+    Failed,
+    /// Failed too many times, it will not be retried
+    Abandoned,
+}
+
+impl DeliveryOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryOutcome::Sent => "sent",
+            DeliveryOutcome::Suppressed => "suppressed",
+            DeliveryOutcome::Failed => "failed",
+            DeliveryOutcome::Abandoned => "abandoned",
+        }
+    }
+}
+
+struct OutboxEmail {
+    id: Uuid,
+    subscriber_id: Option<Uuid>,
+    kind: String,
+    recipient: String,
+    subject: String,
+    html_body: String,
+    text_body: String,
+    attempts: i32,
+}
+
+/// Delivers the emails written to the outbox.
+///
+/// Request handlers try to deliver their email as soon as their transaction commits, the
+/// background worker retries whatever they could not send. Emails are claimed with
+/// `For Update Skip Locked` and marked in the same transaction: delivery is at-least-once,
+/// an email is only sent twice if we crash between sending it and committing.
+#[derive(Clone)]
+pub struct OutboxDispatcher {
+    db_pool: PgPool,
+    email_client: EmailClient,
+    email_hasher: EmailHasher,
+    settings: EmailOutboxSettings,
+}
+
+impl OutboxDispatcher {
+    pub fn build(configuration: &Settings) -> Self {
+        Self::new(
+            get_connection_pool(&configuration.database),
+            configuration.email_client.clone().client(),
+            configuration.suppression.email_hasher(),
+            configuration.email_outbox.clone(),
+        )
+    }
+
+    pub fn new(
+        db_pool: PgPool,
+        email_client: EmailClient,
+        email_hasher: EmailHasher,
+        settings: EmailOutboxSettings,
+    ) -> Self {
+        Self {
+            db_pool,
+            email_client,
+            email_hasher,
+            settings,
+        }
+    }
+
+    pub async fn run_until_stopped(self, mut shutdown: ShutdownSignal) {
+        let mut interval = tokio::time::interval(self.settings.poll_interval());
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.triggered() => return,
+            }
+            match self.run_once().await {
+                Ok(0) => {}
+                Ok(sent) => tracing::info!(sent, "Sent emails from the outbox"),
+                Err(e) => tracing::error!(
+                    error.cause_chain = ?e,
+                    "Failed to send emails from the outbox"
+                ),
+            }
+        }
+    }
+
+    /// Attempt every due email, returning how many were sent
+    #[tracing::instrument(name = "Send due outbox emails", skip(self))]
+    pub async fn run_once(&self) -> Result<u64, anyhow::Error> {
+        let mut sent = 0;
+        loop {
+            let mut txn = self
+                .db_pool
+                .begin()
+                .await
+                .context("Failed to acquire a Postgres connection from the pool")?;
+            let emails = sqlx::query_as!(
+                OutboxEmail,
+                r#"
+                Select id, subscriber_id, kind, recipient, subject, html_body, text_body, attempts
+                From email_outbox
+                Where processed_at Is Null And next_attempt_at <= $1
+                Order By next_attempt_at
+                Limit $2
+                For Update Skip Locked
+                "#,
+                Utc::now(),
+                BATCH_SIZE
+            )
+            .fetch_all(&mut *txn)
+            .await
+            .context("Failed to fetch the due outbox emails.")?;
+            let claimed = emails.len() as i64;
+
+            for email in emails {
+                if self.attempt(&mut txn, email).await? == DeliveryOutcome::Sent {
+                    sent += 1;
+                }
+            }
+            txn.commit()
+                .await
+                .context("Failed to commit SQL transaction to send outbox emails.")?;
+            // Failed emails are pushed back, so every batch makes progress
+            if claimed < BATCH_SIZE {
+                return Ok(sent);
+            }
+        }
+    }
+
+    /// Try to send one email straight away, leaving it to the worker if anything goes wrong
+    #[tracing::instrument(name = "Send an outbox email", skip(self))]
+    pub async fn try_deliver(&self, id: Uuid) {
+        if let Err(e) = self.deliver(id).await {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                "Failed to send an outbox email, it will be retried"
+            );
+        }
+    }
+
+    async fn deliver(&self, id: Uuid) -> Result<(), anyhow::Error> {
+        let mut txn = self
+            .db_pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?;
+        // Skipped when the worker already claimed it
+        let email = sqlx::query_as!(
+            OutboxEmail,
+            r#"
+            Select id, subscriber_id, kind, recipient, subject, html_body, text_body, attempts
+            From email_outbox
+            Where id = $1 And processed_at Is Null
+            For Update Skip Locked
+            "#,
+            id
+        )
+        .fetch_optional(&mut *txn)
+        .await
+        .context("Failed to fetch the outbox email.")?;
+        if let Some(email) = email {
+            self.attempt(&mut txn, email).await?;
+        }
+        txn.commit()
+            .await
+            .context("Failed to commit SQL transaction to send an outbox email.")?;
+        Ok(())
+    }
+
+    async fn attempt(
+        &self,
+        txn: &mut Transaction<'_, Postgres>,
+        email: OutboxEmail,
+    ) -> Result<DeliveryOutcome, anyhow::Error> {
+        let attempts = email.attempts + 1;
+        let result = match SubscriberEmail::parse(email.recipient) {
+            Ok(recipient) => {
+                if !self.email_client.can_deliver_to(&recipient)
+                    || is_suppressed(&self.db_pool, &self.email_hasher, &recipient)
+                        .await
+                        .context("Failed to check the suppression list.")?
+                {
+                    Ok(DeliveryOutcome::Suppressed)
+                } else {
+                    self.email_client
+                        .send_email(
+                            &recipient,
+                            &email.subject,
+                            &email.html_body,
+                            &email.text_body,
+                        )
+                        .await
+                        .map(|_| DeliveryOutcome::Sent)
+                        .map_err(anyhow::Error::from)
+                }
+            }
+            Err(e) => Err(anyhow::anyhow!(e)),
+        };
+        let (outcome, last_error) = match result {
+            Ok(outcome) => (outcome, None),
+            Err(e) if attempts >= self.settings.max_attempts => {
+                tracing::error!(
+                    outbox_email_id = %email.id,
+                    error.cause_chain = ?e,
+                    "Giving up on an outbox email"
+                );
+                (DeliveryOutcome::Abandoned, Some(e.to_string()))
+            }
+            Err(e) => {
+                tracing::warn!(
+                    outbox_email_id = %email.id,
+                    attempts,
+                    error.cause_chain = ?e,
+                    "Failed to send an outbox email"
+                );
+                (DeliveryOutcome::Failed, Some(e.to_string()))
+            }
+        };
+
+        let now = Utc::now();
+        let processed_at = (outcome != DeliveryOutcome::Failed).then_some(now);
+        let query = sqlx::query!(
+            r#"
+            Update email_outbox
+            Set attempts = $2,
+                next_attempt_at = $3,
+                last_error = $4,
+                processed_at = $5,
+                outcome = $6
+            Where id = $1
+            "#,
+            email.id,
+            attempts,
+            now + self.settings.retry_backoff(attempts),
+            last_error,
+            processed_at,
+            outcome.as_str()
+        );
+        txn.execute(query)
+            .await
+            .context("Failed to record an outbox delivery attempt.")?;
+
+        if let (DeliveryOutcome::Sent, Some(subscriber_id)) = (outcome, email.subscriber_id) {
+            if email.kind == OutboxEmailKind::Confirmation.as_str() {
+                let event_source = EventSource {
+                    ip_address: None,
+                    user_agent: None,
+                    source: "email outbox".into(),
+                };
+                record_subscription_event(
+                    txn,
+                    subscriber_id,
+                    SubscriptionEventKind::ConfirmationSent,
+                    &event_source,
+                )
+                .await
+                .context("Failed to record that the confirmation email was sent.")?;
+            }
+        }
+        Ok(outcome)
+    }
+}
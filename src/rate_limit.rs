@@ -0,0 +1,357 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use crate::configuration::{RateLimit, RateLimitSettings, RateLimitStoreKind};
+use crate::domain::SubscriberEmail;
+use crate::shutdown::ShutdownSignal;
+use crate::suppression::EmailHasher;
+
+/// Requests counted for a key since the start of its current window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    pub started_at: DateTime<Utc>,
+    pub hits: u32,
+}
+
+impl Window {
+    /// Count one more request, starting a new window if `current` is missing or expired
+    pub fn hit(current: Option<Window>, now: DateTime<Utc>, limit: &RateLimit) -> Window {
+        match current {
+            Some(window) if !window.has_expired(now, limit) => Window {
+                hits: window.hits.saturating_add(1),
+                ..window
+            },
+            _ => Window {
+                started_at: now,
+                hits: 1,
+            },
+        }
+    }
+
+    pub fn has_expired(&self, now: DateTime<Utc>, limit: &RateLimit) -> bool {
+        self.started_at + limit.window() <= now
+    }
+
+    /// `None` while the window is within the limit, otherwise how long until it resets
+    pub fn retry_after(
+        &self,
+        now: DateTime<Utc>,
+        limit: &RateLimit,
+    ) -> Option<std::time::Duration> {
+        if self.hits <= limit.max_requests {
+            return None;
+        }
+        let remaining = (self.started_at + limit.window() - now)
+            .to_std()
+            .unwrap_or_default();
+        // Never ask clients to retry immediately, they would be limited again
+        Some(remaining.max(std::time::Duration::from_secs(1)))
+    }
+}
+
+/// Counters kept in this process, for a single replica
+#[derive(Default)]
+struct MemoryStore {
+    windows: HashMap<String, StoredWindow>,
+    /// The same keys ordered by expiry, to forget expired windows without scanning them all
+    expiries: BTreeSet<(DateTime<Utc>, String)>,
+}
+
+#[derive(Clone, Copy)]
+struct StoredWindow {
+    window: Window,
+    /// Keys are counted against different limits, each window knows when it ends
+    expires_at: DateTime<Utc>,
+}
+
+impl MemoryStore {
+    fn hit(&mut self, key: &str, now: DateTime<Utc>, limit: &RateLimit, max_keys: usize) -> Window {
+        let current = self.windows.get(key).copied();
+        let window = Window::hit(current.map(|stored| stored.window), now, limit);
+        let expires_at = window.started_at + limit.window();
+        match current {
+            Some(stored) => {
+                self.expiries.remove(&(stored.expires_at, key.to_owned()));
+            }
+            None => {
+                // Make room by forgetting the window closest to its end, the least to lose
+                while self.windows.len() >= max_keys {
+                    let Some((_, evicted)) = self.expiries.pop_first() else {
+                        break;
+                    };
+                    self.windows.remove(&evicted);
+                }
+            }
+        }
+        self.windows
+            .insert(key.to_owned(), StoredWindow { window, expires_at });
+        self.expiries.insert((expires_at, key.to_owned()));
+        window
+    }
+
+    /// Forget the windows that ended, returning how many
+    fn prune(&mut self, now: DateTime<Utc>) -> u64 {
+        let mut pruned = 0;
+        while let Some((expires_at, _)) = self.expiries.first() {
+            if *expires_at > now {
+                break;
+            }
+            let (_, key) = self.expiries.pop_first().expect("Checked just above.");
+            self.windows.remove(&key);
+            pruned += 1;
+        }
+        pruned
+    }
+}
+
+enum RateLimitStore {
+    Memory(Mutex<MemoryStore>),
+    Postgres(PgPool),
+}
+
+/// Throttles signups per client IP and per email address, in fixed windows
+pub struct RateLimiter {
+    enabled: bool,
+    store: RateLimitStore,
+    per_ip: RateLimit,
+    per_email: RateLimit,
+    max_memory_keys: usize,
+    prune_interval: std::time::Duration,
+    email_hasher: EmailHasher,
+}
+
+impl RateLimiter {
+    pub fn from_settings(
+        settings: &RateLimitSettings,
+        db_pool: PgPool,
+        email_hasher: EmailHasher,
+    ) -> Self {
+        let store = match settings.store {
+            RateLimitStoreKind::Memory => RateLimitStore::Memory(Mutex::default()),
+            RateLimitStoreKind::Postgres => RateLimitStore::Postgres(db_pool),
+        };
+        Self {
+            enabled: settings.enabled,
+            store,
+            per_ip: settings.per_ip,
+            per_email: settings.per_email,
+            max_memory_keys: settings.max_memory_keys,
+            prune_interval: settings.prune_interval(),
+            email_hasher,
+        }
+    }
+
+    /// Count a signup attempt from `ip_address`, returning how long to wait if over the limit
+    pub async fn check_ip(
+        &self,
+        ip_address: &str,
+    ) -> Result<Option<std::time::Duration>, sqlx::Error> {
+        self.check(&format!("ip:{}", ip_address), self.per_ip).await
+    }
+
+    /// Count a signup attempt for `email`, returning how long to wait if over the limit.
+    ///
+    /// Addresses are only ever stored hashed.
+    pub async fn check_email(
+        &self,
+        email: &SubscriberEmail,
+    ) -> Result<Option<std::time::Duration>, sqlx::Error> {
+        self.check(
+            &format!("email:{}", self.email_hasher.hash(&email.normalized())),
+            self.per_email,
+        )
+        .await
+    }
+
+    #[tracing::instrument(name = "Check a rate limit", skip(self))]
+    async fn check(
+        &self,
+        key: &str,
+        limit: RateLimit,
+    ) -> Result<Option<std::time::Duration>, sqlx::Error> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        let now = Utc::now();
+        let window = match &self.store {
+            RateLimitStore::Memory(store) => {
+                store
+                    .lock()
+                    .unwrap()
+                    .hit(key, now, &limit, self.max_memory_keys)
+            }
+            RateLimitStore::Postgres(db_pool) => hit_in_postgres(db_pool, key, now, &limit).await?,
+        };
+        Ok(window.retry_after(now, &limit))
+    }
+
+    /// Forget the windows that ended, so that the store does not grow with every client seen
+    #[tracing::instrument(name = "Prune expired rate limit windows", skip(self))]
+    pub async fn prune(&self) -> Result<u64, sqlx::Error> {
+        let now = Utc::now();
+        match &self.store {
+            RateLimitStore::Memory(store) => Ok(store.lock().unwrap().prune(now)),
+            RateLimitStore::Postgres(db_pool) => {
+                let deleted = sqlx::query!(
+                    r#"Delete From rate_limit_buckets Where expires_at <= $1"#,
+                    now
+                )
+                .execute(db_pool)
+                .await?
+                .rows_affected();
+                Ok(deleted)
+            }
+        }
+    }
+
+    pub async fn prune_until_stopped(self: Arc<Self>, mut shutdown: ShutdownSignal) {
+        let mut interval = tokio::time::interval(self.prune_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.triggered() => return,
+            }
+            match self.prune().await {
+                Ok(0) => {}
+                Ok(pruned) => tracing::debug!(pruned, "Pruned expired rate limit windows"),
+                Err(e) => tracing::error!(
+                    error.cause_chain = ?e,
+                    "Failed to prune expired rate limit windows"
+                ),
+            }
+        }
+    }
+}
+
+/// The same logic as [`Window::hit`], applied atomically so that replicas share counters
+async fn hit_in_postgres(
+    db_pool: &PgPool,
+    key: &str,
+    now: DateTime<Utc>,
+    limit: &RateLimit,
+) -> Result<Window, sqlx::Error> {
+    let expired_before = now - limit.window();
+    let row = sqlx::query!(
+        r#"
+        Insert Into rate_limit_buckets (key, window_started_at, hits, expires_at)
+        Values ($1, $2, 1, $4)
+        On Conflict (key) Do Update Set
+            window_started_at = Case
+                When rate_limit_buckets.window_started_at <= $3 Then $2
+                Else rate_limit_buckets.window_started_at
+            End,
+            hits = Case
+                When rate_limit_buckets.window_started_at <= $3 Then 1
+                Else rate_limit_buckets.hits + 1
+            End,
+            expires_at = Case
+                When rate_limit_buckets.window_started_at <= $3 Then $4
+                Else rate_limit_buckets.expires_at
+            End
+        Returning window_started_at, hits
+        "#,
+        key,
+        now,
+        expired_before,
+        now + limit.window()
+    )
+    .fetch_one(db_pool)
+    .await?;
+    Ok(Window {
+        started_at: row.window_started_at,
+        hits: row.hits.max(0) as u32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryStore, Window};
+    use crate::configuration::RateLimit;
+    use chrono::{Duration, Utc};
+
+    const LIMIT: RateLimit = RateLimit {
+        max_requests: 2,
+        window_seconds: 60,
+    };
+    const LONG_LIMIT: RateLimit = RateLimit {
+        max_requests: 2,
+        window_seconds: 3600,
+    };
+
+    #[test]
+    fn requests_within_the_limit_are_allowed() {
+        let now = Utc::now();
+        let first = Window::hit(None, now, &LIMIT);
+        let second = Window::hit(Some(first), now, &LIMIT);
+        assert_eq!(second.hits, 2);
+        assert_eq!(second.retry_after(now, &LIMIT), None);
+    }
+
+    #[test]
+    fn requests_over_the_limit_wait_for_the_window_to_end() {
+        let start = Utc::now();
+        let mut window = None;
+        for _ in 0..3 {
+            window = Some(Window::hit(window, start, &LIMIT));
+        }
+        let retry_after = window
+            .unwrap()
+            .retry_after(start + Duration::seconds(20), &LIMIT);
+        assert_eq!(retry_after, Some(std::time::Duration::from_secs(40)));
+    }
+
+    #[test]
+    fn a_new_window_starts_once_the_previous_one_expired() {
+        let start = Utc::now();
+        let full = Window {
+            started_at: start,
+            hits: 10,
+        };
+        let later = start + Duration::seconds(60);
+        let window = Window::hit(Some(full), later, &LIMIT);
+        assert_eq!(
+            window,
+            Window {
+                started_at: later,
+                hits: 1
+            }
+        );
+        assert_eq!(window.retry_after(later, &LIMIT), None);
+    }
+
+    #[test]
+    fn pruning_keeps_windows_that_have_not_ended_for_their_own_limit() {
+        let start = Utc::now();
+        let mut store = MemoryStore::default();
+        store.hit("ip:short", start, &LIMIT, 10);
+        for _ in 0..3 {
+            store.hit("email:long", start, &LONG_LIMIT, 10);
+        }
+
+        let pruned = store.prune(start + Duration::seconds(61));
+
+        assert_eq!(pruned, 1);
+        assert!(!store.windows.contains_key("ip:short"));
+        let long = store.hit("email:long", start + Duration::seconds(61), &LONG_LIMIT, 10);
+        assert_eq!(long.hits, 4);
+    }
+
+    #[test]
+    fn the_memory_store_forgets_the_window_closest_to_its_end_when_full() {
+        let start = Utc::now();
+        let mut store = MemoryStore::default();
+        store.hit("email:long", start, &LONG_LIMIT, 2);
+        store.hit("ip:short", start, &LIMIT, 2);
+
+        store.hit("ip:new", start, &LIMIT, 2);
+
+        assert_eq!(store.windows.len(), 2);
+        assert_eq!(store.expiries.len(), 2);
+        assert!(store.windows.contains_key("email:long"));
+        assert!(!store.windows.contains_key("ip:short"));
+    }
+}
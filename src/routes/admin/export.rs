@@ -0,0 +1,195 @@
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::web::Bytes;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use super::subscribers::contains_pattern;
+use super::{require_admin, AdminError};
+use crate::configuration::AdminSettings;
+use crate::domain::{OptIn, SubscriptionStatus};
+use crate::import::csv_field;
+
+/// How many rendered rows may wait for a slow client before we stop reading from Postgres
+const EXPORT_BUFFER_ROWS: usize = 256;
+
+#[derive(serde::Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    /// One JSON object per line
+    Ndjson,
+}
+
+impl ExportFormat {
+    fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    fn file_name(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "subscribers.csv",
+            ExportFormat::Ndjson => "subscribers.ndjson",
+        }
+    }
+}
+
+/// The same filters as the subscriber list, without pagination
+#[derive(serde::Deserialize, Debug)]
+pub struct ExportParameters {
+    #[serde(default)]
+    format: ExportFormat,
+    status: Option<SubscriptionStatus>,
+    subscribed_after: Option<DateTime<Utc>>,
+    subscribed_before: Option<DateTime<Utc>>,
+    q: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct ExportedSubscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: SubscriptionStatus,
+    subscribed_at: DateTime<Utc>,
+    /// When the subscriber last confirmed, `None` if they never did
+    confirmed_at: Option<DateTime<Utc>>,
+    opt_in_mode: OptIn,
+    attributes: serde_json::Value,
+}
+
+const CSV_HEADER: &str = "id,email,name,status,subscribed_at,confirmed_at,opt_in_mode,attributes\n";
+
+impl ExportedSubscriber {
+    fn render(&self, format: ExportFormat) -> String {
+        match format {
+            ExportFormat::Csv => format!(
+                "{},{},{},{},{},{},{},{}\n",
+                self.id,
+                csv_field(&self.email),
+                csv_field(&self.name),
+                self.status,
+                self.subscribed_at.to_rfc3339(),
+                self.confirmed_at
+                    .map(|confirmed_at| confirmed_at.to_rfc3339())
+                    .unwrap_or_default(),
+                self.opt_in_mode,
+                csv_field(&self.attributes.to_string())
+            ),
+            ExportFormat::Ndjson => {
+                let mut line =
+                    serde_json::to_string(self).expect("Failed to serialize a subscriber.");
+                line.push('\n');
+                line
+            }
+        }
+    }
+}
+
+/// Stream every subscriber matching the filters, for backups and the data warehouse.
+///
+/// Rows are rendered as they come out of a Postgres cursor, so the export never holds the
+/// whole table in memory. A failure half-way through aborts the response rather than
+/// truncating it silently.
+#[tracing::instrument(name = "Export subscribers", skip(request, db_pool, admin))]
+pub async fn export_subscribers(
+    parameters: web::Query<ExportParameters>,
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    admin: web::Data<AdminSettings>,
+) -> Result<HttpResponse, AdminError> {
+    require_admin(&request, &admin)?;
+    let parameters = parameters.into_inner();
+    let format = parameters.format;
+    let db_pool = db_pool.get_ref().clone();
+
+    let (sender, receiver) = mpsc::channel(EXPORT_BUFFER_ROWS);
+    tokio::spawn(
+        async move {
+            if format == ExportFormat::Csv
+                && sender.send(Ok(Bytes::from(CSV_HEADER))).await.is_err()
+            {
+                return;
+            }
+            stream_subscribers(&db_pool, &parameters, sender).await;
+        }
+        .instrument(tracing::Span::current()),
+    );
+    let body = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format.file_name().into())],
+        })
+        .streaming(body))
+}
+
+/// Send the rendered rows until the cursor is exhausted or the client goes away
+async fn stream_subscribers(
+    db_pool: &PgPool,
+    parameters: &ExportParameters,
+    sender: mpsc::Sender<Result<Bytes, sqlx::Error>>,
+) {
+    let pattern = parameters
+        .q
+        .as_deref()
+        .map(str::trim)
+        .filter(|q| !q.is_empty())
+        .map(contains_pattern);
+    let mut rows = sqlx::query_as!(
+        ExportedSubscriber,
+        r#"
+        Select
+            s.id,
+            s.email,
+            s.name,
+            s.status As "status: SubscriptionStatus",
+            s.subscribed_at,
+            (
+                Select Max(e.occurred_at)
+                From subscription_events e
+                Where e.subscriber_id = s.id And e.kind = 'confirmed'
+            ) As "confirmed_at?",
+            s.opt_in_mode As "opt_in_mode: OptIn",
+            s.attributes
+        From subscriptions s
+        Where ($1::subscription_status Is Null Or s.status = $1)
+            And ($2::timestamptz Is Null Or s.subscribed_at >= $2)
+            And ($3::timestamptz Is Null Or s.subscribed_at < $3)
+            And ($4::text Is Null Or s.email Ilike $4 Or s.name Ilike $4)
+        Order By s.subscribed_at, s.id
+        "#,
+        parameters.status as Option<SubscriptionStatus>,
+        parameters.subscribed_after,
+        parameters.subscribed_before,
+        pattern
+    )
+    .fetch(db_pool);
+
+    while let Some(row) = rows.next().await {
+        let chunk = row.map(|subscriber| Bytes::from(subscriber.render(parameters.format)));
+        if let Err(e) = &chunk {
+            tracing::error!(error.cause_chain = ?e, "Failed to fetch subscribers to export.");
+        }
+        let failed = chunk.is_err();
+        if sender.send(chunk).await.is_err() {
+            tracing::info!("The client went away before the export was complete.");
+            return;
+        }
+        if failed {
+            return;
+        }
+    }
+}
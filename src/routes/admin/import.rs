@@ -0,0 +1,94 @@
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use futures_util::StreamExt;
+use sqlx::PgPool;
+
+use super::{require_admin, AdminError};
+use crate::configuration::{AdminSettings, OptInMode};
+use crate::email_templates::ConfirmationEmailTemplates;
+use crate::import::{DuplicateMode, ImportError, SubscriberImport};
+use crate::startup::ApplicationBaseUrl;
+use crate::suppression::EmailHasher;
+
+#[derive(serde::Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportFormat {
+    #[default]
+    Json,
+    /// Only the rows that were not imported, ready to be fixed and uploaded again
+    Csv,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ImportParameters {
+    #[serde(default)]
+    mode: DuplicateMode,
+    /// For the rows without a `consent` column saying yes
+    #[serde(default)]
+    opt_in: OptInMode,
+    #[serde(default)]
+    format: ReportFormat,
+}
+
+/// Import subscribers from a CSV upload, see [`SubscriberImport`] for the expected columns.
+///
+/// The body is processed as it arrives so that large migrations do not need to fit in memory.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Import subscribers",
+    skip(
+        body,
+        request,
+        db_pool,
+        email_hasher,
+        base_url,
+        confirmation_email_templates,
+        admin
+    )
+)]
+pub async fn import_subscribers(
+    parameters: web::Query<ImportParameters>,
+    mut body: web::Payload,
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    email_hasher: web::Data<EmailHasher>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    confirmation_email_templates: web::Data<ConfirmationEmailTemplates>,
+    admin: web::Data<AdminSettings>,
+) -> Result<HttpResponse, AdminError> {
+    require_admin(&request, &admin)?;
+    let source = format!("{} {}", request.method(), request.path());
+    let mut import = SubscriberImport::new(
+        &db_pool,
+        email_hasher.get_ref().clone(),
+        &base_url.0,
+        confirmation_email_templates.select(None, None),
+        parameters.mode,
+        parameters.opt_in,
+        source,
+    );
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.context("Failed to read the uploaded file.")?;
+        import.feed(&chunk).await.map_err(import_error)?;
+    }
+    let report = import.finish().await.map_err(import_error)?;
+
+    match parameters.format {
+        ReportFormat::Json => Ok(HttpResponse::Ok().json(report)),
+        ReportFormat::Csv => Ok(HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header(ContentDisposition {
+                disposition: DispositionType::Attachment,
+                parameters: vec![DispositionParam::Filename("import-errors.csv".into())],
+            })
+            .body(report.errors_csv())),
+    }
+}
+
+fn import_error(e: ImportError) -> AdminError {
+    match e {
+        ImportError::InvalidHeader(e) => AdminError::ValidationError(e),
+        ImportError::UnexpectedError(e) => AdminError::UnexpectedError(e),
+    }
+}
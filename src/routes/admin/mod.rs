@@ -0,0 +1,78 @@
+mod export;
+mod import;
+mod onboarding;
+mod subscribers;
+mod suppressions;
+
+pub use export::*;
+pub use import::*;
+pub use onboarding::*;
+pub use subscribers::*;
+pub use suppressions::*;
+
+use actix_web::http::header::{ContentType, HeaderValue, WWW_AUTHENTICATE};
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+
+use crate::authentication::{basic_authentication, validate_admin_credentials};
+use crate::configuration::AdminSettings;
+use crate::routes::error_chain_fmt;
+
+#[derive(thiserror::Error)]
+pub enum AdminError {
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The requested resource was not found.")]
+    NotFound,
+    #[error("{0}")]
+    Conflict(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for AdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for AdminError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AdminError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            AdminError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            AdminError::NotFound => StatusCode::NOT_FOUND,
+            AdminError::Conflict(_) => StatusCode::CONFLICT,
+            AdminError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            AdminError::AuthError(_) => {
+                let mut response = HttpResponse::new(self.status_code());
+                response.headers_mut().insert(
+                    WWW_AUTHENTICATE,
+                    HeaderValue::from_static(r#"Basic realm="admin""#),
+                );
+                response
+            }
+            // Tell admins what to fix
+            AdminError::ValidationError(_) | AdminError::NotFound | AdminError::Conflict(_) => {
+                HttpResponse::build(self.status_code())
+                    .content_type(ContentType::plaintext())
+                    .body(self.to_string())
+            }
+            // Do not leak the details of what went wrong
+            AdminError::UnexpectedError(_) => HttpResponse::new(self.status_code()),
+        }
+    }
+}
+
+/// Reject the request unless it carries the admin credentials
+pub fn require_admin(request: &HttpRequest, admin: &AdminSettings) -> Result<(), AdminError> {
+    let credentials = basic_authentication(request.headers()).map_err(AdminError::AuthError)?;
+    validate_admin_credentials(&credentials, admin).map_err(AdminError::AuthError)
+}
@@ -0,0 +1,168 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{require_admin, AdminError};
+use crate::configuration::AdminSettings;
+use crate::template::{Template, TemplateFormat};
+
+#[derive(serde::Serialize)]
+pub struct OnboardingEmail {
+    id: Uuid,
+    delay_days: i32,
+    subject: String,
+    html_body: String,
+    text_body: String,
+    created_at: DateTime<Utc>,
+}
+
+/// An email of the onboarding sequence, as written by admins.
+///
+/// The bodies are templates with the `name`, `email` and `unsubscribe_url` variables.
+#[derive(serde::Deserialize)]
+pub struct OnboardingEmailBody {
+    /// Days after confirmation, 0 for a welcome email sent straight away
+    delay_days: i32,
+    subject: String,
+    html_body: String,
+    text_body: String,
+}
+
+impl OnboardingEmailBody {
+    fn validate(&self) -> Result<(), AdminError> {
+        if self.delay_days < 0 {
+            return Err(AdminError::ValidationError(
+                "The delay cannot be negative.".into(),
+            ));
+        }
+        if self.subject.trim().is_empty() {
+            return Err(AdminError::ValidationError(
+                "The subject cannot be empty.".into(),
+            ));
+        }
+        for (part, body, format) in [
+            ("html body", &self.html_body, TemplateFormat::Html),
+            ("text body", &self.text_body, TemplateFormat::Text),
+        ] {
+            Template::parse(body, format).map_err(|e| {
+                AdminError::ValidationError(format!("The {} is not a valid template: {}", part, e))
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct OnboardingEmailPath {
+    id: Uuid,
+}
+
+/// The onboarding sequence, in the order subscribers receive it
+#[tracing::instrument(name = "List onboarding emails", skip(request, db_pool, admin))]
+pub async fn list_onboarding_emails(
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    admin: web::Data<AdminSettings>,
+) -> Result<HttpResponse, AdminError> {
+    require_admin(&request, &admin)?;
+    let emails = sqlx::query_as!(
+        OnboardingEmail,
+        r#"
+        Select id, delay_days, subject, html_body, text_body, created_at
+        From onboarding_emails
+        Order By delay_days, created_at
+        "#
+    )
+    .fetch_all(db_pool.get_ref())
+    .await
+    .context("Failed to fetch the onboarding emails.")?;
+    Ok(HttpResponse::Ok().json(emails))
+}
+
+#[tracing::instrument(name = "Add an onboarding email", skip(body, request, db_pool, admin))]
+pub async fn add_onboarding_email(
+    body: web::Json<OnboardingEmailBody>,
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    admin: web::Data<AdminSettings>,
+) -> Result<HttpResponse, AdminError> {
+    require_admin(&request, &admin)?;
+    body.validate()?;
+    let email = sqlx::query_as!(
+        OnboardingEmail,
+        r#"
+        Insert Into onboarding_emails (id, delay_days, subject, html_body, text_body, created_at)
+        Values ($1, $2, $3, $4, $5, $6)
+        Returning id, delay_days, subject, html_body, text_body, created_at
+        "#,
+        Uuid::new_v4(),
+        body.delay_days,
+        body.subject.trim(),
+        body.html_body,
+        body.text_body,
+        Utc::now()
+    )
+    .fetch_one(db_pool.get_ref())
+    .await
+    .context("Failed to store the onboarding email.")?;
+    Ok(HttpResponse::Created().json(email))
+}
+
+/// Edit an email of the sequence, including for subscribers who are already waiting for it
+#[tracing::instrument(
+    name = "Update an onboarding email",
+    skip(path, body, request, db_pool, admin)
+)]
+pub async fn update_onboarding_email(
+    path: web::Path<OnboardingEmailPath>,
+    body: web::Json<OnboardingEmailBody>,
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    admin: web::Data<AdminSettings>,
+) -> Result<HttpResponse, AdminError> {
+    require_admin(&request, &admin)?;
+    body.validate()?;
+    let email = sqlx::query_as!(
+        OnboardingEmail,
+        r#"
+        Update onboarding_emails
+        Set delay_days = $2, subject = $3, html_body = $4, text_body = $5
+        Where id = $1
+        Returning id, delay_days, subject, html_body, text_body, created_at
+        "#,
+        path.id,
+        body.delay_days,
+        body.subject.trim(),
+        body.html_body,
+        body.text_body
+    )
+    .fetch_optional(db_pool.get_ref())
+    .await
+    .context("Failed to update the onboarding email.")?
+    .ok_or(AdminError::NotFound)?;
+    Ok(HttpResponse::Ok().json(email))
+}
+
+/// Remove an email from the sequence, it will not be sent to anybody anymore
+#[tracing::instrument(
+    name = "Remove an onboarding email",
+    skip(path, request, db_pool, admin)
+)]
+pub async fn remove_onboarding_email(
+    path: web::Path<OnboardingEmailPath>,
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    admin: web::Data<AdminSettings>,
+) -> Result<HttpResponse, AdminError> {
+    require_admin(&request, &admin)?;
+    let result = sqlx::query!(r#"Delete From onboarding_emails Where id = $1"#, path.id)
+        .execute(db_pool.get_ref())
+        .await
+        .context("Failed to remove the onboarding email.")?;
+    if result.rows_affected() == 0 {
+        return Err(AdminError::NotFound);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
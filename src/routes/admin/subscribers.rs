@@ -0,0 +1,312 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use super::{require_admin, AdminError};
+use crate::configuration::AdminSettings;
+use crate::domain::{OptIn, SubscriberName, SubscriptionStatus};
+use crate::routes::{delete_subscriber, transition_status, StatusChangeError};
+use crate::subscription_events::{record_subscription_event, EventSource, SubscriptionEventKind};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(serde::Serialize)]
+pub struct Subscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: SubscriptionStatus,
+    subscribed_at: DateTime<Utc>,
+    /// How consent was collected
+    opt_in_mode: OptIn,
+    attributes: serde_json::Value,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberPage {
+    subscribers: Vec<Subscriber>,
+    /// Pass as `cursor` to fetch the next page, `None` on the last page
+    next_cursor: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct SubscriberFilters {
+    status: Option<SubscriptionStatus>,
+    subscribed_after: Option<DateTime<Utc>>,
+    subscribed_before: Option<DateTime<Utc>>,
+    /// Case-insensitive substring of the email or the name
+    q: Option<String>,
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+/// Where the previous page stopped, in `subscribed_at Desc, id Desc` order
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Cursor {
+    #[serde(rename = "t")]
+    subscribed_at: DateTime<Utc>,
+    #[serde(rename = "i")]
+    id: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).expect("Failed to serialize a cursor."))
+    }
+
+    fn decode(cursor: &str) -> Result<Self, String> {
+        URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or_else(|| "The cursor is malformed.".to_string())
+    }
+}
+
+/// Escape the `Like` wildcards in a search term, then match it anywhere
+pub(super) fn contains_pattern(term: &str) -> String {
+    let escaped = term
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+#[tracing::instrument(name = "List subscribers", skip(request, db_pool, admin))]
+pub async fn list_subscribers(
+    filters: web::Query<SubscriberFilters>,
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    admin: web::Data<AdminSettings>,
+) -> Result<HttpResponse, AdminError> {
+    require_admin(&request, &admin)?;
+    let limit = filters.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(AdminError::ValidationError(format!(
+            "The limit must be between 1 and {}.",
+            MAX_PAGE_SIZE
+        )));
+    }
+    let cursor = filters
+        .cursor
+        .as_deref()
+        .map(Cursor::decode)
+        .transpose()
+        .map_err(AdminError::ValidationError)?;
+    let pattern = filters
+        .q
+        .as_deref()
+        .map(str::trim)
+        .filter(|q| !q.is_empty())
+        .map(contains_pattern);
+
+    // Fetch one extra row to know whether there is a next page
+    let mut subscribers = sqlx::query_as!(
+        Subscriber,
+        r#"
+        Select
+            id,
+            email,
+            name,
+            status As "status: SubscriptionStatus",
+            subscribed_at,
+            opt_in_mode As "opt_in_mode: OptIn",
+            attributes
+        From subscriptions
+        Where ($1::subscription_status Is Null Or status = $1)
+            And ($2::timestamptz Is Null Or subscribed_at >= $2)
+            And ($3::timestamptz Is Null Or subscribed_at < $3)
+            And ($4::text Is Null Or email Ilike $4 Or name Ilike $4)
+            And ($5::timestamptz Is Null Or (subscribed_at, id) < ($5, $6))
+        Order By subscribed_at Desc, id Desc
+        Limit $7
+        "#,
+        filters.status as Option<SubscriptionStatus>,
+        filters.subscribed_after,
+        filters.subscribed_before,
+        pattern,
+        cursor.as_ref().map(|c| c.subscribed_at),
+        cursor.as_ref().map(|c| c.id),
+        limit + 1
+    )
+    .fetch_all(db_pool.get_ref())
+    .await
+    .context("Failed to fetch subscribers.")?;
+
+    let next_cursor = if subscribers.len() as i64 > limit {
+        subscribers.truncate(limit as usize);
+        subscribers.last().map(|last| {
+            Cursor {
+                subscribed_at: last.subscribed_at,
+                id: last.id,
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+    Ok(HttpResponse::Ok().json(SubscriberPage {
+        subscribers,
+        next_cursor,
+    }))
+}
+
+#[derive(serde::Deserialize)]
+pub struct SubscriberPath {
+    id: Uuid,
+}
+
+#[tracing::instrument(name = "Get a subscriber", skip(path, request, db_pool, admin))]
+pub async fn get_subscriber(
+    path: web::Path<SubscriberPath>,
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    admin: web::Data<AdminSettings>,
+) -> Result<HttpResponse, AdminError> {
+    require_admin(&request, &admin)?;
+    let subscriber = fetch_subscriber(db_pool.get_ref(), path.id)
+        .await
+        .context("Failed to fetch the subscriber.")?
+        .ok_or(AdminError::NotFound)?;
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+/// Manual corrections: fields left out are unchanged
+#[derive(serde::Deserialize)]
+pub struct SubscriberUpdate {
+    name: Option<String>,
+    status: Option<SubscriptionStatus>,
+}
+
+/// Correct a subscriber by hand.
+///
+/// Names are validated like on the subscribe form and status changes follow the
+/// subscription lifecycle, so that admins cannot get a subscriber into a state users cannot.
+#[tracing::instrument(
+    name = "Update a subscriber",
+    skip(path, body, request, db_pool, admin)
+)]
+pub async fn update_subscriber(
+    path: web::Path<SubscriberPath>,
+    body: web::Json<SubscriberUpdate>,
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    admin: web::Data<AdminSettings>,
+) -> Result<HttpResponse, AdminError> {
+    require_admin(&request, &admin)?;
+    let SubscriberUpdate { name, status } = body.0;
+    let name = name
+        .map(SubscriberName::parse)
+        .transpose()
+        .map_err(AdminError::ValidationError)?;
+
+    let mut txn = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    if let Some(status) = status {
+        match transition_status(&mut txn, path.id, status).await {
+            Ok(previous) => {
+                if let Some(kind) = event_kind(status).filter(|_| previous != status) {
+                    record_subscription_event(
+                        &mut txn,
+                        path.id,
+                        kind,
+                        &EventSource::from_request(&request),
+                    )
+                    .await
+                    .context("Failed to record the subscription event.")?;
+                }
+            }
+            Err(StatusChangeError::UnknownSubscriber) => return Err(AdminError::NotFound),
+            Err(StatusChangeError::IllegalTransition(e)) => {
+                return Err(AdminError::Conflict(e.to_string()))
+            }
+            Err(e) => return Err(anyhow::Error::new(e).into()),
+        }
+    }
+    if let Some(name) = name {
+        sqlx::query!(
+            r#"Update subscriptions Set name = $2 Where id = $1"#,
+            path.id,
+            name.as_ref()
+        )
+        .execute(&mut *txn)
+        .await
+        .context("Failed to update the subscriber name.")?;
+    }
+    let subscriber = fetch_subscriber(&mut *txn, path.id)
+        .await
+        .context("Failed to fetch the subscriber.")?
+        .ok_or(AdminError::NotFound)?;
+    txn.commit()
+        .await
+        .context("Failed to commit SQL transaction to update a subscriber.")?;
+
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+/// Remove a subscriber and their history, without preventing them from subscribing again.
+///
+/// Subscribers exercising their right to erasure go through `DELETE /subscriptions/data`.
+#[tracing::instrument(name = "Delete a subscriber", skip(path, request, db_pool, admin))]
+pub async fn remove_subscriber(
+    path: web::Path<SubscriberPath>,
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    admin: web::Data<AdminSettings>,
+) -> Result<HttpResponse, AdminError> {
+    require_admin(&request, &admin)?;
+    let mut txn = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    delete_subscriber(&mut txn, path.id)
+        .await
+        .context("Failed to delete the subscriber.")?
+        .ok_or(AdminError::NotFound)?;
+    txn.commit()
+        .await
+        .context("Failed to commit SQL transaction to delete a subscriber.")?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// The event recorded when an admin moves a subscriber into `status`
+fn event_kind(status: SubscriptionStatus) -> Option<SubscriptionEventKind> {
+    match status {
+        SubscriptionStatus::PendingConfirmation => None,
+        SubscriptionStatus::Confirmed => Some(SubscriptionEventKind::Confirmed),
+        SubscriptionStatus::Unsubscribed => Some(SubscriptionEventKind::Unsubscribed),
+        SubscriptionStatus::Bounced => Some(SubscriptionEventKind::Bounced),
+    }
+}
+
+async fn fetch_subscriber(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+) -> Result<Option<Subscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        Subscriber,
+        r#"
+        Select
+            id,
+            email,
+            name,
+            status As "status: SubscriptionStatus",
+            subscribed_at,
+            opt_in_mode As "opt_in_mode: OptIn",
+            attributes
+        From subscriptions
+        Where id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(executor)
+    .await
+}
@@ -0,0 +1,129 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use super::{require_admin, AdminError};
+use crate::configuration::AdminSettings;
+use crate::domain::SubscriberEmail;
+use crate::suppression::{suppress, SuppressionReason, SuppressionTarget};
+
+#[derive(serde::Serialize)]
+pub struct Suppression {
+    id: i64,
+    kind: String,
+    value: String,
+    reason: String,
+    note: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuppressionKind {
+    Address,
+    Domain,
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewSuppression {
+    kind: SuppressionKind,
+    value: String,
+    note: Option<String>,
+}
+
+impl TryFrom<NewSuppression> for SuppressionTarget {
+    type Error = String;
+
+    fn try_from(value: NewSuppression) -> Result<Self, Self::Error> {
+        match value.kind {
+            SuppressionKind::Address => {
+                let email = SubscriberEmail::parse(value.value.trim().to_string())?;
+                Ok(SuppressionTarget::address(&email))
+            }
+            SuppressionKind::Domain => {
+                let target = SuppressionTarget::domain(&value.value);
+                let domain = target.value();
+                if domain.is_empty() || domain.contains('@') || !domain.contains('.') {
+                    return Err(format!("{} is not a valid domain.", value.value));
+                }
+                Ok(target)
+            }
+        }
+    }
+}
+
+#[tracing::instrument(name = "List suppressions", skip(request, db_pool, admin))]
+pub async fn list_suppressions(
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    admin: web::Data<AdminSettings>,
+) -> Result<HttpResponse, AdminError> {
+    require_admin(&request, &admin)?;
+    let suppressions = sqlx::query_as!(
+        Suppression,
+        r#"
+        Select id, kind, value, reason, note, created_at
+        From suppressions
+        Order By created_at Desc, id Desc
+        "#
+    )
+    .fetch_all(db_pool.get_ref())
+    .await
+    .context("Failed to fetch the suppression list.")?;
+    Ok(HttpResponse::Ok().json(suppressions))
+}
+
+/// Manually suppress an address or a whole domain
+#[tracing::instrument(name = "Add a suppression", skip(body, request, db_pool, admin))]
+pub async fn add_suppression(
+    body: web::Json<NewSuppression>,
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    admin: web::Data<AdminSettings>,
+) -> Result<HttpResponse, AdminError> {
+    require_admin(&request, &admin)?;
+    let note = body.note.clone();
+    let target: SuppressionTarget = body.0.try_into().map_err(AdminError::ValidationError)?;
+
+    let mut txn = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    suppress(
+        &mut txn,
+        &target,
+        SuppressionReason::Manual,
+        note.as_deref(),
+    )
+    .await
+    .context("Failed to store the suppression.")?;
+    txn.commit()
+        .await
+        .context("Failed to commit SQL transaction to store a suppression.")?;
+
+    Ok(HttpResponse::Created().finish())
+}
+
+#[derive(serde::Deserialize)]
+pub struct SuppressionPath {
+    id: i64,
+}
+
+#[tracing::instrument(name = "Remove a suppression", skip(path, request, db_pool, admin))]
+pub async fn remove_suppression(
+    path: web::Path<SuppressionPath>,
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    admin: web::Data<AdminSettings>,
+) -> Result<HttpResponse, AdminError> {
+    require_admin(&request, &admin)?;
+    let result = sqlx::query!(r#"Delete From suppressions Where id = $1"#, path.id)
+        .execute(db_pool.get_ref())
+        .await
+        .context("Failed to remove the suppression.")?;
+    if result.rows_affected() == 0 {
+        return Err(AdminError::NotFound);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,72 @@
+mod subscriptions;
+
+pub use subscriptions::*;
+
+use actix_web::error::JsonPayloadError;
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+
+use crate::routes::{error_chain_fmt, FieldError, SubscribeError};
+
+/// Errors of the JSON API, rendered as `{"errors": [{"field": ..., "message": ...}]}`
+#[derive(thiserror::Error)]
+pub enum ApiError {
+    #[error(transparent)]
+    Subscribe(#[from] SubscribeError),
+    #[error("{0}")]
+    InvalidPayload(String),
+}
+
+impl std::fmt::Debug for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+#[derive(serde::Serialize)]
+struct ErrorsBody<'a> {
+    errors: &'a [FieldError],
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::Subscribe(e) => e.status_code(),
+            ApiError::InvalidPayload(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        let errors = match self {
+            ApiError::Subscribe(SubscribeError::ValidationError(errors)) => {
+                return response.json(ErrorsBody { errors });
+            }
+            ApiError::Subscribe(SubscribeError::RateLimited { retry_after }) => {
+                response.insert_header((RETRY_AFTER, retry_after.as_secs().to_string()));
+                vec![whole_request_error(self.to_string())]
+            }
+            // Do not leak the details of what went wrong
+            ApiError::Subscribe(SubscribeError::UnexpectedError(_)) => {
+                vec![whole_request_error(
+                    "Something went wrong, please try again later.",
+                )]
+            }
+            ApiError::InvalidPayload(message) => vec![whole_request_error(message.as_str())],
+        };
+        response.json(ErrorsBody { errors: &errors })
+    }
+}
+
+fn whole_request_error(message: impl Into<String>) -> FieldError {
+    FieldError {
+        field: None,
+        message: message.into(),
+    }
+}
+
+/// Report malformed JSON bodies in the API's error format
+pub fn json_error_handler(error: JsonPayloadError, _request: &HttpRequest) -> actix_web::Error {
+    ApiError::InvalidPayload(error.to_string()).into()
+}
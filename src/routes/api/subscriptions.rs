@@ -0,0 +1,63 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+
+use crate::captcha::CaptchaVerifier;
+use crate::email_client::EmailClient;
+use crate::email_templates::ConfirmationEmailTemplates;
+use crate::outbox::OutboxDispatcher;
+use crate::rate_limit::RateLimiter;
+use crate::routes::api::ApiError;
+use crate::routes::{register_subscriber, FormData};
+use crate::signup_policy::SignupPolicy;
+use crate::startup::ApplicationBaseUrl;
+use crate::suppression::EmailHasher;
+
+/// `subscribe` for single page applications: a JSON body in, structured errors out.
+///
+/// Responds with a 202 as the subscription is only effective once confirmed.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Adding a subscriber through the API",
+    skip(
+        body,
+        request,
+        db_pool,
+        email_client,
+        email_hasher,
+        outbox_dispatcher,
+        base_url,
+        confirmation_email_templates,
+        signup_policy,
+        rate_limiter,
+        captcha_verifier
+    )
+)]
+pub async fn api_subscribe(
+    body: web::Json<FormData>,
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    email_hasher: web::Data<EmailHasher>,
+    outbox_dispatcher: web::Data<OutboxDispatcher>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    confirmation_email_templates: web::Data<ConfirmationEmailTemplates>,
+    signup_policy: web::Data<SignupPolicy>,
+    rate_limiter: web::Data<RateLimiter>,
+    captcha_verifier: web::Data<Option<Box<dyn CaptchaVerifier>>>,
+) -> Result<HttpResponse, ApiError> {
+    register_subscriber(
+        body.0,
+        &request,
+        &db_pool,
+        &email_client,
+        &email_hasher,
+        &outbox_dispatcher,
+        &base_url,
+        &confirmation_email_templates,
+        &signup_policy,
+        &rate_limiter,
+        captcha_verifier.as_ref().as_deref(),
+    )
+    .await?;
+    Ok(HttpResponse::Accepted().finish())
+}
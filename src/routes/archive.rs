@@ -0,0 +1,332 @@
+use actix_web::http::header::{ContentType, CONTENT_SECURITY_POLICY};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::configuration::NewsletterSettings;
+use crate::routes::error_chain_fmt;
+use crate::startup::ApplicationBaseUrl;
+use crate::template::{escape_html, Template, TemplateContext, TemplateFormat};
+
+/// Number of issues per archive page and in each feed
+const PAGE_SIZE: i64 = 20;
+
+/// Styles and images as the email layout uses them, nothing that runs or submits.
+/// `sandbox` also gives the page a unique origin, away from our cookies and APIs.
+const ISSUE_CONTENT_SECURITY_POLICY: &str = "default-src 'none'; img-src * data:; \
+    style-src 'unsafe-inline'; font-src *; base-uri 'none'; form-action 'none'; \
+    frame-ancestors 'none'; sandbox allow-popups allow-popups-to-escape-sandbox";
+
+#[derive(serde::Deserialize)]
+pub struct ArchiveParameters {
+    page: Option<i64>,
+}
+
+#[derive(thiserror::Error)]
+pub enum ArchiveError {
+    #[error("No newsletter issue was found.")]
+    NotFound,
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ArchiveError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ArchiveError::NotFound => StatusCode::NOT_FOUND,
+            ArchiveError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ArchiveError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+struct PublishedIssue {
+    newsletter_issue_id: Uuid,
+    slug: String,
+    title: String,
+    html_content: String,
+    published_at: DateTime<Utc>,
+}
+
+impl PublishedIssue {
+    /// Render the issue as it is shown on the web, where no subscriber is known
+    fn web_html(&self) -> Result<String, anyhow::Error> {
+        let template = Template::parse(&self.html_content, TemplateFormat::Html)
+            .context("A stored newsletter issue is not a valid template.")?;
+        Ok(template.render(&TemplateContext::new()))
+    }
+}
+
+#[tracing::instrument(
+    name = "List archived newsletter issues",
+    skip(parameters, db_pool, newsletter)
+)]
+pub async fn archive(
+    parameters: web::Query<ArchiveParameters>,
+    db_pool: web::Data<PgPool>,
+    newsletter: web::Data<NewsletterSettings>,
+) -> Result<HttpResponse, ArchiveError> {
+    let page = parameters.page.unwrap_or(1);
+    if page < 1 {
+        return Err(ArchiveError::ValidationError(
+            "The page number must be at least 1.".into(),
+        ));
+    }
+    let offset = (page - 1)
+        .checked_mul(PAGE_SIZE)
+        .ok_or_else(|| ArchiveError::ValidationError("The page number is too large.".into()))?;
+
+    // Fetch one extra row to know whether there is a next page
+    let mut issues = get_published_issues(&db_pool, PAGE_SIZE + 1, offset)
+        .await
+        .context("Failed to fetch published newsletter issues.")?;
+    let has_next_page = issues.len() as i64 > PAGE_SIZE;
+    issues.truncate(PAGE_SIZE as usize);
+
+    let mut items = String::new();
+    for issue in &issues {
+        items.push_str(&format!(
+            "<li><a href=\"/archive/{}\">{}</a> <time datetime=\"{}\">{}</time></li>\n",
+            escape_html(&issue.slug),
+            escape_html(&issue.title),
+            issue.published_at.to_rfc3339(),
+            issue.published_at.format("%B %-d, %Y"),
+        ));
+    }
+    if issues.is_empty() {
+        items.push_str("<li>No issues have been published yet.</li>\n");
+    }
+
+    let mut pagination = String::new();
+    if page > 1 {
+        pagination.push_str(&format!(
+            "<a href=\"/archive?page={}\" rel=\"prev\">Newer issues</a>\n",
+            page - 1
+        ));
+    }
+    if has_next_page {
+        pagination.push_str(&format!(
+            "<a href=\"/archive?page={}\" rel=\"next\">Older issues</a>\n",
+            page + 1
+        ));
+    }
+
+    let title = escape_html(&newsletter.title);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta http-equiv="content-type" content="text/html; charset=utf-8">
+<title>{title} archive</title>
+<link rel="alternate" type="application/atom+xml" href="/feed.atom">
+<link rel="alternate" type="application/rss+xml" href="/feed.rss">
+</head>
+<body>
+<h1>{title} archive</h1>
+<ul>
+{items}</ul>
+<nav>
+{pagination}</nav>
+</body>
+</html>"#,
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct IssuePath {
+    slug: String,
+}
+
+#[tracing::instrument(name = "Show an archived newsletter issue", skip(path, db_pool))]
+pub async fn archived_issue(
+    path: web::Path<IssuePath>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ArchiveError> {
+    let issue = get_published_issue(&db_pool, &path.slug)
+        .await
+        .context("Failed to fetch the newsletter issue.")?
+        .ok_or(ArchiveError::NotFound)?;
+    let html = issue.web_html()?;
+
+    // Issues authored in Markdown are already a complete document thanks to the layout
+    let is_document = {
+        let start = html.trim_start().to_ascii_lowercase();
+        start.starts_with("<!doctype") || start.starts_with("<html")
+    };
+    let body = if is_document {
+        html
+    } else {
+        format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta http-equiv="content-type" content="text/html; charset=utf-8">
+<title>{}</title>
+</head>
+<body>
+{}
+</body>
+</html>"#,
+            escape_html(&issue.title),
+            html
+        )
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        // Issues are authored HTML served from our own origin: no scripts, forms or plugins
+        .insert_header((CONTENT_SECURITY_POLICY, ISSUE_CONTENT_SECURITY_POLICY))
+        .body(body))
+}
+
+#[tracing::instrument(name = "Render the Atom feed", skip(db_pool, base_url, newsletter))]
+pub async fn atom_feed(
+    db_pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    newsletter: web::Data<NewsletterSettings>,
+) -> Result<HttpResponse, ArchiveError> {
+    let issues = get_published_issues(&db_pool, PAGE_SIZE, 0)
+        .await
+        .context("Failed to fetch published newsletter issues.")?;
+    let base_url = &base_url.0;
+    let updated = issues
+        .first()
+        .map(|i| i.published_at)
+        .unwrap_or_else(|| DateTime::<Utc>::from(std::time::UNIX_EPOCH));
+
+    let mut entries = String::new();
+    for issue in &issues {
+        entries.push_str(&format!(
+            r#"<entry>
+<id>urn:uuid:{}</id>
+<title>{}</title>
+<link rel="alternate" type="text/html" href="{}"/>
+<updated>{}</updated>
+<content type="html">{}</content>
+</entry>
+"#,
+            issue.newsletter_issue_id,
+            escape_html(&issue.title),
+            escape_html(&format!("{}/archive/{}", base_url, issue.slug)),
+            issue.published_at.to_rfc3339(),
+            escape_html(&issue.web_html()?),
+        ));
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/atom+xml; charset=utf-8")
+        .body(format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+<id>{archive_url}</id>
+<title>{title}</title>
+<link rel="self" type="application/atom+xml" href="{feed_url}"/>
+<link rel="alternate" type="text/html" href="{archive_url}"/>
+<updated>{updated}</updated>
+{entries}</feed>"#,
+            archive_url = escape_html(&format!("{}/archive", base_url)),
+            feed_url = escape_html(&format!("{}/feed.atom", base_url)),
+            title = escape_html(&newsletter.title),
+            updated = updated.to_rfc3339(),
+        )))
+}
+
+#[tracing::instrument(name = "Render the RSS feed", skip(db_pool, base_url, newsletter))]
+pub async fn rss_feed(
+    db_pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    newsletter: web::Data<NewsletterSettings>,
+) -> Result<HttpResponse, ArchiveError> {
+    let issues = get_published_issues(&db_pool, PAGE_SIZE, 0)
+        .await
+        .context("Failed to fetch published newsletter issues.")?;
+    let base_url = &base_url.0;
+
+    let mut items = String::new();
+    for issue in &issues {
+        let link = escape_html(&format!("{}/archive/{}", base_url, issue.slug));
+        items.push_str(&format!(
+            r#"<item>
+<title>{}</title>
+<link>{}</link>
+<guid isPermaLink="true">{}</guid>
+<pubDate>{}</pubDate>
+<description>{}</description>
+</item>
+"#,
+            escape_html(&issue.title),
+            link,
+            link,
+            issue.published_at.to_rfc2822(),
+            escape_html(&issue.web_html()?),
+        ));
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/rss+xml; charset=utf-8")
+        .body(format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<rss version="2.0">
+<channel>
+<title>{title}</title>
+<link>{archive_url}</link>
+<description>{title}</description>
+{items}</channel>
+</rss>"#,
+            archive_url = escape_html(&format!("{}/archive", base_url)),
+            title = escape_html(&newsletter.title),
+        )))
+}
+
+#[tracing::instrument(name = "Get published newsletter issues", skip(db_pool))]
+async fn get_published_issues(
+    db_pool: &PgPool,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<PublishedIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        PublishedIssue,
+        r#"
+        Select newsletter_issue_id, slug, title, html_content, published_at
+        From newsletter_issues
+        Order By published_at Desc, newsletter_issue_id
+        Limit $1
+        Offset $2
+        "#,
+        limit,
+        offset
+    )
+    .fetch_all(db_pool)
+    .await
+}
+
+#[tracing::instrument(name = "Get a published newsletter issue", skip(db_pool))]
+async fn get_published_issue(
+    db_pool: &PgPool,
+    slug: &str,
+) -> Result<Option<PublishedIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        PublishedIssue,
+        r#"
+        Select newsletter_issue_id, slug, title, html_content, published_at
+        From newsletter_issues
+        Where slug = $1
+        "#,
+        slug
+    )
+    .fetch_optional(db_pool)
+    .await
+}
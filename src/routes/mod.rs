@@ -1,9 +1,27 @@
+pub mod admin;
+pub mod api;
+pub mod archive;
 pub mod health_check;
 pub mod newsletters;
+pub mod readiness;
 pub mod subscriptions;
+pub mod subscriptions_challenge;
 pub mod subscriptions_confirm;
+pub mod subscriptions_data;
+pub mod subscriptions_unsubscribe;
+pub mod tracking;
+pub mod webhooks;
 
+pub use admin::*;
+pub use api::*;
+pub use archive::*;
 pub use health_check::*;
 pub use newsletters::*;
+pub use readiness::*;
 pub use subscriptions::*;
+pub use subscriptions_challenge::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_data::*;
+pub use subscriptions_unsubscribe::*;
+pub use tracking::*;
+pub use webhooks::*;
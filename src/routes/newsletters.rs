@@ -1,16 +1,27 @@
-use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
+use crate::configuration::TrackingSettings;
+use crate::markdown::{render_markdown, EmailLayout};
+use crate::newsletter_delivery::enqueue_deliveries;
 use crate::routes::error_chain_fmt;
+use crate::template::{Template, TemplateError, TemplateFormat};
 use actix_web::http::StatusCode;
-use actix_web::HttpResponse;
 use actix_web::{web, ResponseError};
+use actix_web::{HttpRequest, HttpResponse};
 use anyhow::Context;
-use sqlx::PgPool;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
 
+/// Longer keys are rejected rather than stored
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 100;
+
+/// A newsletter issue, authored either as explicit `content` or as `markdown`
 #[derive(serde::Deserialize)]
 pub struct BodyData {
     title: String,
-    content: Content,
+    content: Option<Content>,
+    markdown: Option<String>,
+    #[serde(default)]
+    tracking: TrackingOptions,
 }
 
 #[derive(serde::Deserialize)]
@@ -19,10 +30,26 @@ pub struct Content {
     text: String,
 }
 
+/// Engagement tracking requested for a single issue, subject to the global switch
+#[derive(serde::Deserialize, Default, Clone, Copy)]
+pub struct TrackingOptions {
+    #[serde(default)]
+    opens: bool,
+    #[serde(default)]
+    clicks: bool,
+}
+
 #[derive(thiserror::Error)]
 pub enum PublishError {
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The newsletter {part} is not a valid template: {source}")]
+    InvalidTemplate {
+        part: &'static str,
+        source: TemplateError,
+    },
 }
 
 impl std::fmt::Debug for PublishError {
@@ -34,66 +61,167 @@ impl std::fmt::Debug for PublishError {
 impl ResponseError for PublishError {
     fn status_code(&self) -> StatusCode {
         match self {
+            PublishError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            PublishError::InvalidTemplate { .. } => StatusCode::BAD_REQUEST,
             PublishError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
 
+/// Store the issue and queue it for every confirmed subscriber, the delivery worker sends it.
+///
+/// A retry carrying the same `Idempotency-Key` header reuses the issue the first attempt created,
+/// and only queues it for the subscribers it has not reached yet.
 pub async fn publish_newsletter(
+    request: HttpRequest,
     body: web::Json<BodyData>,
     db_pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    newsletter_layout: web::Data<EmailLayout>,
+    tracking_settings: web::Data<TrackingSettings>,
 ) -> Result<HttpResponse, PublishError> {
-    let subscribers = get_currently_confirmed_subscribers(&db_pool).await?;
-
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await
-                    .with_context(|| {
-                        format!("Failed to send newsletter issue to {}", subscriber.email)
-                    })?;
+    let idempotency_key = match request.headers().get("Idempotency-Key") {
+        Some(value) => match value.to_str() {
+            Ok(key) if !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LENGTH => Some(key),
+            _ => {
+                return Err(PublishError::ValidationError(format!(
+                "The `Idempotency-Key` header must be between 1 and {} visible ASCII characters.",
+                MAX_IDEMPOTENCY_KEY_LENGTH
+            )))
             }
-            Err(error) => {
-                tracing::warn!(error.cause_chain = ?error, "Skipping a confirmed subscriber. \
-                Their stored contact details are invalid");
+        },
+        None => None,
+    };
+    let tracking = if tracking_settings.enabled {
+        body.tracking
+    } else {
+        TrackingOptions::default()
+    };
+    let content = match (body.0.content, body.0.markdown) {
+        (Some(content), None) => content,
+        (None, Some(markdown)) => {
+            let rendered = render_markdown(&markdown, &newsletter_layout);
+            Content {
+                html: rendered.html,
+                text: rendered.text,
             }
         }
-    }
+        (Some(_), Some(_)) => {
+            return Err(PublishError::ValidationError(
+                "Provide either `content` or `markdown`, not both.".into(),
+            ))
+        }
+        (None, None) => {
+            return Err(PublishError::ValidationError(
+                "The newsletter is missing its `content` or `markdown`.".into(),
+            ))
+        }
+    };
+    let title = body.0.title;
 
-    Ok(HttpResponse::Ok().finish())
-}
+    // Validate both templates before the issue is queued for anyone
+    Template::parse(&content.html, TemplateFormat::Html).map_err(|source| {
+        PublishError::InvalidTemplate {
+            part: "html content",
+            source,
+        }
+    })?;
+    Template::parse(&content.text, TemplateFormat::Text).map_err(|source| {
+        PublishError::InvalidTemplate {
+            part: "text content",
+            source,
+        }
+    })?;
+
+    let mut txn = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let newsletter_issue_id =
+        insert_newsletter_issue(&mut txn, &title, &content, tracking, idempotency_key)
+            .await
+            .context("Failed to store the newsletter issue.")?;
+    enqueue_deliveries(&mut txn, newsletter_issue_id)
+        .await
+        .context("Failed to queue the newsletter issue for delivery.")?;
+    txn.commit()
+        .await
+        .context("Failed to commit SQL transaction to publish a newsletter issue.")?;
 
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
+    Ok(HttpResponse::Accepted().finish())
 }
 
-#[tracing::instrument(name = "Get currently confirmed subscribers", skip(db_pool))]
-async fn get_currently_confirmed_subscribers(
-    db_pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let confirmed_subscribers = sqlx::query!(
+/// Store the issue for the public archive, returning its id.
+///
+/// When an issue was already stored under `idempotency_key`, its id is returned instead.
+#[tracing::instrument(name = "Save newsletter issue", skip(txn, content, tracking))]
+async fn insert_newsletter_issue(
+    txn: &mut Transaction<'_, Postgres>,
+    title: &str,
+    content: &Content,
+    tracking: TrackingOptions,
+    idempotency_key: Option<&str>,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    // The id suffix keeps slugs unique even when titles are reused
+    let slug = format!(
+        "{}-{}",
+        slugify(title),
+        &newsletter_issue_id.simple().to_string()[..8]
+    );
+    // Waits for a concurrent retry with the same key to commit, rather than racing it
+    let inserted = sqlx::query!(
         r#"
-        Select email
-        From subscriptions
-        Where status = 'confirmed'
+        Insert Into newsletter_issues (
+            newsletter_issue_id,
+            slug,
+            title,
+            html_content,
+            text_content,
+            published_at,
+            track_opens,
+            track_clicks,
+            idempotency_key
+        )
+        Values ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        On Conflict (idempotency_key) Do Nothing
         "#,
+        newsletter_issue_id,
+        slug,
+        title,
+        content.html,
+        content.text,
+        Utc::now(),
+        tracking.opens,
+        tracking.clicks,
+        idempotency_key
+    )
+    .execute(&mut **txn)
+    .await?;
+    if inserted.rows_affected() == 1 {
+        return Ok(newsletter_issue_id);
+    }
+    let existing = sqlx::query!(
+        r#"Select newsletter_issue_id From newsletter_issues Where idempotency_key = $1"#,
+        idempotency_key
     )
-    .fetch_all(db_pool)
-    .await?
-    .into_iter()
-    .map(|r| match SubscriberEmail::parse(r.email) {
-        Ok(email) => Ok(ConfirmedSubscriber { email }),
-        Err(error) => Err(anyhow::anyhow!(error)),
-    })
-    .collect();
+    .fetch_one(&mut **txn)
+    .await?;
+    Ok(existing.newsletter_issue_id)
+}
 
-    Ok(confirmed_subscribers)
+/// Turn a title into a lowercase, dash-separated URL fragment
+fn slugify(title: &str) -> String {
+    let mut slug = String::new();
+    for c in title.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug: String = slug.chars().take(60).collect();
+    match slug.trim_end_matches('-') {
+        "" => "issue".into(),
+        slug => slug.into(),
+    }
 }
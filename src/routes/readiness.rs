@@ -0,0 +1,154 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::configuration::ReadinessSettings;
+use crate::email_client::EmailClient;
+use crate::startup::MIGRATOR;
+
+#[derive(serde::Serialize)]
+pub struct ReadinessResponse {
+    is_ready: bool,
+    checks: DependencyChecks,
+}
+
+#[derive(serde::Serialize)]
+pub struct DependencyChecks {
+    database: DependencyCheck,
+    migrations: DependencyCheck,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email_provider: Option<DependencyCheck>,
+}
+
+/// What the load balancer, and anybody else, gets to see: the reasons for a failure are
+/// logged instead, they can tell a lot about our infrastructure.
+#[derive(serde::Serialize)]
+pub struct DependencyCheck {
+    is_healthy: bool,
+    /// Whether the instance is not ready while this dependency is down
+    #[serde(skip)]
+    critical: bool,
+    latency_ms: u64,
+}
+
+impl DependencyChecks {
+    fn all_critical_healthy(&self) -> bool {
+        [&self.database, &self.migrations]
+            .into_iter()
+            .chain(&self.email_provider)
+            .all(|check| check.is_healthy || !check.critical)
+    }
+}
+
+/// Whether this instance can serve traffic, for the load balancer.
+///
+/// Unlike `/health-check`, which only says the process is alive, this checks the dependencies
+/// and responds with a 503 when a critical one is down.
+#[tracing::instrument(name = "Check readiness", skip(db_pool, email_client, settings))]
+pub async fn readiness(
+    db_pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    settings: web::Data<ReadinessSettings>,
+) -> HttpResponse {
+    let timeout = settings.timeout();
+    let email_provider = async {
+        if settings.probe_email_provider {
+            let probe = async {
+                email_client
+                    .probe()
+                    .await
+                    .context("The email provider did not accept our token.")
+            };
+            Some(check("email_provider", false, timeout, probe).await)
+        } else {
+            None
+        }
+    };
+    let (database, migrations, email_provider) = tokio::join!(
+        check("database", true, timeout, ping_database(&db_pool)),
+        check("migrations", true, timeout, check_migrations(&db_pool)),
+        email_provider,
+    );
+    let checks = DependencyChecks {
+        database,
+        migrations,
+        email_provider,
+    };
+
+    let is_ready = checks.all_critical_healthy();
+    if !is_ready {
+        tracing::warn!("Not ready to serve traffic");
+    }
+    let mut response = if is_ready {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::ServiceUnavailable()
+    };
+    response.json(ReadinessResponse { is_ready, checks })
+}
+
+async fn check(
+    dependency: &'static str,
+    critical: bool,
+    timeout: Duration,
+    probe: impl Future<Output = Result<(), anyhow::Error>>,
+) -> DependencyCheck {
+    let start = Instant::now();
+    let outcome = match tokio::time::timeout(timeout, probe).await {
+        Ok(outcome) => outcome,
+        Err(_) => Err(anyhow::anyhow!(
+            "No answer within {} ms.",
+            timeout.as_millis()
+        )),
+    };
+    let latency_ms = start.elapsed().as_millis() as u64;
+    if let Err(e) = &outcome {
+        tracing::warn!(
+            dependency,
+            critical,
+            latency_ms,
+            error.cause_chain = ?e,
+            "A dependency is unhealthy"
+        );
+    }
+    DependencyCheck {
+        is_healthy: outcome.is_ok(),
+        critical,
+        latency_ms,
+    }
+}
+
+async fn ping_database(db_pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query!("Select 1 As ping")
+        .fetch_one(db_pool)
+        .await
+        .context("Failed to reach Postgres.")?;
+    Ok(())
+}
+
+/// Every migration this build ships with must have been applied, unchanged
+async fn check_migrations(db_pool: &PgPool) -> Result<(), anyhow::Error> {
+    // sqlx's own bookkeeping table, not part of our schema, so not checked at compile time
+    let applied: Vec<(i64, Vec<u8>, bool)> =
+        sqlx::query_as(r#"Select version, checksum, success From _sqlx_migrations"#)
+            .fetch_all(db_pool)
+            .await
+            .context("Failed to fetch the applied migrations.")?;
+    let pending: Vec<String> = MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .filter(|migration| {
+            !applied.iter().any(|(version, checksum, success)| {
+                *version == migration.version && *success && **checksum == *migration.checksum
+            })
+        })
+        .map(|migration| migration.version.to_string())
+        .collect();
+    if !pending.is_empty() {
+        anyhow::bail!("Migrations not applied: {}.", pending.join(", "));
+    }
+    Ok(())
+}
@@ -1,4 +1,5 @@
-use actix_web::{web, HttpResponse, ResponseError};
+use actix_web::http::header::{ContentType, ACCEPT_LANGUAGE, RETRY_AFTER};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
 use chrono::Utc;
 use rand::distributions::Alphanumeric;
@@ -7,23 +8,69 @@ use reqwest::StatusCode;
 use sqlx::{Executor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
-use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::captcha::CaptchaVerifier;
+use crate::client_ip::client_ip;
+use crate::configuration::OptInMode;
+use crate::domain::{NewSubscriber, OptIn, SubscriberEmail, SubscriberName, SubscriptionStatus};
 use crate::email_client::EmailClient;
+use crate::email_templates::{
+    ConfirmationEmailTemplate, ConfirmationEmailTemplates, RenderedEmail,
+};
+use crate::onboarding::schedule_onboarding;
+use crate::outbox::{enqueue_email, OutboxDispatcher, OutboxEmailKind};
+use crate::rate_limit::RateLimiter;
+use crate::signup_policy::SignupPolicy;
 use crate::startup::ApplicationBaseUrl;
+use crate::subscription_events::{record_subscription_event, EventSource, SubscriptionEventKind};
+use crate::suppression::{is_suppressed, EmailHasher};
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
     email: String,
     name: String,
+    /// Preferred language for the confirmation email, overriding `Accept-Language`
+    locale: Option<String>,
+    /// Honeypot: the form hides this field from humans, so only bots fill it in
+    #[serde(default)]
+    website: String,
+    /// Required when a CAPTCHA verifier is configured
+    captcha_token: Option<String>,
 }
 
 impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+    type Error = Vec<FieldError>;
 
+    /// Reports every invalid field, not just the first one
     fn try_from(value: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(value.name)?;
-        let email = SubscriberEmail::parse(value.email)?;
-        Ok(NewSubscriber { email, name })
+        match (
+            SubscriberName::parse(value.name),
+            SubscriberEmail::parse(value.email),
+        ) {
+            (Ok(name), Ok(email)) => Ok(NewSubscriber { email, name }),
+            (name, email) => Err(name
+                .err()
+                .map(|message| FieldError::new("name", message))
+                .into_iter()
+                .chain(email.err().map(|message| FieldError::new("email", message)))
+                .collect()),
+        }
+    }
+}
+
+/// Why a submitted field was refused
+#[derive(Debug, serde::Serialize)]
+pub struct FieldError {
+    /// `None` for problems with the request as a whole
+    pub field: Option<&'static str>,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            field: Some(field),
+            message: message.into(),
+        }
     }
 }
 
@@ -31,8 +78,16 @@ impl TryFrom<FormData> for NewSubscriber {
 pub enum SubscribeError {
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
-    #[error("{0}")]
-    ValidationError(String),
+    #[error("{}", .0.iter().map(|e| e.message.as_str()).collect::<Vec<_>>().join(" "))]
+    ValidationError(Vec<FieldError>),
+    #[error("Too many signup attempts, please try again later.")]
+    RateLimited { retry_after: std::time::Duration },
+}
+
+impl SubscribeError {
+    fn invalid(field: &'static str, message: impl Into<String>) -> Self {
+        SubscribeError::ValidationError(vec![FieldError::new(field, message)])
+    }
 }
 
 impl std::fmt::Debug for SubscribeError {
@@ -45,55 +100,250 @@ impl ResponseError for SubscribeError {
     fn status_code(&self) -> StatusCode {
         match self {
             SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
             SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let SubscribeError::RateLimited { retry_after } = self {
+            response.insert_header((RETRY_AFTER, retry_after.as_secs().to_string()));
+        }
+        response
+            .insert_header(ContentType::plaintext())
+            .body(self.to_string())
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(
     name = "Adding a subscriber",
-    skip(form, db_pool, email_client, base_url),
-    fields(
-        subscriber_email = %form.email,
-        subscriber_name = %form.name
+    skip(
+        form,
+        request,
+        db_pool,
+        email_client,
+        email_hasher,
+        outbox_dispatcher,
+        base_url,
+        confirmation_email_templates,
+        signup_policy,
+        rate_limiter,
+        captcha_verifier
     )
 )]
 pub async fn subscribe(
     form: web::Form<FormData>,
+    request: HttpRequest,
     db_pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
+    email_hasher: web::Data<EmailHasher>,
+    outbox_dispatcher: web::Data<OutboxDispatcher>,
     base_url: web::Data<ApplicationBaseUrl>,
+    confirmation_email_templates: web::Data<ConfirmationEmailTemplates>,
+    signup_policy: web::Data<SignupPolicy>,
+    rate_limiter: web::Data<RateLimiter>,
+    captcha_verifier: web::Data<Option<Box<dyn CaptchaVerifier>>>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    register_subscriber(
+        form.0,
+        &request,
+        &db_pool,
+        &email_client,
+        &email_hasher,
+        &outbox_dispatcher,
+        &base_url,
+        &confirmation_email_templates,
+        &signup_policy,
+        &rate_limiter,
+        captcha_verifier.as_ref().as_deref(),
+    )
+    .await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// The signup flow shared by the form and the JSON API.
+///
+/// Known and suppressed addresses succeed quietly, so that nobody can probe our list.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Register a subscriber",
+    skip(
+        form,
+        request,
+        db_pool,
+        email_client,
+        email_hasher,
+        outbox_dispatcher,
+        base_url,
+        confirmation_email_templates,
+        signup_policy,
+        rate_limiter,
+        captcha_verifier
+    ),
+    fields(
+        subscriber_email = %form.email,
+        subscriber_name = %form.name
+    )
+)]
+pub async fn register_subscriber(
+    form: FormData,
+    request: &HttpRequest,
+    db_pool: &PgPool,
+    email_client: &EmailClient,
+    email_hasher: &EmailHasher,
+    outbox_dispatcher: &OutboxDispatcher,
+    base_url: &ApplicationBaseUrl,
+    confirmation_email_templates: &ConfirmationEmailTemplates,
+    signup_policy: &SignupPolicy,
+    rate_limiter: &RateLimiter,
+    captcha_verifier: Option<&dyn CaptchaVerifier>,
+) -> Result<(), SubscribeError> {
+    if !form.website.is_empty() {
+        tracing::info!("Dropping a signup that filled in the honeypot field");
+        return Ok(());
+    }
+    // Only our load balancers are believed about the client IP, anybody can send the header
+    let ip_address = client_ip(request).map(|ip| ip.to_string());
+    match &ip_address {
+        Some(ip_address) => {
+            if let Some(retry_after) = rate_limiter
+                .check_ip(ip_address)
+                .await
+                .context("Failed to check the per-IP rate limit.")?
+            {
+                return Err(SubscribeError::RateLimited { retry_after });
+            }
+        }
+        // Sharing a single bucket would let one client lock everybody else out
+        None => tracing::warn!("Not applying the per-IP rate limit to a signup without an IP"),
+    }
+    if let Some(verifier) = captcha_verifier {
+        let token = form
+            .captcha_token
+            .as_deref()
+            .filter(|token| !token.is_empty())
+            .ok_or_else(|| {
+                SubscribeError::invalid("captcha_token", "Please complete the CAPTCHA.")
+            })?;
+        if !verifier
+            .verify(token, ip_address.as_deref())
+            .await
+            .context("Failed to verify the CAPTCHA token.")?
+        {
+            return Err(SubscribeError::invalid(
+                "captcha_token",
+                "The CAPTCHA could not be verified, please try again.",
+            ));
+        }
+    }
+
+    let locale = confirmation_email_templates.select_locale(
+        form.locale.as_deref(),
+        request
+            .headers()
+            .get(ACCEPT_LANGUAGE)
+            .and_then(|h| h.to_str().ok()),
+    );
+    let template = confirmation_email_templates.select(Some(locale), None);
+    let new_subscriber: NewSubscriber = form.try_into().map_err(SubscribeError::ValidationError)?;
+    signup_policy
+        .check(&new_subscriber.email)
+        .map_err(|message| SubscribeError::invalid("email", message))?;
+    if let Some(retry_after) = rate_limiter
+        .check_email(&new_subscriber.email)
+        .await
+        .context("Failed to check the per-email rate limit.")?
+    {
+        return Err(SubscribeError::RateLimited { retry_after });
+    }
+    if !email_client.can_deliver_to(&new_subscriber.email) {
+        return Err(SubscribeError::invalid(
+            "email",
+            format!(
+                "We cannot send emails to {} yet, please use an address written in ASCII characters.",
+                new_subscriber.email
+            ),
+        ));
+    }
+    // Don't reveal to whoever fills the form that we know about the address
+    if is_suppressed(db_pool, email_hasher, &new_subscriber.email)
+        .await
+        .context("Failed to check whether the email address was suppressed.")?
+    {
+        return Ok(());
+    }
+    let opt_in = signup_policy.opt_in();
     // Create a mutable transaction
     let mut txn = db_pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
     // Check to ensure that the subscriber insert didn't error
-    let subscriber_id = insert_subscriber(&mut txn, &new_subscriber)
+    let Some(subscriber_id) = insert_subscriber(&mut txn, &new_subscriber, opt_in, locale)
         .await
-        .context("Failed to insert new subscriber in the database.")?;
+        .context("Failed to insert new subscriber in the database.")?
+    else {
+        // Already subscribed, possibly with a differently written address
+        return Ok(());
+    };
+    // Single opt-in subscribers never confirm, but their unsubscribe links need a token
     let subscription_token = generate_subscription_token();
     store_token(&mut txn, subscriber_id, &subscription_token)
         .await
         .context("Failed to store the confirmation token for a new subscriber.")?;
+    let event_source = EventSource::from_request(request);
+    record_subscription_event(
+        &mut txn,
+        subscriber_id,
+        SubscriptionEventKind::Subscribed,
+        &event_source,
+    )
+    .await
+    .context("Failed to record the subscription event.")?;
+    let outbox_email_id = match opt_in {
+        OptInMode::Single => {
+            record_subscription_event(
+                &mut txn,
+                subscriber_id,
+                SubscriptionEventKind::Confirmed,
+                &event_source,
+            )
+            .await
+            .context("Failed to record the confirmation event.")?;
+            schedule_onboarding(&mut txn, subscriber_id)
+                .await
+                .context("Failed to schedule the onboarding sequence.")?;
+            None
+        }
+        // Written with the subscriber, so that it goes out even if sending it now fails
+        OptInMode::Double => Some(
+            enqueue_confirmation_email(
+                &mut txn,
+                subscriber_id,
+                &new_subscriber,
+                &base_url.0,
+                &subscription_token,
+                template,
+            )
+            .await
+            .context("Failed to write the confirmation email to the outbox.")?,
+        ),
+    };
     txn.commit()
         .await
         .context("Failed to commit SQL transaction to store a new subscriber")?;
+    if let Some(outbox_email_id) = outbox_email_id {
+        outbox_dispatcher.try_deliver(outbox_email_id).await;
+    }
 
-    send_confirmation_email(
-        &email_client,
-        new_subscriber,
-        &base_url.0,
-        &subscription_token,
-    )
-    .await
-    .context("Failed to send a confirmation email.")?;
-
-    Ok(HttpResponse::Ok().finish())
+    Ok(())
 }
 
+/// Returns `None` when somebody already subscribed with the same normalized address.
+///
+/// Single opt-in subscribers are stored as confirmed straight away.
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
     skip(txn, new_subscriber)
@@ -101,20 +351,33 @@ pub async fn subscribe(
 pub async fn insert_subscriber(
     txn: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
+    opt_in: OptInMode,
+    locale: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
     let subscriber_id = Uuid::new_v4();
+    let status = match opt_in {
+        OptInMode::Double => SubscriptionStatus::PendingConfirmation,
+        OptInMode::Single => SubscriptionStatus::Confirmed,
+    };
     let query = sqlx::query!(
         r#"
-        Insert Into subscriptions (id, email, name, subscribed_at, status)
-        Values ($1, $2, $3, $4, 'pending_confirmation')
+        Insert Into subscriptions (
+            id, email, email_normalized, name, subscribed_at, status, opt_in_mode, locale
+        )
+        Values ($1, $2, $3, $4, $5, $6, $7, $8)
+        On Conflict (email_normalized) Do Nothing
         "#,
         subscriber_id,
         new_subscriber.email.as_ref(),
+        new_subscriber.email.normalized(),
         new_subscriber.name.as_ref(),
-        Utc::now()
+        Utc::now(),
+        status as SubscriptionStatus,
+        OptIn::from(opt_in) as OptIn,
+        locale
     );
-    txn.execute(query).await?; // Using the `?` operator to return early if the function failed, returning `sqlx::Error`
-    Ok(subscriber_id)
+    let result = txn.execute(query).await?; // Using the `?` operator to return early if the function failed, returning `sqlx::Error`
+    Ok((result.rows_affected() == 1).then_some(subscriber_id))
 }
 
 pub struct StoreTokenError(sqlx::Error);
@@ -153,49 +416,104 @@ pub async fn store_token(
 ) -> Result<(), StoreTokenError> {
     let query = sqlx::query!(
         r#"
-        Insert Into subscription_tokens (subscription_token, subscriber_id)
-        Values ($1, $2)
+        Insert Into subscription_tokens (subscription_token, subscriber_id, created_at)
+        Values ($1, $2, $3)
     "#,
         subscription_token,
-        subscriber_id
+        subscriber_id,
+        Utc::now()
     );
     txn.execute(query).await.map_err(StoreTokenError)?;
     Ok(())
 }
 
+/// Whether a confirmation email actually left
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfirmationEmailOutcome {
+    Sent,
+    Suppressed,
+}
+
 #[tracing::instrument(
     name = "Send a confirmation email to a new subscriber.",
-    skip(email_client, new_subscriber, base_url, subscription_token)
+    skip(
+        email_client,
+        db_pool,
+        email_hasher,
+        new_subscriber,
+        base_url,
+        subscription_token,
+        template
+    )
 )]
 pub async fn send_confirmation_email(
     email_client: &EmailClient,
+    db_pool: &PgPool,
+    email_hasher: &EmailHasher,
     new_subscriber: NewSubscriber,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+    template: &ConfirmationEmailTemplate,
+) -> Result<ConfirmationEmailOutcome, anyhow::Error> {
+    if is_suppressed(db_pool, email_hasher, &new_subscriber.email)
+        .await
+        .context("Failed to check the suppression list.")?
+    {
+        tracing::info!("Skipping the confirmation email to a suppressed address");
+        return Ok(ConfirmationEmailOutcome::Suppressed);
+    }
+
+    let email = render_confirmation_email(&new_subscriber, base_url, subscription_token, template);
+    email_client
+        .send_email(
+            &new_subscriber.email,
+            &email.subject,
+            &email.html_body,
+            &email.text_body,
+        )
+        .await?;
+    Ok(ConfirmationEmailOutcome::Sent)
+}
+
+/// Write the confirmation email to the outbox, to be sent once `txn` commits
+pub async fn enqueue_confirmation_email(
+    txn: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    new_subscriber: &NewSubscriber,
+    base_url: &str,
+    subscription_token: &str,
+    template: &ConfirmationEmailTemplate,
+) -> Result<Uuid, sqlx::Error> {
+    let email = render_confirmation_email(new_subscriber, base_url, subscription_token, template);
+    enqueue_email(
+        txn,
+        Some(subscriber_id),
+        OutboxEmailKind::Confirmation,
+        &new_subscriber.email,
+        &email,
+    )
+    .await
+}
+
+fn render_confirmation_email(
+    new_subscriber: &NewSubscriber,
+    base_url: &str,
+    subscription_token: &str,
+    template: &ConfirmationEmailTemplate,
+) -> RenderedEmail {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token,
     );
-
-    let html_body = &format!(
-        "Welcome to our newsletter!<br />\
-    Click <a href=\"{}\">here</a> to confirm your subscription.",
-        confirmation_link
-    );
-
-    let plain_body = &format!(
-        "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
-        confirmation_link
-    );
-
-    email_client
-        .send_email(&new_subscriber.email, "Welcome", html_body, plain_body)
-        .await
+    template.render(
+        new_subscriber.name.as_ref(),
+        new_subscriber.email.as_ref(),
+        &confirmation_link,
+    )
 }
 
 /// Generates a random 25-character-long case-sensitive subscription token
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -0,0 +1,18 @@
+use actix_web::{web, HttpResponse};
+
+use crate::captcha::CaptchaVerifier;
+
+/// Hand out a proof of work challenge for the subscribe form, when it requires one
+#[tracing::instrument(name = "Issue a signup challenge", skip(captcha_verifier))]
+pub async fn signup_challenge(
+    captcha_verifier: web::Data<Option<Box<dyn CaptchaVerifier>>>,
+) -> HttpResponse {
+    match captcha_verifier
+        .as_ref()
+        .as_ref()
+        .and_then(|verifier| verifier.issue_challenge())
+    {
+        Some(challenge) => HttpResponse::Ok().json(challenge),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
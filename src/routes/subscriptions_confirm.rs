@@ -1,50 +1,446 @@
-use actix_web::{web, HttpResponse};
-use sqlx::PgPool;
+use actix_web::http::header::{ContentType, ACCEPT_LANGUAGE, LOCATION};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+use crate::configuration::ConfirmationLinkSettings;
+use crate::domain::{
+    IllegalTransition, NewSubscriber, SubscriberEmail, SubscriberName, SubscriptionStatus,
+};
+use crate::email_templates::{ConfirmationEmailTemplate, ConfirmationEmailTemplates};
+use crate::onboarding::{cancel_onboarding, schedule_onboarding};
+use crate::outbox::OutboxDispatcher;
+use crate::routes::{
+    enqueue_confirmation_email, error_chain_fmt, generate_subscription_token, store_token,
+};
+use crate::startup::ApplicationBaseUrl;
+use crate::subscription_events::{record_subscription_event, EventSource, SubscriptionEventKind};
+use crate::template::escape_html;
+
 #[derive(serde::Deserialize)]
 pub struct Parameters {
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, db_pool))]
+/// Where following a confirmation link leads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConfirmationOutcome {
+    Confirmed,
+    AlreadyConfirmed,
+    InvalidToken,
+    /// e.g. following an old confirmation link after unsubscribing
+    NoLongerConfirmable,
+    /// Offers to send a new link, link scanners following it must not trigger emails
+    ExpiredToken,
+    /// A new link was sent in place of the expired one, on request
+    NewLinkSent,
+    Failed,
+}
+
+impl ConfirmationOutcome {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ConfirmationOutcome::Confirmed
+            | ConfirmationOutcome::AlreadyConfirmed
+            | ConfirmationOutcome::NewLinkSent => StatusCode::OK,
+            ConfirmationOutcome::InvalidToken => StatusCode::UNAUTHORIZED,
+            ConfirmationOutcome::NoLongerConfirmable => StatusCode::CONFLICT,
+            ConfirmationOutcome::ExpiredToken => StatusCode::GONE,
+            ConfirmationOutcome::Failed => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn redirect_url<'a>(&self, settings: &'a ConfirmationLinkSettings) -> Option<&'a str> {
+        match self {
+            ConfirmationOutcome::Confirmed => settings.confirmed_redirect_url.as_deref(),
+            ConfirmationOutcome::AlreadyConfirmed => {
+                settings.already_confirmed_redirect_url.as_deref()
+            }
+            ConfirmationOutcome::InvalidToken | ConfirmationOutcome::NoLongerConfirmable => {
+                settings.invalid_redirect_url.as_deref()
+            }
+            ConfirmationOutcome::ExpiredToken => settings.expired_redirect_url.as_deref(),
+            ConfirmationOutcome::NewLinkSent | ConfirmationOutcome::Failed => None,
+        }
+    }
+
+    fn title_and_message(&self) -> (&'static str, &'static str) {
+        match self {
+            ConfirmationOutcome::Confirmed => (
+                "Subscription confirmed",
+                "Thanks for confirming your email address, you are now subscribed to our newsletter.",
+            ),
+            ConfirmationOutcome::AlreadyConfirmed => (
+                "Already confirmed",
+                "Your subscription was already confirmed, there is nothing else to do.",
+            ),
+            ConfirmationOutcome::InvalidToken => (
+                "Invalid link",
+                "This confirmation link is not valid. Please check that you copied it completely.",
+            ),
+            ConfirmationOutcome::NoLongerConfirmable => (
+                "Invalid link",
+                "This subscription can no longer be confirmed. Please subscribe again.",
+            ),
+            ConfirmationOutcome::ExpiredToken => (
+                "Link expired",
+                "This confirmation link has expired, but we can send you a new one.",
+            ),
+            ConfirmationOutcome::NewLinkSent => (
+                "New link sent",
+                "We just sent you a new confirmation link. Please check your inbox.",
+            ),
+            ConfirmationOutcome::Failed => (
+                "Something went wrong",
+                "We could not confirm your subscription, please try again later.",
+            ),
+        }
+    }
+
+    fn response(
+        &self,
+        settings: &ConfirmationLinkSettings,
+        subscription_token: &str,
+    ) -> HttpResponse {
+        if let Some(url) = self.redirect_url(settings) {
+            return HttpResponse::SeeOther()
+                .insert_header((LOCATION, url))
+                .finish();
+        }
+        let (title, message) = self.title_and_message();
+        let form = if *self == ConfirmationOutcome::ExpiredToken {
+            format!(
+                r#"
+<form method="post" action="/subscriptions/confirm?subscription_token={}">
+<button type="submit">Send me a new link</button>
+</form>"#,
+                escape_html(subscription_token)
+            )
+        } else {
+            String::new()
+        };
+        HttpResponse::build(self.status_code())
+            .content_type(ContentType::html())
+            .body(format!(
+                r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta http-equiv="content-type" content="text/html; charset=utf-8">
+<title>{title}</title>
+</head>
+<body>
+<h1>{title}</h1>
+<p>{message}</p>{form}
+</body>
+</html>"#
+            ))
+    }
+}
+
+/// Follow the link of a confirmation email.
+///
+/// Expired links only offer to send a new one: mail scanners follow links too.
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(
+        parameters,
+        request,
+        db_pool,
+        outbox_dispatcher,
+        base_url,
+        confirmation_email_templates,
+        link_settings
+    )
+)]
 pub async fn confirm(
     parameters: web::Query<Parameters>,
+    request: HttpRequest,
     db_pool: web::Data<PgPool>,
+    outbox_dispatcher: web::Data<OutboxDispatcher>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    confirmation_email_templates: web::Data<ConfirmationEmailTemplates>,
+    link_settings: web::Data<ConfirmationLinkSettings>,
 ) -> HttpResponse {
-    let id = match get_subscriber_id_from_token(&db_pool, &parameters.subscription_token).await {
-        Ok(id) => id,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
+    let outcome = follow_confirmation_link(
+        &parameters.subscription_token,
+        &request,
+        &db_pool,
+        &outbox_dispatcher,
+        &base_url.0,
+        &confirmation_email_templates,
+        &link_settings,
+        false,
+    )
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!(error.cause_chain = ?e, "Failed to follow a confirmation link");
+        ConfirmationOutcome::Failed
+    });
+    outcome.response(&link_settings, &parameters.subscription_token)
+}
+
+/// Submitted from the expired link page to get a new confirmation email
+#[tracing::instrument(
+    name = "Request a new confirmation link",
+    skip(
+        parameters,
+        request,
+        db_pool,
+        outbox_dispatcher,
+        base_url,
+        confirmation_email_templates,
+        link_settings
+    )
+)]
+pub async fn request_new_confirmation_link(
+    parameters: web::Query<Parameters>,
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    outbox_dispatcher: web::Data<OutboxDispatcher>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    confirmation_email_templates: web::Data<ConfirmationEmailTemplates>,
+    link_settings: web::Data<ConfirmationLinkSettings>,
+) -> HttpResponse {
+    let outcome = follow_confirmation_link(
+        &parameters.subscription_token,
+        &request,
+        &db_pool,
+        &outbox_dispatcher,
+        &base_url.0,
+        &confirmation_email_templates,
+        &link_settings,
+        true,
+    )
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!(error.cause_chain = ?e, "Failed to send a new confirmation link");
+        ConfirmationOutcome::Failed
+    });
+    outcome.response(&link_settings, &parameters.subscription_token)
+}
+
+/// Confirm the subscriber behind `subscription_token`, sending a new link instead when it
+/// expired and `resend_expired` is set
+#[allow(clippy::too_many_arguments)]
+async fn follow_confirmation_link(
+    subscription_token: &str,
+    request: &HttpRequest,
+    db_pool: &PgPool,
+    outbox_dispatcher: &OutboxDispatcher,
+    base_url: &str,
+    confirmation_email_templates: &ConfirmationEmailTemplates,
+    link_settings: &ConfirmationLinkSettings,
+    resend_expired: bool,
+) -> Result<ConfirmationOutcome, anyhow::Error> {
+    let token = sqlx::query!(
+        r#"
+        Select
+            t.subscriber_id,
+            t.created_at,
+            s.email,
+            s.name,
+            s.status As "status: SubscriptionStatus",
+            s.locale
+        From subscription_tokens t
+        Join subscriptions s On s.id = t.subscriber_id
+        Where t.subscription_token = $1
+        "#,
+        subscription_token
+    )
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to look up the confirmation token.")?;
+    // Non-existing token protection
+    let Some(token) = token else {
+        return Ok(ConfirmationOutcome::InvalidToken);
     };
-    match id {
-        // Non-existing token protection
-        None => HttpResponse::Unauthorized().finish(),
-        Some(subscriber_id) => {
-            if confirm_subscriber(&db_pool, subscriber_id).await.is_err() {
-                return HttpResponse::InternalServerError().finish();
-            }
-            HttpResponse::Ok().finish()
+    // Tokens double as unsubscribe links, they only expire for confirmation purposes
+    if token.status == SubscriptionStatus::PendingConfirmation
+        && token.created_at + link_settings.ttl() <= Utc::now()
+    {
+        if !resend_expired {
+            return Ok(ConfirmationOutcome::ExpiredToken);
+        }
+        let new_subscriber = NewSubscriber {
+            email: SubscriberEmail::parse(token.email).map_err(anyhow::Error::msg)?,
+            name: SubscriberName::parse(token.name).map_err(anyhow::Error::msg)?,
+        };
+        let template = confirmation_email_templates.select(
+            token.locale.as_deref(),
+            request
+                .headers()
+                .get(ACCEPT_LANGUAGE)
+                .and_then(|h| h.to_str().ok()),
+        );
+        resend_confirmation_email(
+            db_pool,
+            outbox_dispatcher,
+            base_url,
+            template,
+            token.subscriber_id,
+            subscription_token,
+            new_subscriber,
+        )
+        .await?;
+        return Ok(ConfirmationOutcome::NewLinkSent);
+    }
+
+    let event_source = EventSource::from_request(request);
+    match confirm_subscriber(db_pool, token.subscriber_id, &event_source).await {
+        Ok(SubscriptionStatus::Confirmed) => Ok(ConfirmationOutcome::AlreadyConfirmed),
+        Ok(_) => Ok(ConfirmationOutcome::Confirmed),
+        Err(StatusChangeError::IllegalTransition(_)) => {
+            Ok(ConfirmationOutcome::NoLongerConfirmable)
         }
+        Err(e) => Err(e).context("Failed to confirm the subscriber."),
+    }
+}
+
+/// Replace an expired token with a new one, and email the new link
+#[tracing::instrument(
+    name = "Resend a confirmation email",
+    skip(
+        db_pool,
+        outbox_dispatcher,
+        base_url,
+        template,
+        expired_token,
+        new_subscriber
+    )
+)]
+async fn resend_confirmation_email(
+    db_pool: &PgPool,
+    outbox_dispatcher: &OutboxDispatcher,
+    base_url: &str,
+    template: &ConfirmationEmailTemplate,
+    subscriber_id: Uuid,
+    expired_token: &str,
+    new_subscriber: NewSubscriber,
+) -> Result<(), anyhow::Error> {
+    let subscription_token = generate_subscription_token();
+    let mut txn = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    // The expired link cannot be used to request any more emails
+    txn.execute(sqlx::query!(
+        r#"Delete From subscription_tokens Where subscription_token = $1"#,
+        expired_token
+    ))
+    .await
+    .context("Failed to delete the expired token.")?;
+    store_token(&mut txn, subscriber_id, &subscription_token)
+        .await
+        .context("Failed to store the new confirmation token.")?;
+    let outbox_email_id = enqueue_confirmation_email(
+        &mut txn,
+        subscriber_id,
+        &new_subscriber,
+        base_url,
+        &subscription_token,
+        template,
+    )
+    .await
+    .context("Failed to write the confirmation email to the outbox.")?;
+    txn.commit()
+        .await
+        .context("Failed to commit SQL transaction to replace an expired token.")?;
+    outbox_dispatcher.try_deliver(outbox_email_id).await;
+    Ok(())
+}
+
+#[derive(thiserror::Error)]
+pub enum StatusChangeError {
+    #[error(transparent)]
+    IllegalTransition(#[from] IllegalTransition),
+    #[error("The subscriber does not exist.")]
+    UnknownSubscriber,
+    #[error("A database error was encountered while changing a subscription status.")]
+    DatabaseError(#[from] sqlx::Error),
+}
+
+impl std::fmt::Debug for StatusChangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
     }
 }
 
-#[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, db_pool))]
-pub async fn confirm_subscriber(db_pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+/// Returns the status the subscriber was in
+#[tracing::instrument(
+    name = "Mark subscriber as confirmed",
+    skip(subscriber_id, db_pool, event_source)
+)]
+pub async fn confirm_subscriber(
+    db_pool: &PgPool,
+    subscriber_id: Uuid,
+    event_source: &EventSource,
+) -> Result<SubscriptionStatus, StatusChangeError> {
+    let mut txn = db_pool.begin().await?;
+    let previous =
+        transition_status(&mut txn, subscriber_id, SubscriptionStatus::Confirmed).await?;
+    if previous != SubscriptionStatus::Confirmed {
+        record_subscription_event(
+            &mut txn,
+            subscriber_id,
+            SubscriptionEventKind::Confirmed,
+            event_source,
+        )
+        .await?;
+    }
+    txn.commit().await.map_err(|e| {
+        tracing::error!("Failed to commit transaction: {:?}", e);
+        e
+    })?;
+    Ok(previous)
+}
+
+/// Move the subscriber to the `to` status, returning the status they were in.
+///
+/// The row is locked until the transaction ends, callers record the matching event.
+/// Confirming starts the onboarding sequence, leaving the confirmed status cancels it.
+#[tracing::instrument(name = "Change a subscription status", skip(txn))]
+pub async fn transition_status(
+    txn: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    to: SubscriptionStatus,
+) -> Result<SubscriptionStatus, StatusChangeError> {
+    let current = sqlx::query!(
         r#"
-        Update subscriptions
-        SET status = 'confirmed'
+        Select status As "status: SubscriptionStatus" From subscriptions
         Where id = $1
-    "#,
+        For Update
+        "#,
         subscriber_id
     )
-    .execute(db_pool)
-    .await
-    .map_err(|e| {
+    .fetch_optional(&mut **txn)
+    .await?
+    .ok_or(StatusChangeError::UnknownSubscriber)?
+    .status;
+    current.transition(to)?;
+    if current != SubscriptionStatus::Confirmed && to == SubscriptionStatus::Confirmed {
+        schedule_onboarding(txn, subscriber_id).await?;
+    }
+    if current == SubscriptionStatus::Confirmed && to != SubscriptionStatus::Confirmed {
+        cancel_onboarding(txn, subscriber_id).await?;
+    }
+
+    let query = sqlx::query!(
+        r#"
+        Update subscriptions
+        SET status = $2
+        Where id = $1
+    "#,
+        subscriber_id,
+        to as SubscriptionStatus
+    );
+    txn.execute(query).await.map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
         e
     })?;
-    Ok(())
+    Ok(current)
 }
 
 #[tracing::instrument(
@@ -0,0 +1,339 @@
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::domain::SubscriptionStatus;
+use crate::routes::{error_chain_fmt, get_subscriber_id_from_token};
+use crate::suppression::EmailHasher;
+
+#[derive(serde::Deserialize)]
+pub struct SubscriberDataParameters {
+    subscription_token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum SubscriberDataError {
+    #[error("The subscription token is not valid.")]
+    UnknownToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscriberDataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscriberDataError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SubscriberDataError::UnknownToken => StatusCode::UNAUTHORIZED,
+            SubscriberDataError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Everything we store about a single subscriber
+#[derive(serde::Serialize)]
+pub struct SubscriberDataExport {
+    profile: Profile,
+    status_history: Vec<StatusChange>,
+    tokens: Vec<IssuedToken>,
+    deliveries: Vec<Delivery>,
+    engagement_events: Vec<EngagementEvent>,
+    onboarding_emails: Vec<OnboardingEmail>,
+    /// Transactional emails, e.g. confirmations, sent or waiting to be
+    outbox_emails: Vec<OutboxEmail>,
+}
+
+#[derive(serde::Serialize)]
+struct Profile {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: SubscriptionStatus,
+    subscribed_at: DateTime<Utc>,
+    attributes: serde_json::Value,
+}
+
+#[derive(serde::Serialize)]
+struct StatusChange {
+    kind: String,
+    occurred_at: DateTime<Utc>,
+    ip_address: Option<String>,
+    user_agent: Option<String>,
+    source: String,
+}
+
+#[derive(serde::Serialize)]
+struct IssuedToken {
+    subscription_token: String,
+}
+
+#[derive(serde::Serialize)]
+struct Delivery {
+    newsletter_issue_id: Uuid,
+    title: String,
+    delivered_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct EngagementEvent {
+    newsletter_issue_id: Uuid,
+    kind: String,
+    url: Option<String>,
+    occurred_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct OnboardingEmail {
+    onboarding_email_id: Uuid,
+    subject: String,
+    confirmed_at: DateTime<Utc>,
+    /// `None` while it is still to be sent
+    sent_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+struct OutboxEmail {
+    kind: String,
+    recipient: String,
+    subject: String,
+    text_body: String,
+    created_at: DateTime<Utc>,
+    processed_at: Option<DateTime<Utc>>,
+    outcome: Option<String>,
+}
+
+/// Let a subscriber download all the data we hold about them
+#[tracing::instrument(name = "Export a subscriber's data", skip(parameters, db_pool))]
+pub async fn export_subscriber_data(
+    parameters: web::Query<SubscriberDataParameters>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SubscriberDataError> {
+    let subscriber_id = get_subscriber_id_from_token(&db_pool, &parameters.subscription_token)
+        .await
+        .context("Failed to retrieve the subscriber associated with the provided token.")?
+        .ok_or(SubscriberDataError::UnknownToken)?;
+    let export = get_subscriber_data(&db_pool, subscriber_id)
+        .await
+        .context("Failed to collect the subscriber's data.")?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("subscriber-data.json".into())],
+        })
+        .json(export))
+}
+
+/// Forget a subscriber, keeping only a hash of their address so they are never re-added
+#[tracing::instrument(
+    name = "Erase a subscriber's data",
+    skip(parameters, db_pool, email_hasher)
+)]
+pub async fn erase_subscriber_data(
+    parameters: web::Query<SubscriberDataParameters>,
+    db_pool: web::Data<PgPool>,
+    email_hasher: web::Data<EmailHasher>,
+) -> Result<HttpResponse, SubscriberDataError> {
+    let subscriber_id = get_subscriber_id_from_token(&db_pool, &parameters.subscription_token)
+        .await
+        .context("Failed to retrieve the subscriber associated with the provided token.")?
+        .ok_or(SubscriberDataError::UnknownToken)?;
+
+    let mut txn = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    erase_subscriber(&mut txn, &email_hasher, subscriber_id)
+        .await
+        .context("Failed to erase the subscriber.")?;
+    txn.commit()
+        .await
+        .context("Failed to commit SQL transaction to erase a subscriber.")?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[tracing::instrument(name = "Collect a subscriber's data", skip(db_pool))]
+async fn get_subscriber_data(
+    db_pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<SubscriberDataExport, sqlx::Error> {
+    let profile = sqlx::query_as!(
+        Profile,
+        r#"
+        Select id, email, name, status As "status: SubscriptionStatus", subscribed_at, attributes
+        From subscriptions
+        Where id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_one(db_pool)
+    .await?;
+    let status_history = sqlx::query_as!(
+        StatusChange,
+        r#"
+        Select kind, occurred_at, ip_address, user_agent, source
+        From subscription_events
+        Where subscriber_id = $1
+        Order By occurred_at, id
+        "#,
+        subscriber_id
+    )
+    .fetch_all(db_pool)
+    .await?;
+    let tokens = sqlx::query_as!(
+        IssuedToken,
+        r#"
+        Select subscription_token From subscription_tokens
+        Where subscriber_id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_all(db_pool)
+    .await?;
+    let deliveries = sqlx::query_as!(
+        Delivery,
+        r#"
+        Select d.newsletter_issue_id, i.title, d.delivered_at
+        From newsletter_deliveries d
+        Join newsletter_issues i On i.newsletter_issue_id = d.newsletter_issue_id
+        Where d.subscriber_id = $1
+        Order By d.delivered_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(db_pool)
+    .await?;
+    let engagement_events = sqlx::query_as!(
+        EngagementEvent,
+        r#"
+        Select newsletter_issue_id, kind, url, occurred_at
+        From newsletter_engagement_events
+        Where subscriber_id = $1
+        Order By occurred_at, id
+        "#,
+        subscriber_id
+    )
+    .fetch_all(db_pool)
+    .await?;
+    let onboarding_emails = sqlx::query_as!(
+        OnboardingEmail,
+        r#"
+        Select d.onboarding_email_id, e.subject, d.confirmed_at, d.sent_at
+        From onboarding_deliveries d
+        Join onboarding_emails e On e.id = d.onboarding_email_id
+        Where d.subscriber_id = $1
+        Order By e.delay_days, e.id
+        "#,
+        subscriber_id
+    )
+    .fetch_all(db_pool)
+    .await?;
+    let outbox_emails = sqlx::query_as!(
+        OutboxEmail,
+        r#"
+        Select kind, recipient, subject, text_body, created_at, processed_at, outcome
+        From email_outbox
+        Where subscriber_id = $1
+        Order By created_at, id
+        "#,
+        subscriber_id
+    )
+    .fetch_all(db_pool)
+    .await?;
+
+    Ok(SubscriberDataExport {
+        profile,
+        status_history,
+        tokens,
+        deliveries,
+        engagement_events,
+        onboarding_emails,
+        outbox_emails,
+    })
+}
+
+/// Delete the subscriber and make sure they are never emailed or subscribed again
+#[tracing::instrument(name = "Erase a subscriber", skip(txn, email_hasher))]
+pub async fn erase_subscriber(
+    txn: &mut Transaction<'_, Postgres>,
+    email_hasher: &EmailHasher,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    if let Some(email_normalized) = delete_subscriber(txn, subscriber_id).await? {
+        // The hash below replaces any plain-text suppression of the address
+        txn.execute(sqlx::query!(
+            r#"Delete From suppressions Where kind = 'address' And value = $1"#,
+            email_normalized
+        ))
+        .await?;
+        txn.execute(sqlx::query!(
+            r#"
+            Insert Into suppressed_email_hashes (email_hash, suppressed_at)
+            Values ($1, $2)
+            On Conflict (email_hash) Do Nothing
+            "#,
+            email_hasher.hash(&email_normalized),
+            Utc::now()
+        ))
+        .await?;
+    }
+    Ok(())
+}
+
+/// Delete every row referencing the subscriber, then the subscriber itself.
+///
+/// Returns the normalized address of the deleted subscriber, if there was one.
+#[tracing::instrument(name = "Delete a subscriber across all tables", skip(txn))]
+pub async fn delete_subscriber(
+    txn: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    for query in [
+        sqlx::query!(
+            r#"Delete From subscription_events Where subscriber_id = $1"#,
+            subscriber_id
+        ),
+        sqlx::query!(
+            r#"Delete From newsletter_engagement_events Where subscriber_id = $1"#,
+            subscriber_id
+        ),
+        sqlx::query!(
+            r#"Delete From newsletter_delivery_queue Where subscriber_id = $1"#,
+            subscriber_id
+        ),
+        sqlx::query!(
+            r#"Delete From newsletter_deliveries Where subscriber_id = $1"#,
+            subscriber_id
+        ),
+        sqlx::query!(
+            r#"Delete From onboarding_deliveries Where subscriber_id = $1"#,
+            subscriber_id
+        ),
+        sqlx::query!(
+            r#"Delete From email_outbox Where subscriber_id = $1"#,
+            subscriber_id
+        ),
+        sqlx::query!(
+            r#"Delete From subscription_tokens Where subscriber_id = $1"#,
+            subscriber_id
+        ),
+    ] {
+        txn.execute(query).await?;
+    }
+    let deleted = sqlx::query!(
+        r#"Delete From subscriptions Where id = $1 Returning email_normalized"#,
+        subscriber_id
+    )
+    .fetch_optional(&mut **txn)
+    .await?;
+    Ok(deleted.map(|deleted| deleted.email_normalized))
+}
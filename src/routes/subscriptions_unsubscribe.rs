@@ -0,0 +1,135 @@
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::SubscriptionStatus;
+use crate::routes::{get_subscriber_id_from_token, transition_status, StatusChangeError};
+use crate::subscription_events::{record_subscription_event, EventSource, SubscriptionEventKind};
+use crate::suppression::{suppress, SuppressionReason, SuppressionTarget};
+use crate::template::escape_html;
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    subscription_token: String,
+}
+
+/// Ask for confirmation first: following a link must not change anything, mail scanners and
+/// link previews fetch them on the subscriber's behalf.
+#[tracing::instrument(name = "Show the unsubscribe form", skip(parameters, db_pool))]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    db_pool: web::Data<PgPool>,
+) -> HttpResponse {
+    match get_subscriber_id_from_token(&db_pool, &parameters.subscription_token).await {
+        Ok(Some(_)) => {}
+        // Non-existing token protection
+        Ok(None) => return HttpResponse::Unauthorized().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+    let action = format!(
+        "/subscriptions/unsubscribe?subscription_token={}",
+        escape_html(&parameters.subscription_token)
+    );
+    page(
+        StatusCode::OK,
+        "Unsubscribe",
+        &format!(
+            r#"<p>Do you want to stop receiving our newsletter?</p>
+<form method="post" action="{action}">
+<button type="submit">Unsubscribe</button>
+</form>"#
+        ),
+    )
+}
+
+/// Submitted from the unsubscribe form, or by mail clients offering one-click unsubscribe
+/// through the `List-Unsubscribe-Post` header (RFC 8058), with the token in the query string.
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip(parameters, request, db_pool))]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let id = match get_subscriber_id_from_token(&db_pool, &parameters.subscription_token).await {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    match id {
+        // Non-existing token protection
+        None => HttpResponse::Unauthorized().finish(),
+        Some(subscriber_id) => {
+            let event_source = EventSource::from_request(&request);
+            match unsubscribe_subscriber(&db_pool, subscriber_id, &event_source).await {
+                Ok(()) => page(
+                    StatusCode::OK,
+                    "Unsubscribed",
+                    "<p>You will not receive our newsletter anymore.</p>",
+                ),
+                Err(StatusChangeError::IllegalTransition(_)) => HttpResponse::Conflict().finish(),
+                Err(_) => HttpResponse::InternalServerError().finish(),
+            }
+        }
+    }
+}
+
+fn page(status: StatusCode, title: &str, body: &str) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta http-equiv="content-type" content="text/html; charset=utf-8">
+<title>{title}</title>
+</head>
+<body>
+<h1>{title}</h1>
+{body}
+</body>
+</html>"#
+        ))
+}
+
+#[tracing::instrument(
+    name = "Mark subscriber as unsubscribed",
+    skip(subscriber_id, db_pool, event_source)
+)]
+pub async fn unsubscribe_subscriber(
+    db_pool: &PgPool,
+    subscriber_id: Uuid,
+    event_source: &EventSource,
+) -> Result<(), StatusChangeError> {
+    let mut txn = db_pool.begin().await?;
+    let previous =
+        transition_status(&mut txn, subscriber_id, SubscriptionStatus::Unsubscribed).await?;
+    if previous != SubscriptionStatus::Unsubscribed {
+        let email_normalized = sqlx::query!(
+            r#"Select email_normalized From subscriptions Where id = $1"#,
+            subscriber_id
+        )
+        .fetch_one(&mut *txn)
+        .await?
+        .email_normalized;
+        suppress(
+            &mut txn,
+            &SuppressionTarget::Address(email_normalized),
+            SuppressionReason::Unsubscribed,
+            None,
+        )
+        .await?;
+        record_subscription_event(
+            &mut txn,
+            subscriber_id,
+            SubscriptionEventKind::Unsubscribed,
+            event_source,
+        )
+        .await?;
+    }
+    txn.commit().await.map_err(|e| {
+        tracing::error!("Failed to commit transaction: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
@@ -0,0 +1,188 @@
+use actix_web::http::header::{CacheControl, CacheDirective, LOCATION};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::configuration::{AdminSettings, TrackingSettings};
+use crate::routes::{require_admin, AdminError};
+use crate::tracking::TrackingToken;
+
+/// A transparent 1x1 GIF
+const PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+#[derive(serde::Deserialize)]
+pub struct TrackingPath {
+    token: String,
+}
+
+/// Record that an issue was opened. The pixel is always served so the email renders normally.
+#[tracing::instrument(
+    name = "Track a newsletter open",
+    skip(path, db_pool, tracking_settings)
+)]
+pub async fn track_open(
+    path: web::Path<TrackingPath>,
+    db_pool: web::Data<PgPool>,
+    tracking_settings: web::Data<TrackingSettings>,
+) -> HttpResponse {
+    if tracking_settings.enabled {
+        match TrackingToken::decode(&path.token, &tracking_settings.signing_key) {
+            Ok(token) => {
+                if let Err(e) = record_event(&db_pool, &token, "open").await {
+                    tracing::error!(error.cause_chain = ?e, "Failed to record a newsletter open");
+                }
+            }
+            Err(e) => tracing::warn!("Ignoring an invalid open tracking token: {}", e),
+        }
+    }
+
+    HttpResponse::Ok()
+        .content_type("image/gif")
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(PIXEL)
+}
+
+/// Record a click on a link of an issue and redirect to its original target
+#[tracing::instrument(
+    name = "Track a newsletter click",
+    skip(path, db_pool, tracking_settings)
+)]
+pub async fn track_click(
+    path: web::Path<TrackingPath>,
+    db_pool: web::Data<PgPool>,
+    tracking_settings: web::Data<TrackingSettings>,
+) -> HttpResponse {
+    // Only links we signed ourselves are followed, otherwise this would be an open redirect
+    let token = match TrackingToken::decode(&path.token, &tracking_settings.signing_key) {
+        Ok(token) => token,
+        Err(e) => {
+            tracing::warn!("Rejecting an invalid click tracking token: {}", e);
+            return HttpResponse::NotFound().finish();
+        }
+    };
+    let Some(url) = token.url.clone() else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    // Links in issues that were already sent keep working when tracking is switched off
+    if tracking_settings.enabled {
+        if let Err(e) = record_event(&db_pool, &token, "click").await {
+            tracing::error!(error.cause_chain = ?e, "Failed to record a newsletter click");
+        }
+    }
+
+    HttpResponse::Found()
+        .insert_header((LOCATION, url))
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .finish()
+}
+
+#[tracing::instrument(name = "Record a newsletter engagement event", skip(db_pool, token))]
+async fn record_event(
+    db_pool: &PgPool,
+    token: &TrackingToken,
+    kind: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        Insert Into newsletter_engagement_events (
+            newsletter_issue_id,
+            subscriber_id,
+            kind,
+            url,
+            occurred_at
+        )
+        Values ($1, $2, $3, $4, $5)
+        "#,
+        token.newsletter_issue_id,
+        token.subscriber_id,
+        kind,
+        token.url,
+        Utc::now()
+    )
+    .execute(db_pool)
+    .await?;
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+pub struct EngagementPath {
+    newsletter_issue_id: Uuid,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct EngagementReport {
+    pub newsletter_issue_id: Uuid,
+    pub track_opens: bool,
+    pub track_clicks: bool,
+    pub recipients: i64,
+    pub unique_opens: i64,
+    pub unique_clicks: i64,
+    pub open_rate: f64,
+    pub click_rate: f64,
+}
+
+/// Unique open and click rates of an issue, relative to the subscribers it was delivered to
+#[tracing::instrument(
+    name = "Report newsletter engagement",
+    skip(path, request, db_pool, admin)
+)]
+pub async fn newsletter_engagement(
+    path: web::Path<EngagementPath>,
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    admin: web::Data<AdminSettings>,
+) -> Result<HttpResponse, AdminError> {
+    require_admin(&request, &admin)?;
+    let row = sqlx::query!(
+        r#"
+        Select
+            i.track_opens,
+            i.track_clicks,
+            (
+                Select Count(*) From newsletter_deliveries d
+                Where d.newsletter_issue_id = i.newsletter_issue_id
+            ) As "recipients!",
+            (
+                Select Count(Distinct e.subscriber_id) From newsletter_engagement_events e
+                Where e.newsletter_issue_id = i.newsletter_issue_id And e.kind = 'open'
+            ) As "unique_opens!",
+            (
+                Select Count(Distinct e.subscriber_id) From newsletter_engagement_events e
+                Where e.newsletter_issue_id = i.newsletter_issue_id And e.kind = 'click'
+            ) As "unique_clicks!"
+        From newsletter_issues i
+        Where i.newsletter_issue_id = $1
+        "#,
+        path.newsletter_issue_id
+    )
+    .fetch_optional(db_pool.get_ref())
+    .await
+    .context("Failed to compute the engagement of a newsletter issue.")?
+    .ok_or(AdminError::NotFound)?;
+
+    let rate = |count: i64| {
+        if row.recipients == 0 {
+            0.0
+        } else {
+            count as f64 / row.recipients as f64
+        }
+    };
+
+    Ok(HttpResponse::Ok().json(EngagementReport {
+        newsletter_issue_id: path.newsletter_issue_id,
+        track_opens: row.track_opens,
+        track_clicks: row.track_clicks,
+        recipients: row.recipients,
+        unique_opens: row.unique_opens,
+        unique_clicks: row.unique_clicks,
+        open_rate: rate(row.unique_opens),
+        click_rate: rate(row.unique_clicks),
+    }))
+}
@@ -0,0 +1,127 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::authentication::{basic_authentication, validate_webhook_credentials};
+use crate::configuration::WebhookSettings;
+use crate::domain::{SubscriberEmail, SubscriptionStatus};
+use crate::routes::{transition_status, AdminError, StatusChangeError};
+use crate::subscription_events::{record_subscription_event, EventSource, SubscriptionEventKind};
+use crate::suppression::{suppress, SuppressionReason, SuppressionTarget};
+
+/// The subset of Postmark's bounce and spam complaint webhook payloads we rely on
+#[derive(serde::Deserialize)]
+#[serde(tag = "RecordType")]
+pub enum PostmarkEvent {
+    Bounce {
+        #[serde(rename = "Type")]
+        bounce_type: String,
+        #[serde(rename = "Email")]
+        email: String,
+    },
+    SpamComplaint {
+        #[serde(rename = "Email")]
+        email: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+/// Postmark bounces that mean the address will never accept our emails
+const PERMANENT_BOUNCES: &[&str] = &["HardBounce", "BadEmailAddress", "ManuallyDeactivated"];
+
+/// Suppress addresses that hard bounced or reported us as spam.
+///
+/// Postmark is configured to call this with the webhook credentials using basic auth.
+#[tracing::instrument(
+    name = "Handle a Postmark webhook",
+    skip(event, request, db_pool, webhook)
+)]
+pub async fn postmark_webhook(
+    event: web::Json<PostmarkEvent>,
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    webhook: web::Data<WebhookSettings>,
+) -> Result<HttpResponse, AdminError> {
+    basic_authentication(request.headers())
+        .and_then(|credentials| validate_webhook_credentials(&credentials, &webhook))
+        .map_err(AdminError::AuthError)?;
+    let (email, reason, status, event_kind) = match event.0 {
+        PostmarkEvent::Bounce { bounce_type, email }
+            if PERMANENT_BOUNCES.contains(&bounce_type.as_str()) =>
+        {
+            (
+                email,
+                SuppressionReason::Bounced,
+                SubscriptionStatus::Bounced,
+                SubscriptionEventKind::Bounced,
+            )
+        }
+        PostmarkEvent::SpamComplaint { email } => (
+            email,
+            SuppressionReason::Complained,
+            SubscriptionStatus::Unsubscribed,
+            SubscriptionEventKind::Unsubscribed,
+        ),
+        // Soft bounces and other notifications are transient, nothing to do
+        _ => return Ok(HttpResponse::Ok().finish()),
+    };
+
+    let email = SubscriberEmail::parse(email).map_err(AdminError::ValidationError)?;
+
+    let mut txn = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    suppress(&mut txn, &SuppressionTarget::address(&email), reason, None)
+        .await
+        .context("Failed to suppress the address.")?;
+    update_subscriber(
+        &mut txn,
+        &email,
+        status,
+        event_kind,
+        &EventSource::from_request(&request),
+    )
+    .await?;
+    txn.commit()
+        .await
+        .context("Failed to commit SQL transaction to suppress an address.")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Reflect the event on the matching subscriber, if we have one
+async fn update_subscriber(
+    txn: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+    status: SubscriptionStatus,
+    event_kind: SubscriptionEventKind,
+    event_source: &EventSource,
+) -> Result<(), anyhow::Error> {
+    let subscriber = sqlx::query!(
+        r#"Select id From subscriptions Where email_normalized = $1"#,
+        email.normalized()
+    )
+    .fetch_optional(&mut **txn)
+    .await
+    .context("Failed to look up the subscriber.")?;
+    let Some(subscriber) = subscriber else {
+        return Ok(());
+    };
+
+    match transition_status(txn, subscriber.id, status).await {
+        Ok(previous) if previous != status => {
+            record_subscription_event(txn, subscriber.id, event_kind, event_source)
+                .await
+                .context("Failed to record the subscription event.")?;
+        }
+        Ok(_) => {}
+        // e.g. a bounce for somebody who already unsubscribed: the suppression is enough
+        Err(StatusChangeError::IllegalTransition(e)) => {
+            tracing::info!("Leaving the subscription status unchanged: {}", e);
+        }
+        Err(e) => return Err(e).context("Failed to update the subscription status."),
+    }
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::{watch, Notify};
+
+/// Coordinates stopping the server and the background workers, e.g. on `SIGTERM` during a deploy
+#[derive(Clone)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+/// Handed to each background worker, to stop taking on new work once shutdown starts
+#[derive(Clone)]
+pub struct ShutdownSignal(watch::Receiver<bool>);
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (sender, _) = watch::channel(false);
+        Self {
+            sender: Arc::new(sender),
+        }
+    }
+
+    /// Start shutting down, it cannot be undone
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn signal(&self) -> ShutdownSignal {
+        ShutdownSignal(self.sender.subscribe())
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShutdownSignal {
+    /// Resolves once shutdown was triggered, straight away if it already was
+    pub async fn triggered(&mut self) {
+        // The sender is only dropped along with the application, that is a shutdown too
+        let _ = self.0.wait_for(|stopped| *stopped).await;
+    }
+
+    /// Whether shutdown was triggered, for workers checking in between two tasks
+    pub fn is_triggered(&self) -> bool {
+        *self.0.borrow()
+    }
+}
+
+/// Counts the requests being handled, so that shutdown can wait for them.
+///
+/// actix-server's own graceful stop can drop in-flight connections: a worker exits as soon as
+/// the accept thread hangs up, which may happen before it is told to stop gracefully.
+#[derive(Clone, Default)]
+pub struct InFlightRequests(Arc<InFlight>);
+
+#[derive(Default)]
+struct InFlight {
+    count: AtomicUsize,
+    idle: Notify,
+}
+
+/// Held for as long as a request is being handled
+pub struct InFlightGuard(Arc<InFlight>);
+
+impl InFlightRequests {
+    pub fn start(&self) -> InFlightGuard {
+        self.0.count.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard(self.0.clone())
+    }
+
+    /// Resolves once no request is being handled
+    pub async fn drained(&self) {
+        loop {
+            // Registered before checking, so that the last guard cannot be missed
+            let idle = self.0.idle.notified();
+            if self.0.count.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+/// Resolves on `SIGTERM` or Ctrl-C
+pub async fn termination_requested() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for Ctrl-C.");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM.")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InFlightRequests, Shutdown};
+
+    #[tokio::test]
+    async fn signals_resolve_once_triggered() {
+        let shutdown = Shutdown::new();
+        let mut before = shutdown.signal();
+        let waiting = tokio::spawn(async move { before.triggered().await });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+
+        shutdown.trigger();
+
+        waiting.await.unwrap();
+        // Signals handed out late still see it
+        shutdown.signal().triggered().await;
+    }
+
+    #[tokio::test]
+    async fn draining_waits_for_the_last_request() {
+        let in_flight = InFlightRequests::default();
+        in_flight.drained().await;
+        let first = in_flight.start();
+        let second = in_flight.start();
+
+        drop(first);
+        let draining = tokio::spawn({
+            let in_flight = in_flight.clone();
+            async move { in_flight.drained().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!draining.is_finished());
+        drop(second);
+
+        draining.await.unwrap();
+    }
+}
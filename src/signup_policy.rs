@@ -0,0 +1,221 @@
+use std::collections::HashSet;
+
+use anyhow::Context;
+
+use crate::configuration::{OptInMode, SignupPolicySettings};
+use crate::domain::SubscriberEmail;
+
+/// Shared mailboxes that do not belong to a single person (RFC 2142 and friends)
+const ROLE_ACCOUNTS: &[&str] = &[
+    "abuse",
+    "admin",
+    "administrator",
+    "hostmaster",
+    "mailer-daemon",
+    "no-reply",
+    "noc",
+    "noreply",
+    "postmaster",
+    "root",
+    "security",
+    "webmaster",
+];
+
+/// Rules deciding which addresses may subscribe, on top of them being valid
+#[derive(Debug, Clone)]
+pub struct SignupPolicy {
+    disposable_domains: HashSet<String>,
+    reject_role_accounts: bool,
+    allowed_domains: HashSet<String>,
+    denied_domains: HashSet<String>,
+    opt_in: OptInMode,
+}
+
+impl SignupPolicy {
+    pub fn from_settings(settings: &SignupPolicySettings) -> Result<Self, anyhow::Error> {
+        let disposable_domains = std::fs::read_to_string(&settings.disposable_domains_path)
+            .with_context(|| {
+                format!(
+                    "Failed to read the disposable domains at {}",
+                    settings.disposable_domains_path
+                )
+            })?;
+        Ok(Self {
+            disposable_domains: parse_domain_list(&disposable_domains),
+            reject_role_accounts: settings.reject_role_accounts,
+            allowed_domains: settings
+                .allowed_domains
+                .iter()
+                .map(|d| normalise_domain(d))
+                .collect(),
+            denied_domains: settings
+                .denied_domains
+                .iter()
+                .map(|d| normalise_domain(d))
+                .collect(),
+            opt_in: settings.opt_in,
+        })
+    }
+
+    /// Explain why `email` may not subscribe, if it may not
+    pub fn check(&self, email: &SubscriberEmail) -> Result<(), String> {
+        let normalized = email.normalized();
+        let (local_part, domain) = normalized
+            .rsplit_once('@')
+            .expect("A parsed email always contains an @.");
+
+        if !self.allowed_domains.is_empty() && !matches_any(domain, &self.allowed_domains) {
+            return Err(format!("Signups from {} are not accepted.", domain));
+        }
+        if matches_any(domain, &self.denied_domains) {
+            return Err(format!("Signups from {} are not accepted.", domain));
+        }
+        if matches_any(domain, &self.disposable_domains) {
+            return Err("Disposable email addresses are not accepted.".into());
+        }
+        if self.reject_role_accounts {
+            // `postmaster+news@` is still the postmaster
+            let mailbox = local_part.split('+').next().unwrap_or_default();
+            if ROLE_ACCOUNTS.contains(&mailbox) {
+                return Err(format!(
+                    "Role accounts such as {}@ cannot subscribe, please use a personal address.",
+                    mailbox
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SignupPolicy {
+    /// Whether new subscribers have to confirm their address before being subscribed.
+    ///
+    /// The same for everybody: the signup form is public, so nothing about the address says
+    /// that it belongs to whoever filled it.
+    pub fn opt_in(&self) -> OptInMode {
+        self.opt_in
+    }
+}
+
+/// Match the punycode domains of normalized addresses
+fn normalise_domain(domain: &str) -> String {
+    let domain = domain
+        .trim()
+        .trim_start_matches('@')
+        .trim_end_matches('.')
+        .to_lowercase();
+    idna::domain_to_ascii(&domain).unwrap_or(domain)
+}
+
+/// One domain per line, ignoring blank lines and `#` comments
+fn parse_domain_list(list: &str) -> HashSet<String> {
+    list.lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .map(normalise_domain)
+        .filter(|domain| !domain.is_empty())
+        .collect()
+}
+
+/// Whether `domain` or one of its parent domains is in `domains`
+fn matches_any(domain: &str, domains: &HashSet<String>) -> bool {
+    let mut candidate = domain;
+    loop {
+        if domains.contains(candidate) {
+            return true;
+        }
+        match candidate.split_once('.') {
+            Some((_, parent)) => candidate = parent,
+            None => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SignupPolicy;
+    use crate::configuration::{OptInMode, SignupPolicySettings};
+    use crate::domain::SubscriberEmail;
+    use claims::{assert_err, assert_ok};
+
+    fn settings() -> SignupPolicySettings {
+        SignupPolicySettings {
+            disposable_domains_path: "configuration/disposable_domains.txt".into(),
+            reject_role_accounts: true,
+            allowed_domains: vec![],
+            denied_domains: vec![],
+            opt_in: OptInMode::Double,
+        }
+    }
+
+    fn check(settings: SignupPolicySettings, email: &str) -> Result<(), String> {
+        SignupPolicy::from_settings(&settings)
+            .unwrap()
+            .check(&SubscriberEmail::parse(email.into()).unwrap())
+    }
+
+    #[test]
+    fn a_personal_address_is_accepted() {
+        assert_ok!(check(settings(), "ursula@domain.com"));
+    }
+
+    #[test]
+    fn disposable_domains_and_their_subdomains_are_rejected() {
+        assert_err!(check(settings(), "ursula@mailinator.com"));
+        assert_err!(check(settings(), "ursula@eu.Mailinator.com"));
+    }
+
+    #[test]
+    fn role_accounts_are_rejected_when_configured() {
+        assert_err!(check(settings(), "postmaster@domain.com"));
+        assert_err!(check(settings(), "NoReply+news@domain.com"));
+        let settings = SignupPolicySettings {
+            reject_role_accounts: false,
+            ..settings()
+        };
+        assert_ok!(check(settings, "postmaster@domain.com"));
+    }
+
+    #[test]
+    fn denied_domains_are_rejected() {
+        let settings = SignupPolicySettings {
+            denied_domains: vec!["competitor.com".into()],
+            ..settings()
+        };
+        assert_err!(check(settings.clone(), "ursula@competitor.com"));
+        assert_ok!(check(settings, "ursula@domain.com"));
+    }
+
+    #[test]
+    fn only_allowed_domains_are_accepted_when_configured() {
+        let settings = SignupPolicySettings {
+            allowed_domains: vec!["@Example.com".into()],
+            ..settings()
+        };
+        assert_ok!(check(settings.clone(), "ursula@example.com"));
+        assert_ok!(check(settings.clone(), "ursula@mail.example.com"));
+        assert_err!(check(settings, "ursula@domain.com"));
+    }
+
+    #[test]
+    fn each_rule_explains_the_rejection() {
+        let settings = SignupPolicySettings {
+            denied_domains: vec!["competitor.com".into()],
+            ..settings()
+        };
+        let policy = SignupPolicy::from_settings(&settings).unwrap();
+        let message = |email: &str| {
+            policy
+                .check(&SubscriberEmail::parse(email.into()).unwrap())
+                .unwrap_err()
+        };
+        assert_eq!(
+            message("ursula@competitor.com"),
+            "Signups from competitor.com are not accepted."
+        );
+        assert_eq!(
+            message("ursula@yopmail.com"),
+            "Disposable email addresses are not accepted."
+        );
+        assert!(message("abuse@domain.com").starts_with("Role accounts such as abuse@"));
+    }
+}
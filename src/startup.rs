@@ -1,99 +1,375 @@
+use crate::captcha::captcha_verifier;
+use crate::cleanup::PendingSubscriberCleanup;
+use crate::client_ip::TrustedProxies;
 use crate::configuration::DatabaseSettings;
 use crate::configuration::Settings;
 use crate::email_client::EmailClient;
-use crate::routes::confirm;
+use crate::email_templates::ConfirmationEmailTemplates;
+use crate::markdown::EmailLayout;
+use crate::newsletter_delivery::NewsletterDeliveryWorker;
+use crate::onboarding::OnboardingWorker;
+use crate::outbox::OutboxDispatcher;
+use crate::rate_limit::RateLimiter;
+use crate::routes::add_onboarding_email;
+use crate::routes::add_suppression;
+use crate::routes::api_subscribe;
+use crate::routes::archive;
+use crate::routes::archived_issue;
+use crate::routes::atom_feed;
+use crate::routes::erase_subscriber_data;
+use crate::routes::export_subscriber_data;
+use crate::routes::export_subscribers;
+use crate::routes::get_subscriber;
 use crate::routes::health_check;
+use crate::routes::import_subscribers;
+use crate::routes::json_error_handler;
+use crate::routes::list_onboarding_emails;
+use crate::routes::list_subscribers;
+use crate::routes::list_suppressions;
+use crate::routes::newsletter_engagement;
+use crate::routes::postmark_webhook;
 use crate::routes::publish_newsletter;
+use crate::routes::readiness;
+use crate::routes::remove_onboarding_email;
+use crate::routes::remove_subscriber;
+use crate::routes::remove_suppression;
+use crate::routes::rss_feed;
+use crate::routes::signup_challenge;
 use crate::routes::subscribe;
-use actix_web::{dev::Server, web, App, HttpServer};
+use crate::routes::track_click;
+use crate::routes::track_open;
+use crate::routes::update_onboarding_email;
+use crate::routes::update_subscriber;
+use crate::routes::{confirm, request_new_confirmation_link};
+use crate::routes::{unsubscribe, unsubscribe_form};
+use crate::shutdown::{termination_requested, InFlightRequests, Shutdown};
+use crate::signup_policy::SignupPolicy;
+use actix_web::dev::{Server, Service};
+use actix_web::{web, App, HttpServer};
+use sqlx::migrate::Migrator;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 use std::net::TcpListener;
+use std::time::Duration;
+use tokio::task::JoinHandle;
 use tracing_actix_web::TracingLogger;
 
 pub struct Application {
     port: u16,
     server: Server,
+    workers: Vec<JoinHandle<()>>,
+    in_flight: InFlightRequests,
+    shutdown: Shutdown,
+    shutdown_timeout: Duration,
 }
 
 pub struct ApplicationBaseUrl(pub String);
 
 impl Application {
-    pub async fn build(configuration: Settings) -> Result<Self, std::io::Error> {
+    pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
         let db_pool = get_connection_pool(&configuration.database);
 
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address.");
-
-        let email_timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            configuration.email_client.authorization_token,
-            configuration.email_client.base_url,
-            sender_email,
-            email_timeout,
-        );
+        let email_client = configuration.email_client.clone().client();
+
+        let newsletter_layout = EmailLayout::from_file(&configuration.newsletter.layout_path)?;
+        let confirmation_email_templates =
+            ConfirmationEmailTemplates::load(&configuration.confirmation_email)?;
+        let signup_policy = SignupPolicy::from_settings(&configuration.signup_policy)?;
 
         let address = format!(
             "{}:{}",
             configuration.application.host, configuration.application.port
         );
 
+        let shutdown = Shutdown::new();
+        let shutdown_timeout = configuration.shutdown.timeout();
+        let mut workers = Vec::new();
+        if configuration.pending_cleanup.enabled {
+            let cleanup = PendingSubscriberCleanup::build(&configuration)?;
+            workers.push(tokio::spawn(cleanup.run_until_stopped(shutdown.signal())));
+        }
+        if configuration.onboarding.enabled {
+            let onboarding = OnboardingWorker::build(&configuration);
+            workers.push(tokio::spawn(
+                onboarding.run_until_stopped(shutdown.signal()),
+            ));
+        }
+        if configuration.email_outbox.enabled {
+            let dispatcher = OutboxDispatcher::build(&configuration);
+            workers.push(tokio::spawn(
+                dispatcher.run_until_stopped(shutdown.signal()),
+            ));
+        }
+        if configuration.newsletter_delivery.enabled {
+            let delivery = NewsletterDeliveryWorker::build(&configuration);
+            workers.push(tokio::spawn(delivery.run_until_stopped(shutdown.signal())));
+        }
+
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
 
+        let rate_limiter = web::Data::new(RateLimiter::from_settings(
+            &configuration.rate_limit,
+            db_pool.clone(),
+            configuration.suppression.email_hasher(),
+        ));
+        if configuration.rate_limit.enabled {
+            workers.push(tokio::spawn(
+                rate_limiter
+                    .clone()
+                    .into_inner()
+                    .prune_until_stopped(shutdown.signal()),
+            ));
+        }
+
+        let in_flight = InFlightRequests::default();
         let server = run(
             listener,
             db_pool,
             email_client,
-            configuration.application.base_url,
+            configuration,
+            newsletter_layout,
+            confirmation_email_templates,
+            signup_policy,
+            rate_limiter,
+            in_flight.clone(),
         )?;
 
         // "Save" the bound port in one of the `Application's` fields
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            workers,
+            in_flight,
+            shutdown,
+            shutdown_timeout,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    /// Stops the application as `SIGTERM` would
+    pub fn shutdown(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Serve until `SIGTERM`, Ctrl-C or `Application::shutdown`, then stop accepting requests
+    /// and give in-flight ones and the background workers `shutdown.timeout_seconds` to finish.
+    ///
+    /// Workers still running after that are aborted, rolling back and so releasing their claims.
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        let server_handle = self.server.handle();
+        let mut server = tokio::spawn(self.server);
+        let mut shutdown_signal = self.shutdown.signal();
+        tokio::select! {
+            result = &mut server => {
+                // The server died on its own, there is nothing left to drain
+                self.shutdown.trigger();
+                for worker in self.workers {
+                    worker.abort();
+                }
+                return result.map_err(std::io::Error::other)?;
+            }
+            _ = termination_requested() => tracing::info!("Received a termination signal"),
+            _ = shutdown_signal.triggered() => {}
+        }
+        tracing::info!(
+            timeout_seconds = self.shutdown_timeout.as_secs(),
+            "Shutting down"
+        );
+        self.shutdown.trigger();
+
+        let aborts: Vec<_> = self.workers.iter().map(|w| w.abort_handle()).collect();
+        let drain = async {
+            server_handle.pause().await;
+            self.in_flight.drained().await;
+            server_handle.stop(true).await;
+            for worker in self.workers {
+                let _ = worker.await;
+            }
+            server.await
+        };
+        match tokio::time::timeout(self.shutdown_timeout, drain).await {
+            Ok(result) => {
+                tracing::info!("Shut down gracefully");
+                result.map_err(std::io::Error::other)?
+            }
+            Err(_) => {
+                tracing::warn!("Timed out waiting for in-flight work, aborting it");
+                for abort in aborts {
+                    abort.abort();
+                }
+                server_handle.stop(false).await;
+                Ok(())
+            }
+        }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn run(
     listener: TcpListener,
     db_pool: PgPool,
     email_client: EmailClient,
-    base_url: String,
+    configuration: Settings,
+    newsletter_layout: EmailLayout,
+    confirmation_email_templates: ConfirmationEmailTemplates,
+    signup_policy: SignupPolicy,
+    rate_limiter: web::Data<RateLimiter>,
+    in_flight: InFlightRequests,
 ) -> Result<Server, std::io::Error> {
+    let shutdown_timeout = configuration.shutdown.timeout_seconds;
     // Wrap the pool using web::Data which is an Arc smart pointer
     let db_pool = web::Data::new(db_pool);
+    let admin_settings = web::Data::new(configuration.admin);
+    let webhook_settings = web::Data::new(configuration.webhook);
+    let email_hasher = web::Data::new(configuration.suppression.email_hasher());
+    let outbox_dispatcher = web::Data::new(OutboxDispatcher::new(
+        db_pool.get_ref().clone(),
+        email_client.clone(),
+        email_hasher.get_ref().clone(),
+        configuration.email_outbox,
+    ));
     let email_client = web::Data::new(email_client);
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let base_url = web::Data::new(ApplicationBaseUrl(configuration.application.base_url));
+    let newsletter_layout = web::Data::new(newsletter_layout);
+    let confirmation_email_templates = web::Data::new(confirmation_email_templates);
+    let confirmation_link_settings = web::Data::new(configuration.confirmation_link);
+    let newsletter_settings = web::Data::new(configuration.newsletter);
+    let signup_policy = web::Data::new(signup_policy);
+    let trusted_proxies = web::Data::new(TrustedProxies::new(
+        configuration.rate_limit.trusted_proxies,
+    ));
+    let captcha_verifier = web::Data::new(captcha_verifier(&configuration.captcha));
+    let tracking_settings = web::Data::new(configuration.tracking);
+    let readiness_settings = web::Data::new(configuration.readiness);
 
     // Define the server with the correct listener
     let server = HttpServer::new(move || {
         App::new()
             // All middlewares are added with the wrap command
             .wrap(TracingLogger::default())
+            .wrap_fn({
+                let in_flight = in_flight.clone();
+                move |request, service| {
+                    let guard = in_flight.start();
+                    let response = service.call(request);
+                    async move {
+                        let response = response.await;
+                        drop(guard);
+                        response
+                    }
+                }
+            })
+            .route("/admin/subscribers", web::get().to(list_subscribers))
+            .route(
+                "/admin/subscribers/export",
+                web::get().to(export_subscribers),
+            )
+            .route(
+                "/admin/subscribers/import",
+                web::post().to(import_subscribers),
+            )
+            .route("/admin/subscribers/{id}", web::get().to(get_subscriber))
+            .route(
+                "/admin/subscribers/{id}",
+                web::patch().to(update_subscriber),
+            )
+            .route(
+                "/admin/subscribers/{id}",
+                web::delete().to(remove_subscriber),
+            )
+            .route(
+                "/admin/onboarding/emails",
+                web::get().to(list_onboarding_emails),
+            )
+            .route(
+                "/admin/onboarding/emails",
+                web::post().to(add_onboarding_email),
+            )
+            .route(
+                "/admin/onboarding/emails/{id}",
+                web::put().to(update_onboarding_email),
+            )
+            .route(
+                "/admin/onboarding/emails/{id}",
+                web::delete().to(remove_onboarding_email),
+            )
+            .route("/admin/suppressions", web::get().to(list_suppressions))
+            .route("/admin/suppressions", web::post().to(add_suppression))
+            .route(
+                "/admin/suppressions/{id}",
+                web::delete().to(remove_suppression),
+            )
+            .service(
+                web::resource("/api/v1/subscriptions")
+                    .app_data(web::JsonConfig::default().error_handler(json_error_handler))
+                    .route(web::post().to(api_subscribe)),
+            )
+            .route("/archive", web::get().to(archive))
+            .route("/archive/{slug}", web::get().to(archived_issue))
+            .route("/feed.atom", web::get().to(atom_feed))
+            .route("/feed.rss", web::get().to(rss_feed))
             .route("/health-check", web::get().to(health_check))
+            .route("/ready", web::get().to(readiness))
             .route("/newsletters", web::post().to(publish_newsletter))
+            .route(
+                "/newsletters/{newsletter_issue_id}/engagement",
+                web::get().to(newsletter_engagement),
+            )
             .route("/subscriptions", web::post().to(subscribe))
+            .route("/subscriptions/challenge", web::get().to(signup_challenge))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route(
+                "/subscriptions/confirm",
+                web::post().to(request_new_confirmation_link),
+            )
+            .route("/subscriptions/data", web::get().to(export_subscriber_data))
+            .route(
+                "/subscriptions/data",
+                web::delete().to(erase_subscriber_data),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route("/t/c/{token}", web::get().to(track_click))
+            .route("/t/o/{token}", web::get().to(track_open))
+            .route("/webhooks/postmark", web::post().to(postmark_webhook))
             // Register the connection as part of the application state
             .app_data(db_pool.clone())
+            .app_data(admin_settings.clone())
+            .app_data(webhook_settings.clone())
             .app_data(email_client.clone())
+            .app_data(email_hasher.clone())
+            .app_data(outbox_dispatcher.clone())
             .app_data(base_url.clone())
+            .app_data(newsletter_layout.clone())
+            .app_data(confirmation_email_templates.clone())
+            .app_data(confirmation_link_settings.clone())
+            .app_data(newsletter_settings.clone())
+            .app_data(signup_policy.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(trusted_proxies.clone())
+            .app_data(captcha_verifier.clone())
+            .app_data(tracking_settings.clone())
+            .app_data(readiness_settings.clone())
     })
     .listen(listener)?
+    // `Application::run_until_stopped` handles signals, to stop the workers too
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout)
     .run();
 
     Ok(server)
 }
 
+/// The migrations this build expects, embedded at compile time
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
 pub fn get_connection_pool(db_config: &DatabaseSettings) -> PgPool {
     PgPoolOptions::new()
         .acquire_timeout(std::time::Duration::from_secs(2))
@@ -0,0 +1,82 @@
+use actix_web::http::header::USER_AGENT;
+use actix_web::HttpRequest;
+use chrono::Utc;
+use sqlx::{Executor, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::client_ip::client_ip;
+
+/// A transition in the lifecycle of a subscription
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionEventKind {
+    Subscribed,
+    ConfirmationSent,
+    Confirmed,
+    Unsubscribed,
+    Bounced,
+}
+
+impl SubscriptionEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionEventKind::Subscribed => "subscribed",
+            SubscriptionEventKind::ConfirmationSent => "confirmation_sent",
+            SubscriptionEventKind::Confirmed => "confirmed",
+            SubscriptionEventKind::Unsubscribed => "unsubscribed",
+            SubscriptionEventKind::Bounced => "bounced",
+        }
+    }
+}
+
+/// Who triggered a subscription event, and through which endpoint
+#[derive(Debug, Clone)]
+pub struct EventSource {
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub source: String,
+}
+
+impl EventSource {
+    pub fn from_request(request: &HttpRequest) -> Self {
+        Self {
+            ip_address: client_ip(request).map(|ip| ip.to_string()),
+            user_agent: request
+                .headers()
+                .get(USER_AGENT)
+                .and_then(|h| h.to_str().ok())
+                .map(ToOwned::to_owned),
+            source: format!("{} {}", request.method(), request.path()),
+        }
+    }
+}
+
+/// Append an event to the subscriber's history, as part of the transaction changing their state
+#[tracing::instrument(name = "Record a subscription event", skip(txn, event_source))]
+pub async fn record_subscription_event(
+    txn: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    kind: SubscriptionEventKind,
+    event_source: &EventSource,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        Insert Into subscription_events (
+            subscriber_id,
+            kind,
+            occurred_at,
+            ip_address,
+            user_agent,
+            source
+        )
+        Values ($1, $2, $3, $4, $5, $6)
+        "#,
+        subscriber_id,
+        kind.as_str(),
+        Utc::now(),
+        event_source.ip_address,
+        event_source.user_agent,
+        event_source.source
+    );
+    txn.execute(query).await?;
+    Ok(())
+}
@@ -0,0 +1,227 @@
+use std::collections::HashSet;
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+
+use crate::domain::SubscriberEmail;
+
+/// Why an address or domain ended up on the suppression list
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuppressionReason {
+    Unsubscribed,
+    Bounced,
+    Complained,
+    Manual,
+}
+
+impl SuppressionReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SuppressionReason::Unsubscribed => "unsubscribed",
+            SuppressionReason::Bounced => "bounced",
+            SuppressionReason::Complained => "complained",
+            SuppressionReason::Manual => "manual",
+        }
+    }
+}
+
+/// What a suppression applies to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SuppressionTarget {
+    Address(String),
+    Domain(String),
+}
+
+impl SuppressionTarget {
+    /// Suppressions are matched on the normalized form of the address
+    pub fn address(email: &SubscriberEmail) -> Self {
+        SuppressionTarget::Address(email.normalized())
+    }
+
+    /// Internationalised domains are stored in punycode, like normalized addresses
+    pub fn domain(domain: &str) -> Self {
+        let domain = domain.trim().trim_start_matches('@').to_lowercase();
+        SuppressionTarget::Domain(idna::domain_to_ascii(&domain).unwrap_or(domain))
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SuppressionTarget::Address(_) => "address",
+            SuppressionTarget::Domain(_) => "domain",
+        }
+    }
+
+    pub fn value(&self) -> &str {
+        match self {
+            SuppressionTarget::Address(value) | SuppressionTarget::Domain(value) => value,
+        }
+    }
+}
+
+/// Add `target` to the suppression list, keeping the original entry if it is already there
+#[tracing::instrument(name = "Suppress an address or domain", skip(txn))]
+pub async fn suppress(
+    txn: &mut Transaction<'_, Postgres>,
+    target: &SuppressionTarget,
+    reason: SuppressionReason,
+    note: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        Insert Into suppressions (kind, value, reason, note, created_at)
+        Values ($1, $2, $3, $4, $5)
+        On Conflict (kind, value) Do Nothing
+        "#,
+        target.kind(),
+        target.value(),
+        reason.as_str(),
+        note,
+        Utc::now()
+    );
+    txn.execute(query).await?;
+    Ok(())
+}
+
+/// Whether we must not email `email`: its address or domain is suppressed, or it was erased
+#[tracing::instrument(
+    name = "Check whether an email address is suppressed",
+    skip(db_pool, email_hasher)
+)]
+pub async fn is_suppressed(
+    db_pool: &PgPool,
+    email_hasher: &EmailHasher,
+    email: &SubscriberEmail,
+) -> Result<bool, sqlx::Error> {
+    let address = SuppressionTarget::address(email);
+    let domain = SuppressionTarget::domain(&email.normalized_domain());
+    let row = sqlx::query!(
+        r#"
+        Select Exists (
+            Select 1 From suppressions
+            Where (kind = 'address' And value = $1) Or (kind = 'domain' And value = $2)
+        ) Or Exists (
+            Select 1 From suppressed_email_hashes Where email_hash = $3
+        ) As "suppressed!"
+        "#,
+        address.value(),
+        domain.value(),
+        email_hasher.hash(&email.normalized())
+    )
+    .fetch_one(db_pool)
+    .await?;
+    Ok(row.suppressed)
+}
+
+/// The normalized form of every address in `emails` that we must not email, in one query
+#[tracing::instrument(
+    name = "Check a batch of email addresses against the suppression list",
+    skip(db_pool, email_hasher, emails)
+)]
+pub async fn suppressed_addresses(
+    db_pool: &PgPool,
+    email_hasher: &EmailHasher,
+    emails: &[SubscriberEmail],
+) -> Result<HashSet<String>, sqlx::Error> {
+    let addresses: Vec<String> = emails.iter().map(|e| e.normalized()).collect();
+    let domains: Vec<String> = emails
+        .iter()
+        .map(|e| {
+            SuppressionTarget::domain(&e.normalized_domain())
+                .value()
+                .to_owned()
+        })
+        .collect();
+    let hashes: Vec<String> = addresses.iter().map(|a| email_hasher.hash(a)).collect();
+    let rows = sqlx::query!(
+        r#"
+        Select e.address As "address!"
+        From Unnest($1::text[], $2::text[], $3::text[]) As e(address, domain, email_hash)
+        Where Exists (
+            Select 1 From suppressions s
+            Where (s.kind = 'address' And s.value = e.address)
+                Or (s.kind = 'domain' And s.value = e.domain)
+        ) Or Exists (
+            Select 1 From suppressed_email_hashes h Where h.email_hash = e.email_hash
+        )
+        "#,
+        &addresses,
+        &domains,
+        &hashes
+    )
+    .fetch_all(db_pool)
+    .await?;
+    Ok(rows.into_iter().map(|row| row.address).collect())
+}
+
+/// Keyed one-way fingerprints of addresses, insensitive to case and surrounding whitespace.
+///
+/// Erased subscribers are suppressed by fingerprint so that we do not keep their address around.
+/// A plain hash could be reversed by hashing a list of likely addresses, the key prevents it.
+#[derive(Clone)]
+pub struct EmailHasher {
+    key: Secret<String>,
+}
+
+impl EmailHasher {
+    pub fn new(key: Secret<String>) -> Self {
+        Self { key }
+    }
+
+    pub fn hash(&self, email: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.key.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size.");
+        mac.update(email.trim().to_lowercase().as_bytes());
+        format!("{:x}", mac.finalize().into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EmailHasher, SuppressionTarget};
+    use crate::domain::SubscriberEmail;
+    use secrecy::Secret;
+
+    fn hasher(key: &str) -> EmailHasher {
+        EmailHasher::new(Secret::new(key.into()))
+    }
+
+    #[test]
+    fn targets_are_normalised() {
+        assert_eq!(
+            SuppressionTarget::address(
+                &SubscriberEmail::parse(" Ursula@Domain.COM ".into()).unwrap()
+            ),
+            SuppressionTarget::Address("ursula@domain.com".into())
+        );
+        assert_eq!(
+            SuppressionTarget::domain("@Example.ORG"),
+            SuppressionTarget::Domain("example.org".into())
+        );
+        assert_eq!(
+            SuppressionTarget::domain("Bücher.example"),
+            SuppressionTarget::Domain("xn--bcher-kva.example".into())
+        );
+    }
+
+    #[test]
+    fn the_email_hash_ignores_case_and_whitespace() {
+        let hasher = hasher("key");
+        assert_eq!(
+            hasher.hash("Ursula@Domain.com "),
+            hasher.hash("ursula@domain.com")
+        );
+        assert!(!hasher.hash("ursula@domain.com").contains("ursula"));
+    }
+
+    #[test]
+    fn the_email_hash_depends_on_the_key() {
+        assert_ne!(
+            hasher("key").hash("ursula@domain.com"),
+            hasher("another key").hash("ursula@domain.com")
+        );
+    }
+}
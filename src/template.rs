@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+
+/// How substituted values are written into the rendered output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemplateFormat {
+    /// Values are HTML-escaped before being inserted
+    Html,
+    /// Values are inserted verbatim
+    Text,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Variable(String),
+}
+
+/// A parsed template using `{{ variable }}` placeholders.
+///
+/// Parsing validates the syntax up front so that rendering can never fail.
+#[derive(Debug, Clone)]
+pub struct Template {
+    format: TemplateFormat,
+    segments: Vec<Segment>,
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum TemplateError {
+    #[error("Unclosed `{{{{` at line {line}, column {column}.")]
+    UnclosedTag { line: usize, column: usize },
+    #[error("Unexpected `}}}}` at line {line}, column {column}.")]
+    UnexpectedClosingTag { line: usize, column: usize },
+    #[error("`{name}` at line {line}, column {column} is not a valid variable name.")]
+    InvalidVariableName {
+        name: String,
+        line: usize,
+        column: usize,
+    },
+}
+
+/// The values available to a template while it is being rendered.
+///
+/// Variables missing from the context render as an empty string.
+#[derive(Debug, Clone, Default)]
+pub struct TemplateContext(HashMap<String, String>);
+
+impl TemplateContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.0.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+}
+
+impl Template {
+    pub fn parse(source: &str, format: TemplateFormat) -> Result<Template, TemplateError> {
+        let mut segments = Vec::new();
+        let mut rest = source;
+        let mut offset = 0;
+
+        while !rest.is_empty() {
+            let open = rest.find("{{");
+            let close = rest.find("}}");
+
+            match (open, close) {
+                // A closing tag before any opening tag is always a typo
+                (Some(o), Some(c)) if c < o => {
+                    return Err(unexpected_closing_tag(source, offset + c));
+                }
+                (None, Some(c)) => {
+                    return Err(unexpected_closing_tag(source, offset + c));
+                }
+                (None, None) => {
+                    segments.push(Segment::Literal(rest.to_string()));
+                    break;
+                }
+                (Some(o), _) => {
+                    if o > 0 {
+                        segments.push(Segment::Literal(rest[..o].to_string()));
+                    }
+                    let after_open = &rest[o + 2..];
+                    let end = match (after_open.find("}}"), after_open.find("{{")) {
+                        (Some(end), Some(next)) if next < end => {
+                            return Err(unclosed_tag(source, offset + o));
+                        }
+                        (Some(end), _) => end,
+                        (None, _) => return Err(unclosed_tag(source, offset + o)),
+                    };
+                    let name = after_open[..end].trim();
+                    if !is_valid_variable_name(name) {
+                        let (line, column) = line_and_column(source, offset + o);
+                        return Err(TemplateError::InvalidVariableName {
+                            name: name.to_string(),
+                            line,
+                            column,
+                        });
+                    }
+                    segments.push(Segment::Variable(name.to_string()));
+
+                    let consumed = o + 2 + end + 2;
+                    rest = &rest[consumed..];
+                    offset += consumed;
+                }
+            }
+        }
+
+        Ok(Self { format, segments })
+    }
+
+    /// Render the template, substituting every placeholder from `context`
+    pub fn render(&self, context: &TemplateContext) -> String {
+        let mut output = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => output.push_str(literal),
+                Segment::Variable(name) => {
+                    let value = context.get(name).unwrap_or_default();
+                    match self.format {
+                        TemplateFormat::Html => output.push_str(&escape_html(value)),
+                        TemplateFormat::Text => output.push_str(value),
+                    }
+                }
+            }
+        }
+        output
+    }
+
+    /// The names of the variables referenced by the template, in order of appearance
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        self.segments.iter().filter_map(|s| match s {
+            Segment::Variable(name) => Some(name.as_str()),
+            Segment::Literal(_) => None,
+        })
+    }
+}
+
+/// Escape the characters with a special meaning in HTML text and attribute values
+pub fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn is_valid_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+fn unclosed_tag(source: &str, position: usize) -> TemplateError {
+    let (line, column) = line_and_column(source, position);
+    TemplateError::UnclosedTag { line, column }
+}
+
+fn unexpected_closing_tag(source: &str, position: usize) -> TemplateError {
+    let (line, column) = line_and_column(source, position);
+    TemplateError::UnexpectedClosingTag { line, column }
+}
+
+/// Convert a byte offset into a 1-based line and column pair for error messages
+fn line_and_column(source: &str, position: usize) -> (usize, usize) {
+    let before = &source[..position];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .map(|l| l.chars().count())
+        .unwrap_or_default()
+        + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Template, TemplateContext, TemplateError, TemplateFormat};
+    use claims::{assert_err, assert_ok};
+
+    fn context() -> TemplateContext {
+        let mut context = TemplateContext::new();
+        context
+            .insert("name", "Daniel <Furman>")
+            .insert("email", "djfurman@users.noreply.github.com");
+        context
+    }
+
+    #[test]
+    fn a_template_without_placeholders_renders_unchanged() {
+        let template = Template::parse("Hello there!", TemplateFormat::Text).unwrap();
+        assert_eq!(template.render(&context()), "Hello there!");
+    }
+
+    #[test]
+    fn placeholders_are_substituted_with_or_without_whitespace() {
+        let template = Template::parse("Hi {{ name }}, {{email}}", TemplateFormat::Text).unwrap();
+        assert_eq!(
+            template.render(&context()),
+            "Hi Daniel <Furman>, djfurman@users.noreply.github.com"
+        );
+    }
+
+    #[test]
+    fn values_are_escaped_in_html_templates() {
+        let template = Template::parse("<p>Hi {{ name }}</p>", TemplateFormat::Html).unwrap();
+        assert_eq!(
+            template.render(&context()),
+            "<p>Hi Daniel &lt;Furman&gt;</p>"
+        );
+    }
+
+    #[test]
+    fn missing_variables_render_as_empty() {
+        let template = Template::parse("[{{ company }}]", TemplateFormat::Text).unwrap();
+        assert_eq!(template.render(&context()), "[]");
+    }
+
+    #[test]
+    fn variables_are_listed_in_order() {
+        let template =
+            Template::parse("{{ name }} {{ unsubscribe_url }}", TemplateFormat::Text).unwrap();
+        let variables: Vec<_> = template.variables().collect();
+        assert_eq!(variables, vec!["name", "unsubscribe_url"]);
+    }
+
+    #[test]
+    fn an_unclosed_tag_is_rejected() {
+        let result = Template::parse("Hello\n  {{ name", TemplateFormat::Text);
+        assert_eq!(
+            result.unwrap_err(),
+            TemplateError::UnclosedTag { line: 2, column: 3 }
+        );
+    }
+
+    #[test]
+    fn a_nested_opening_tag_is_rejected() {
+        assert_err!(Template::parse("{{ name {{ email }}", TemplateFormat::Text));
+    }
+
+    #[test]
+    fn a_stray_closing_tag_is_rejected() {
+        assert_err!(Template::parse("Hello }} there", TemplateFormat::Text));
+    }
+
+    #[test]
+    fn invalid_variable_names_are_rejected() {
+        for source in [
+            "{{ }}",
+            "{{ 1st }}",
+            "{{ first name }}",
+            "{{ name | upper }}",
+        ] {
+            assert_err!(Template::parse(source, TemplateFormat::Text));
+        }
+    }
+
+    #[test]
+    fn single_braces_are_left_alone() {
+        assert_ok!(Template::parse(
+            "p { color: red; } {{ name }}",
+            TemplateFormat::Html
+        ));
+    }
+}
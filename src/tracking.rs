@@ -0,0 +1,206 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// Identifies the recipient of an issue, and for clicks the link they followed.
+///
+/// Tokens are signed so that the click redirect cannot be abused as an open redirect and
+/// so that nobody can forge engagement for somebody else.
+#[derive(Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TrackingToken {
+    #[serde(rename = "i")]
+    pub newsletter_issue_id: Uuid,
+    #[serde(rename = "s")]
+    pub subscriber_id: Uuid,
+    #[serde(rename = "u", skip_serializing_if = "Option::is_none", default)]
+    pub url: Option<String>,
+}
+
+impl TrackingToken {
+    /// Encode the token as `<payload>.<signature>`, both URL-safe base64
+    pub fn encode(&self, signing_key: &Secret<String>) -> String {
+        let payload = URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(self).expect("Failed to serialize a tracking token."));
+        let signature = URL_SAFE_NO_PAD.encode(mac(signing_key, &payload).finalize().into_bytes());
+        format!("{}.{}", payload, signature)
+    }
+
+    pub fn decode(token: &str, signing_key: &Secret<String>) -> Result<TrackingToken, String> {
+        let (payload, signature) = token
+            .split_once('.')
+            .ok_or_else(|| "The tracking token is malformed.".to_string())?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| "The tracking token signature is malformed.".to_string())?;
+        mac(signing_key, payload)
+            .verify_slice(&signature)
+            .map_err(|_| "The tracking token signature is invalid.".to_string())?;
+
+        let payload = URL_SAFE_NO_PAD
+            .decode(payload)
+            .map_err(|_| "The tracking token payload is malformed.".to_string())?;
+        serde_json::from_slice(&payload)
+            .map_err(|_| "The tracking token payload is malformed.".to_string())
+    }
+}
+
+fn mac(signing_key: &Secret<String>, payload: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(signing_key.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size.");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+/// Replace the target of every absolute `http(s)` link in `html` with the output of `rewrite`.
+///
+/// Links for which `rewrite` returns `None` are left untouched.
+pub fn rewrite_links(html: &str, rewrite: impl Fn(&str) -> Option<String>) -> String {
+    let mut rewritten = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = find_href(rest) {
+        let (before, after) = rest.split_at(start);
+        rewritten.push_str(before);
+        let quote = after.as_bytes()[0] as char;
+        let Some(end) = after[1..].find(quote) else {
+            rest = after;
+            break;
+        };
+        let escaped_url = &after[1..end + 1];
+        let url = unescape_html(escaped_url);
+        let is_web_link = url.starts_with("https://") || url.starts_with("http://");
+        let new_url = if is_web_link { rewrite(&url) } else { None };
+        match new_url {
+            Some(new_url) => rewritten.push_str(&format!(
+                "{}{}{}",
+                quote,
+                crate::template::escape_html(&new_url),
+                quote
+            )),
+            None => rewritten.push_str(&after[..end + 2]),
+        }
+        rest = &after[end + 2..];
+    }
+    rewritten.push_str(rest);
+    rewritten
+}
+
+/// The offset of the opening quote of the next `href` attribute value
+fn find_href(html: &str) -> Option<usize> {
+    let lowercase = html.to_ascii_lowercase();
+    let mut offset = 0;
+    while let Some(position) = lowercase[offset..].find("href") {
+        let start = offset + position + 4;
+        let after_name = lowercase[start..].trim_start();
+        if let Some(after_equals) = after_name.strip_prefix('=') {
+            let value = after_equals.trim_start();
+            if value.starts_with('"') || value.starts_with('\'') {
+                return Some(lowercase.len() - value.len());
+            }
+        }
+        offset = start;
+    }
+    None
+}
+
+fn unescape_html(s: &str) -> String {
+    s.replace("&quot;", "\"")
+        .replace("&#x27;", "'")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Add an invisible image loading `pixel_url` at the end of the email body
+pub fn add_open_pixel(html: &str, pixel_url: &str) -> String {
+    let pixel = format!(
+        "<img src=\"{}\" width=\"1\" height=\"1\" alt=\"\" style=\"display: block; border: 0;\" />",
+        crate::template::escape_html(pixel_url)
+    );
+    match html.to_ascii_lowercase().rfind("</body>") {
+        Some(position) => format!("{}{}\n{}", &html[..position], pixel, &html[position..]),
+        None => format!("{}\n{}", html, pixel),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add_open_pixel, rewrite_links, TrackingToken};
+    use claims::assert_err;
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn key() -> Secret<String> {
+        Secret::new("a-very-secret-key".into())
+    }
+
+    fn token() -> TrackingToken {
+        TrackingToken {
+            newsletter_issue_id: Uuid::new_v4(),
+            subscriber_id: Uuid::new_v4(),
+            url: Some("https://example.com/?a=1&b=2".into()),
+        }
+    }
+
+    #[test]
+    fn an_encoded_token_round_trips() {
+        let token = token();
+        let encoded = token.encode(&key());
+        assert_eq!(TrackingToken::decode(&encoded, &key()).unwrap(), token);
+    }
+
+    #[test]
+    fn a_token_signed_with_another_key_is_rejected() {
+        let encoded = token().encode(&Secret::new("another-key".into()));
+        assert_err!(TrackingToken::decode(&encoded, &key()));
+    }
+
+    #[test]
+    fn a_tampered_payload_is_rejected() {
+        let encoded = token().encode(&key());
+        let (_, signature) = encoded.split_once('.').unwrap();
+        let forged = TrackingToken {
+            url: Some("https://evil.example.com".into()),
+            ..token()
+        }
+        .encode(&key());
+        let (forged_payload, _) = forged.split_once('.').unwrap();
+        assert_err!(TrackingToken::decode(
+            &format!("{}.{}", forged_payload, signature),
+            &key()
+        ));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        for token in ["", "no-dot", "a.b", "!!!.???"] {
+            assert_err!(TrackingToken::decode(token, &key()));
+        }
+    }
+
+    #[test]
+    fn only_web_links_are_rewritten() {
+        let html = r#"<a href="https://example.com/?a=1&amp;b=2">x</a> <a HREF='mailto:a@b.c'>y</a> <a href = "http://example.org">z</a>"#;
+        let rewritten = rewrite_links(html, |url| Some(format!("T[{}]", url)));
+        assert_eq!(
+            rewritten,
+            r#"<a href="T[https://example.com/?a=1&amp;b=2]">x</a> <a HREF='mailto:a@b.c'>y</a> <a href = "T[http://example.org]">z</a>"#
+        );
+    }
+
+    #[test]
+    fn links_can_be_skipped() {
+        let html = r#"<a href="https://example.com/unsubscribe">x</a>"#;
+        assert_eq!(rewrite_links(html, |_| None), html);
+    }
+
+    #[test]
+    fn the_open_pixel_goes_before_the_end_of_the_body() {
+        let html = add_open_pixel("<html><body><p>Hi</p></body></html>", "https://t/o/1");
+        assert!(html.starts_with("<html><body><p>Hi</p><img src=\"https://t/o/1\""));
+        assert!(html.ends_with("/>\n</body></html>"));
+    }
+}
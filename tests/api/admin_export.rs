@@ -0,0 +1,120 @@
+use reqwest::Method;
+
+use crate::helpers::{spawn_app, TestApp};
+
+/// Subscribers imported with consent are confirmed straight away
+async fn import_subscribers(app: &TestApp, csv: &str) {
+    app.admin_request(Method::POST, "/admin/subscribers/import")
+        .body(csv.to_owned())
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+async fn export(app: &TestApp, query: &str) -> reqwest::Response {
+    app.admin_request(Method::GET, &format!("/admin/subscribers/export?{}", query))
+        .send()
+        .await
+        .expect("Failed to execute request to export subscribers.")
+}
+
+#[tokio::test]
+async fn exporting_subscribers_requires_credentials() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/admin/subscribers/export", app.api_address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn subscribers_are_exported_as_csv_by_default() {
+    // Arrange
+    let app = spawn_app().await;
+    import_subscribers(
+        &app,
+        "email,name,consent\nursula@example.com,\"Le Guin, Ursula\",yes\n",
+    )
+    .await;
+
+    // Act
+    let response = export(&app, "").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    let body = response.text().await.unwrap();
+    let lines: Vec<&str> = body.lines().collect();
+    assert_eq!(
+        lines[0],
+        "id,email,name,status,subscribed_at,confirmed_at,opt_in_mode,attributes"
+    );
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].contains(",ursula@example.com,\"Le Guin, Ursula\",confirmed,"));
+    assert!(lines[1].contains(",imported,"));
+}
+
+#[tokio::test]
+async fn subscribers_are_exported_as_ndjson_with_their_confirmation_time() {
+    // Arrange
+    let app = spawn_app().await;
+    import_subscribers(
+        &app,
+        "email,name,consent\nursula@example.com,Ursula,yes\nalice@example.com,Alice,no\n",
+    )
+    .await;
+
+    // Act
+    let response = export(&app, "format=ndjson").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body = response.text().await.unwrap();
+    let subscribers: Vec<serde_json::Value> = body
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(subscribers.len(), 2);
+    let ursula = subscribers
+        .iter()
+        .find(|s| s["email"] == "ursula@example.com")
+        .unwrap();
+    let alice = subscribers
+        .iter()
+        .find(|s| s["email"] == "alice@example.com")
+        .unwrap();
+    assert_eq!(ursula["status"], "confirmed");
+    assert!(ursula["confirmed_at"].is_string());
+    assert_eq!(alice["status"], "pending_confirmation");
+    assert!(alice["confirmed_at"].is_null());
+}
+
+#[tokio::test]
+async fn exports_can_be_filtered() {
+    // Arrange
+    let app = spawn_app().await;
+    import_subscribers(
+        &app,
+        "email,name,consent\nursula@example.com,Ursula,yes\nalice@example.com,Alice,no\n",
+    )
+    .await;
+
+    // Act
+    let response = export(&app, "format=ndjson&status=pending_confirmation").await;
+
+    // Assert
+    let body = response.text().await.unwrap();
+    let lines: Vec<&str> = body.lines().collect();
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].contains("alice@example.com"));
+}
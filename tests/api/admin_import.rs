@@ -0,0 +1,297 @@
+use reqwest::Method;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::domain::{OptIn, SubscriptionStatus};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn import(app: &TestApp, query: &str, csv: &str) -> reqwest::Response {
+    app.admin_request(
+        Method::POST,
+        &format!("/admin/subscribers/import?{}", query),
+    )
+    .header("Content-Type", "text/csv")
+    .body(csv.to_owned())
+    .send()
+    .await
+    .expect("Failed to execute request to import subscribers.")
+}
+
+async fn import_report(app: &TestApp, query: &str, csv: &str) -> serde_json::Value {
+    let response = import(app, query, csv).await;
+    assert_eq!(response.status().as_u16(), 200);
+    response.json().await.unwrap()
+}
+
+async fn subscriber(app: &TestApp, email: &str) -> (String, SubscriptionStatus) {
+    let row = sqlx::query!(
+        r#"
+        Select name, status As "status: SubscriptionStatus"
+        From subscriptions
+        Where email_normalized = $1
+        "#,
+        email
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch the imported subscriber.");
+    (row.name, row.status)
+}
+
+#[tokio::test]
+async fn importing_subscribers_requires_credentials() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/subscribers/import", &app.api_address))
+        .body("email,name\nursula@example.com,Ursula\n")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn valid_rows_are_imported_without_sending_emails_during_the_upload() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let csv = "Name,Email,Consent\n\
+               Ursula Le Guin,Ursula@Example.com,yes\n\
+               Alice,alice@example.com,\n";
+
+    // Act
+    let report = import_report(&app, "", csv).await;
+
+    // Assert
+    assert_eq!(report["imported"], 2);
+    assert_eq!(report["confirmation_requested"], 1);
+    assert_eq!(report["failed"], 0);
+    assert_eq!(
+        subscriber(&app, "ursula@example.com").await,
+        ("Ursula Le Guin".into(), SubscriptionStatus::Confirmed)
+    );
+    assert_eq!(
+        subscriber(&app, "alice@example.com").await.1,
+        SubscriptionStatus::PendingConfirmation
+    );
+    let events = sqlx::query!(r#"Select kind, source From subscription_events Order By id"#)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(events.len(), 3);
+    assert!(events
+        .iter()
+        .all(|e| e.source == "POST /admin/subscribers/import"));
+}
+
+#[tokio::test]
+async fn subscribers_imported_without_consent_are_asked_to_confirm() {
+    // Arrange
+    let app = spawn_app().await;
+    let csv = "email,name,consent\n\
+               ursula@example.com,Ursula,yes\n\
+               alice@example.com,Alice,no\n";
+
+    // Act
+    import_report(&app, "", csv).await;
+
+    // Assert
+    let opt_ins = sqlx::query!(
+        r#"Select email, opt_in_mode As "opt_in_mode: OptIn" From subscriptions Order By email"#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    // Only the file's word for it when it asserted consent
+    assert_eq!(opt_ins[0].email, "alice@example.com");
+    assert_eq!(opt_ins[0].opt_in_mode, OptIn::Double);
+    assert_eq!(opt_ins[1].opt_in_mode, OptIn::Imported);
+    let outbox = sqlx::query!(r#"Select subscriber_id, recipient, kind From email_outbox"#)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(outbox.len(), 1);
+    assert_eq!(outbox[0].recipient, "alice@example.com");
+    assert_eq!(outbox[0].kind, "confirmation");
+    let token = sqlx::query!(
+        r#"Select subscription_token From subscription_tokens Where subscriber_id = $1"#,
+        outbox[0].subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .subscription_token;
+    let body = sqlx::query!(r#"Select text_body From email_outbox"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .text_body;
+    assert!(body.contains(&format!(
+        "/subscriptions/confirm?subscription_token={}",
+        token
+    )));
+}
+
+#[tokio::test]
+async fn single_opt_in_imports_confirm_every_row() {
+    // Arrange
+    let app = spawn_app().await;
+    let csv = "email,name\nalice@example.com,Alice\n";
+
+    // Act
+    let report = import_report(&app, "opt_in=single", csv).await;
+
+    // Assert
+    assert_eq!(report["imported"], 1);
+    assert_eq!(report["confirmation_requested"], 0);
+    let saved = sqlx::query!(
+        r#"
+        Select status As "status: SubscriptionStatus", opt_in_mode As "opt_in_mode: OptIn"
+        From subscriptions
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.status, SubscriptionStatus::Confirmed);
+    assert_eq!(saved.opt_in_mode, OptIn::Single);
+    let outbox = sqlx::query!(r#"Select Count(*) As "count!" From email_outbox"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(outbox.count, 0);
+}
+
+#[tokio::test]
+async fn invalid_rows_are_reported_and_the_others_imported() {
+    // Arrange
+    let app = spawn_app().await;
+    let csv = "email,name,consent\n\
+               not-an-email,Bob,no\n\
+               carol@example.com,,no\n\
+               dave@example.com,Dave,maybe\n\
+               erin@example.com,Erin,no\n\
+               ERIN@example.com,Erin again,no\n";
+
+    // Act
+    let report = import_report(&app, "", csv).await;
+
+    // Assert
+    assert_eq!(report["imported"], 1);
+    assert_eq!(report["failed"], 3);
+    assert_eq!(report["skipped"], 1);
+    let rows: Vec<u64> = report["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["row"].as_u64().unwrap())
+        .collect();
+    assert_eq!(rows, vec![1, 2, 3, 5]);
+}
+
+#[tokio::test]
+async fn the_error_report_can_be_downloaded_as_csv() {
+    // Arrange
+    let app = spawn_app().await;
+    let csv = "email,name\nnot-an-email,Bob\n";
+
+    // Act
+    let response = import(&app, "format=csv", csv).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    let body = response.text().await.unwrap();
+    assert!(body.starts_with("row,email,error\n1,not-an-email,"));
+}
+
+#[tokio::test]
+async fn existing_subscribers_are_skipped_or_updated_depending_on_the_mode() {
+    // Arrange
+    let app = spawn_app().await;
+    import_report(&app, "", "email,name\nursula@example.com,Ursula\n").await;
+    let csv = "email,name,consent\nursula@example.com,Ursula Le Guin,yes\n";
+
+    // Act
+    let skipped = import_report(&app, "mode=skip", csv).await;
+    let after_skip = subscriber(&app, "ursula@example.com").await;
+    let updated = import_report(&app, "mode=update", csv).await;
+    let after_update = subscriber(&app, "ursula@example.com").await;
+
+    // Assert
+    assert_eq!(skipped["skipped"], 1);
+    assert_eq!(
+        after_skip,
+        ("Ursula".into(), SubscriptionStatus::PendingConfirmation)
+    );
+    assert_eq!(updated["updated"], 1);
+    assert_eq!(
+        after_update,
+        ("Ursula Le Guin".into(), SubscriptionStatus::Confirmed)
+    );
+    let opt_in = sqlx::query!(r#"Select opt_in_mode As "opt_in_mode: OptIn" From subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .opt_in_mode;
+    assert_eq!(opt_in, OptIn::Imported);
+}
+
+#[tokio::test]
+async fn re_importing_the_same_details_does_not_count_as_an_update() {
+    // Arrange
+    let app = spawn_app().await;
+    let csv = "email,name,consent\nursula@example.com,Ursula,yes\nalice@example.com,Alice,no\n";
+    import_report(&app, "", csv).await;
+
+    // Act
+    let report = import_report(&app, "mode=update", csv).await;
+
+    // Assert
+    assert_eq!(report["updated"], 0);
+    assert_eq!(report["unchanged"], 2);
+    assert_eq!(report["errors"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn suppressed_addresses_are_not_imported() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_suppression(serde_json::json!({"kind": "domain", "value": "blocked.example"}))
+        .await
+        .error_for_status()
+        .unwrap();
+    let csv = "email,name\nursula@blocked.example,Ursula\nalice@example.com,Alice\n";
+
+    // Act
+    let report = import_report(&app, "", csv).await;
+
+    // Assert
+    assert_eq!(report["imported"], 1);
+    assert_eq!(report["skipped"], 1);
+    assert_eq!(report["errors"][0]["email"], "ursula@blocked.example");
+}
+
+#[tokio::test]
+async fn a_file_without_the_required_columns_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = import(&app, "", "address,full_name\nursula@example.com,Ursula\n").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -0,0 +1,269 @@
+use chrono::{DateTime, Duration, Utc};
+use reqwest::Method;
+use uuid::Uuid;
+use zero2prod::domain::SubscriptionStatus;
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn insert_subscriber(
+    app: &TestApp,
+    name: &str,
+    status: SubscriptionStatus,
+    subscribed_at: DateTime<Utc>,
+) -> Uuid {
+    let id = Uuid::new_v4();
+    let email = format!("{}@example.com", name.to_lowercase());
+    sqlx::query!(
+        r#"
+        Insert Into subscriptions (
+            id, email, email_normalized, name, subscribed_at, status, opt_in_mode
+        )
+        Values ($1, $2, $2, $3, $4, $5, 'double')
+        "#,
+        id,
+        email,
+        name,
+        subscribed_at,
+        status as SubscriptionStatus
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to insert a subscriber.");
+    id
+}
+
+async fn list(app: &TestApp, query: &str) -> serde_json::Value {
+    app.admin_request(Method::GET, &format!("/admin/subscribers?{}", query))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+fn names(page: &serde_json::Value) -> Vec<&str> {
+    page["subscribers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["name"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn listing_subscribers_requires_credentials() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(&format!("{}/admin/subscribers", app.api_address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn subscribers_are_paginated_newest_first_with_a_cursor() {
+    // Arrange
+    let app = spawn_app().await;
+    let now = Utc::now();
+    for (i, name) in ["Alice", "Bob", "Carol", "Dave", "Erin"].iter().enumerate() {
+        let subscribed_at = now - Duration::minutes(i as i64);
+        insert_subscriber(&app, name, SubscriptionStatus::Confirmed, subscribed_at).await;
+    }
+
+    // Act
+    let mut pages = vec![];
+    let mut query = "limit=2".to_string();
+    loop {
+        let page = list(&app, &query).await;
+        pages.push(names(&page).join(","));
+        match page["next_cursor"].as_str() {
+            Some(cursor) => query = format!("limit=2&cursor={}", cursor),
+            None => break,
+        }
+    }
+
+    // Assert
+    assert_eq!(pages, vec!["Alice,Bob", "Carol,Dave", "Erin"]);
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered_by_status_date_and_search_term() {
+    // Arrange
+    let app = spawn_app().await;
+    let now = Utc::now();
+    insert_subscriber(&app, "Alice", SubscriptionStatus::Confirmed, now).await;
+    insert_subscriber(&app, "Alicia", SubscriptionStatus::Unsubscribed, now).await;
+    insert_subscriber(
+        &app,
+        "Malice",
+        SubscriptionStatus::Confirmed,
+        now - Duration::days(30),
+    )
+    .await;
+    insert_subscriber(&app, "Bob", SubscriptionStatus::Confirmed, now).await;
+    let last_week = (now - Duration::days(7)).to_rfc3339().replace('+', "%2B");
+
+    // Act
+    let by_status = list(&app, "status=confirmed").await;
+    let by_search = list(&app, "q=ALIC").await;
+    let by_everything = list(
+        &app,
+        &format!("status=confirmed&q=alic&subscribed_after={}", last_week),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(names(&by_status).len(), 3);
+    assert_eq!(names(&by_search).len(), 3);
+    assert_eq!(names(&by_everything), vec!["Alice"]);
+}
+
+#[tokio::test]
+async fn search_terms_are_matched_literally() {
+    // Arrange
+    let app = spawn_app().await;
+    insert_subscriber(&app, "Alice", SubscriptionStatus::Confirmed, Utc::now()).await;
+
+    // Act
+    let page = list(&app, "q=%25").await;
+
+    // Assert
+    assert!(names(&page).is_empty());
+}
+
+#[tokio::test]
+async fn a_subscriber_can_be_renamed_with_a_valid_name_only() {
+    // Arrange
+    let app = spawn_app().await;
+    let id = insert_subscriber(&app, "Alice", SubscriptionStatus::Confirmed, Utc::now()).await;
+    let path = format!("/admin/subscribers/{}", id);
+
+    // Act
+    let invalid = app
+        .admin_request(Method::PATCH, &path)
+        .json(&serde_json::json!({ "name": "<script>" }))
+        .send()
+        .await
+        .unwrap();
+    let valid = app
+        .admin_request(Method::PATCH, &path)
+        .json(&serde_json::json!({ "name": "Alice Liddell" }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(invalid.status().as_u16(), 400);
+    assert_eq!(valid.status().as_u16(), 200);
+    let body: serde_json::Value = valid.json().await.unwrap();
+    assert_eq!(body["name"], "Alice Liddell");
+}
+
+#[tokio::test]
+async fn invalid_corrections_are_explained() {
+    // Arrange
+    let app = spawn_app().await;
+    let id = insert_subscriber(&app, "Alice", SubscriptionStatus::Confirmed, Utc::now()).await;
+
+    // Act
+    let response = app
+        .admin_request(Method::PATCH, &format!("/admin/subscribers/{}", id))
+        .json(&serde_json::json!({ "name": "<script>" }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/plain; charset=utf-8"
+    );
+    assert_eq!(
+        response.text().await.unwrap(),
+        "<script> is not a valid subscriber name."
+    );
+}
+
+#[tokio::test]
+async fn status_corrections_follow_the_subscription_lifecycle() {
+    // Arrange
+    let app = spawn_app().await;
+    let pending = insert_subscriber(
+        &app,
+        "Alice",
+        SubscriptionStatus::PendingConfirmation,
+        Utc::now(),
+    )
+    .await;
+    let unsubscribed =
+        insert_subscriber(&app, "Bob", SubscriptionStatus::Unsubscribed, Utc::now()).await;
+    let confirm = serde_json::json!({ "status": "confirmed" });
+
+    // Act
+    let allowed = app
+        .admin_request(Method::PATCH, &format!("/admin/subscribers/{}", pending))
+        .json(&confirm)
+        .send()
+        .await
+        .unwrap();
+    let refused = app
+        .admin_request(
+            Method::PATCH,
+            &format!("/admin/subscribers/{}", unsubscribed),
+        )
+        .json(&confirm)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(allowed.status().as_u16(), 200);
+    assert_eq!(refused.status().as_u16(), 409);
+    let events = sqlx::query!(
+        "Select kind, source From subscription_events Where subscriber_id = $1",
+        pending
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].kind, "confirmed");
+    assert_eq!(
+        events[0].source,
+        format!("PATCH /admin/subscribers/{}", pending)
+    );
+}
+
+#[tokio::test]
+async fn a_deleted_subscriber_is_gone() {
+    // Arrange
+    let app = spawn_app().await;
+    let id = insert_subscriber(&app, "Alice", SubscriptionStatus::Confirmed, Utc::now()).await;
+    let path = format!("/admin/subscribers/{}", id);
+
+    // Act
+    let deleted = app
+        .admin_request(Method::DELETE, &path)
+        .send()
+        .await
+        .unwrap();
+    let fetched = app.admin_request(Method::GET, &path).send().await.unwrap();
+    let deleted_again = app
+        .admin_request(Method::DELETE, &path)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(deleted.status().as_u16(), 204);
+    assert_eq!(fetched.status().as_u16(), 404);
+    assert_eq!(deleted_again.status().as_u16(), 404);
+}
@@ -0,0 +1,113 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn api_subscribe_returns_a_202_and_persists_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "name": "Daniel Furman",
+        "email": "djfurman@users.noreply.github.com"
+    });
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_api_subscription(body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    let saved = sqlx::query!("Select email, name From subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "djfurman@users.noreply.github.com");
+    assert_eq!(saved.name, "Daniel Furman");
+}
+
+#[tokio::test]
+async fn api_subscribe_reports_every_invalid_field_at_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "name": "",
+        "email": "definitely-not-an-email"
+    });
+
+    // Act
+    let response = app.post_api_subscription(body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let errors = body["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0]["field"], "name");
+    assert_eq!(errors[1]["field"], "email");
+    assert_eq!(
+        errors[1]["message"],
+        "definitely-not-an-email is not a valid subscriber email."
+    );
+}
+
+#[tokio::test]
+async fn api_subscribe_attributes_signup_policy_refusals_to_the_email() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "name": "Ursula",
+        "email": "ursula@mailinator.com"
+    });
+
+    // Act
+    let response = app.post_api_subscription(body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!({
+            "errors": [{
+                "field": "email",
+                "message": "Disposable email addresses are not accepted."
+            }]
+        })
+    );
+}
+
+#[tokio::test]
+async fn api_subscribe_reports_malformed_payloads_as_structured_errors() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (serde_json::json!({ "name": "Ursula" }), "missing the email"),
+        (
+            serde_json::json!({ "email": "ursula@domain.com" }),
+            "missing the name",
+        ),
+        (serde_json::json!(["ursula@domain.com"]), "not an object"),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_api_subscription(body).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            description
+        );
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["errors"][0]["field"], serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,163 @@
+use crate::helpers::{spawn_app, TestApp};
+
+async fn publish_issue(app: &TestApp, title: &str) {
+    let newsletter_body = serde_json::json!({
+        "title": title,
+        "content": {
+            "html": "<p>Hi {{ name }}, here is the <em>news</em> &amp; more.</p>",
+            "text": "Hi {{ name }}, here is the news & more.",
+        }
+    });
+    app.post_newsletter(newsletter_body)
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn published_issues_are_listed_in_the_archive() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_issue(&app, "First <issue>").await;
+
+    // Act
+    let response = app.get_archive(None).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"<a href="/archive/first-issue-"#));
+    assert!(html.contains("First &lt;issue&gt;"));
+}
+
+#[tokio::test]
+async fn the_web_version_of_an_issue_is_served_from_its_slug() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_issue(&app, "First issue").await;
+    let slug = sqlx::query!("Select slug From newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .slug;
+
+    // Act
+    let response = reqwest::get(format!("{}/archive/{}", app.api_address, slug))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let csp = response.headers()["Content-Security-Policy"]
+        .to_str()
+        .unwrap();
+    assert!(csp.starts_with("default-src 'none';"));
+    assert!(csp.contains("sandbox"));
+    let html = response.text().await.unwrap();
+    assert!(html.contains("<title>First issue</title>"));
+    assert!(html.contains("<p>Hi , here is the <em>news</em> &amp; more.</p>"));
+}
+
+#[tokio::test]
+async fn an_unknown_slug_returns_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/archive/not-an-issue", app.api_address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn the_archive_is_paginated() {
+    // Arrange
+    let app = spawn_app().await;
+    for i in 0..21 {
+        publish_issue(&app, &format!("Issue {}", i)).await;
+    }
+
+    // Act
+    let first_page = app.get_archive(None).await.text().await.unwrap();
+    let second_page = app.get_archive(Some(2)).await.text().await.unwrap();
+
+    // Assert
+    assert_eq!(first_page.matches("<li>").count(), 20);
+    assert!(first_page.contains(r#"href="/archive?page=2""#));
+    assert_eq!(second_page.matches("<li>").count(), 1);
+    assert!(second_page.contains(r#"href="/archive?page=1""#));
+    assert!(second_page.contains("Issue 0"));
+}
+
+#[tokio::test]
+async fn an_invalid_page_returns_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_archive(Some(0)).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn a_page_past_the_end_of_the_offsets_returns_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_archive(Some(i64::MAX)).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn published_issues_appear_in_the_atom_feed() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_issue(&app, "First issue").await;
+
+    // Act
+    let response = reqwest::get(format!("{}/feed.atom", app.api_address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/atom+xml; charset=utf-8"
+    );
+    let xml = response.text().await.unwrap();
+    assert!(xml.contains(r#"<feed xmlns="http://www.w3.org/2005/Atom">"#));
+    assert!(xml.contains("<title>First issue</title>"));
+    assert!(xml.contains("&lt;em&gt;news&lt;/em&gt; &amp;amp; more."));
+}
+
+#[tokio::test]
+async fn published_issues_appear_in_the_rss_feed() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_issue(&app, "First issue").await;
+
+    // Act
+    let response = reqwest::get(format!("{}/feed.rss", app.api_address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/rss+xml; charset=utf-8"
+    );
+    let xml = response.text().await.unwrap();
+    assert!(xml.contains(r#"<rss version="2.0">"#));
+    assert!(xml.contains("<title>First issue</title>"));
+    assert!(xml.contains("<link>http://127.0.0.1/archive/first-issue-"));
+}
@@ -0,0 +1,79 @@
+use chrono::Utc;
+use uuid::Uuid;
+use zero2prod::domain::SubscriptionStatus;
+use zero2prod::email_normalization::normalize_stored_emails;
+
+use crate::helpers::{spawn_app, TestApp};
+
+/// A subscriber as stored before internationalised domains were normalized
+async fn insert_legacy_subscriber(app: &TestApp, email: &str) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        Insert Into subscriptions (
+            id, email, email_normalized, name, subscribed_at, status, opt_in_mode
+        )
+        Values ($1, $2, Lower($2), 'Ursula', $3, $4, 'double')
+        "#,
+        id,
+        email,
+        Utc::now(),
+        SubscriptionStatus::Confirmed as SubscriptionStatus
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to insert a subscriber.");
+    id
+}
+
+async fn normalized_email(app: &TestApp, id: Uuid) -> String {
+    sqlx::query!(
+        "Select email_normalized From subscriptions Where id = $1",
+        id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .email_normalized
+}
+
+#[tokio::test]
+async fn internationalised_domains_stored_before_normalization_are_converted_to_punycode() {
+    // Arrange
+    let app = spawn_app().await;
+    let legacy = insert_legacy_subscriber(&app, "Ursula@Bücher.example").await;
+    let ascii = insert_legacy_subscriber(&app, "ursula@example.com").await;
+
+    // Act
+    let report = normalize_stored_emails(&app.db_pool).await.unwrap();
+    let again = normalize_stored_emails(&app.db_pool).await.unwrap();
+
+    // Assert
+    assert_eq!(report.normalized, 1);
+    assert!(report.conflicts.is_empty());
+    assert_eq!(again.normalized, 0);
+    assert_eq!(
+        normalized_email(&app, legacy).await,
+        "ursula@xn--bcher-kva.example"
+    );
+    assert_eq!(normalized_email(&app, ascii).await, "ursula@example.com");
+}
+
+#[tokio::test]
+async fn addresses_already_taken_once_normalized_are_reported_as_conflicts() {
+    // Arrange
+    let app = spawn_app().await;
+    let legacy = insert_legacy_subscriber(&app, "ursula@bücher.example").await;
+    insert_legacy_subscriber(&app, "ursula@xn--bcher-kva.example").await;
+
+    // Act
+    let report = normalize_stored_emails(&app.db_pool).await.unwrap();
+
+    // Assert
+    assert_eq!(report.normalized, 0);
+    assert_eq!(report.conflicts, vec![legacy]);
+    assert_eq!(
+        normalized_email(&app, legacy).await,
+        "ursula@bücher.example"
+    );
+}
@@ -0,0 +1,109 @@
+use chrono::Utc;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+async fn outbox(app: &TestApp) -> (i32, Option<String>) {
+    let row = sqlx::query!(r#"Select attempts, outcome From email_outbox"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the outbox email.");
+    (row.attempts, row.outcome)
+}
+
+/// Make the retry due now rather than after the backoff
+async fn skip_backoff(app: &TestApp) {
+    sqlx::query!(
+        r#"Update email_outbox Set next_attempt_at = $1"#,
+        Utc::now()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn subscribe_succeeds_when_the_email_provider_is_down() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=Daniel%20Furman&email=djfurman%40users.noreply.github.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscription(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(outbox(&app).await, (1, Some("failed".into())));
+}
+
+#[tokio::test]
+async fn the_dispatcher_retries_confirmation_emails_that_failed() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=Daniel%20Furman&email=djfurman%40users.noreply.github.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscription(body.into()).await;
+
+    // Act - Part 1 - Before the backoff elapsed
+    let sent_too_early = app.email_outbox.run_once().await.unwrap();
+    // Act - Part 2 - Once it elapsed
+    skip_backoff(&app).await;
+    let sent = app.email_outbox.run_once().await.unwrap();
+    let sent_again = app.email_outbox.run_once().await.unwrap();
+
+    // Assert
+    assert_eq!(sent_too_early, 0);
+    assert_eq!(sent, 1);
+    assert_eq!(sent_again, 0);
+    assert_eq!(outbox(&app).await, (2, Some("sent".into())));
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    assert_eq!(confirmation_links.html.path(), "/subscriptions/confirm");
+    let events = sqlx::query!(r#"Select kind From subscription_events Order By id"#)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let kinds: Vec<_> = events.iter().map(|e| e.kind.as_str()).collect();
+    assert_eq!(kinds, ["subscribed", "confirmation_sent"]);
+}
+
+#[tokio::test]
+async fn the_dispatcher_gives_up_after_too_many_attempts() {
+    // Arrange
+    let app = spawn_app_with(|c| c.email_outbox.max_attempts = 2).await;
+    let body = "name=Daniel%20Furman&email=djfurman%40users.noreply.github.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscription(body.into()).await;
+
+    // Act
+    skip_backoff(&app).await;
+    app.email_outbox.run_once().await.unwrap();
+    skip_backoff(&app).await;
+    app.email_outbox.run_once().await.unwrap();
+
+    // Assert
+    assert_eq!(outbox(&app).await, (2, Some("abandoned".into())));
+}
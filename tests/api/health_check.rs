@@ -1,4 +1,15 @@
-use crate::helpers::spawn_app;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+async fn get_ready(app: &TestApp) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/ready", &app.api_address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
 
 #[tokio::test]
 async fn health_check_works() {
@@ -20,3 +31,76 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(19), response.content_length());
 }
+
+#[tokio::test]
+async fn ready_returns_a_200_when_every_dependency_is_up() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = get_ready(&app).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["is_ready"], true);
+    assert_eq!(body["checks"]["database"]["is_healthy"], true);
+    assert_eq!(body["checks"]["migrations"]["is_healthy"], true);
+    assert!(body["checks"]["database"]["latency_ms"].is_u64());
+    // Not probed unless configured
+    assert!(body["checks"].get("email_provider").is_none());
+}
+
+#[tokio::test]
+async fn ready_returns_a_503_when_migrations_are_missing() {
+    // Arrange
+    let app = spawn_app().await;
+    sqlx::query(
+        r#"
+        Delete From _sqlx_migrations
+        Where version = (Select Max(version) From _sqlx_migrations)
+        "#,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = get_ready(&app).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["is_ready"], false);
+    assert_eq!(body["checks"]["database"]["is_healthy"], true);
+    assert_eq!(body["checks"]["migrations"]["is_healthy"], false);
+    // Which migrations are missing is logged, not shown to the world
+    assert_eq!(
+        body["checks"]["migrations"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .collect::<Vec<_>>(),
+        ["is_healthy", "latency_ms"]
+    );
+}
+
+#[tokio::test]
+async fn ready_reports_the_email_provider_without_failing_on_it() {
+    // Arrange
+    let app = spawn_app_with(|c| c.readiness.probe_email_provider = true).await;
+    Mock::given(path("/server"))
+        .and(method("GET"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = get_ready(&app).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["checks"]["email_provider"]["is_healthy"], false);
+}
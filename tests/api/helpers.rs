@@ -1,9 +1,16 @@
 use once_cell::sync::Lazy;
+use secrecy::ExposeSecret;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
+use tokio::task::JoinHandle;
 use uuid::Uuid;
 use wiremock::MockServer;
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
-use zero2prod::startup::{get_connection_pool, Application};
+use zero2prod::cleanup::PendingSubscriberCleanup;
+use zero2prod::configuration::{get_configuration, DatabaseSettings, Settings};
+use zero2prod::newsletter_delivery::NewsletterDeliveryWorker;
+use zero2prod::onboarding::OnboardingWorker;
+use zero2prod::outbox::OutboxDispatcher;
+use zero2prod::shutdown::Shutdown;
+use zero2prod::startup::{get_connection_pool, Application, MIGRATOR};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
 static TRACING: Lazy<()> = Lazy::new(|| {
@@ -27,6 +34,17 @@ pub struct TestApp {
     pub api_port: u16,
     pub db_pool: PgPool,
     pub email_server: MockServer,
+    pub admin_username: String,
+    pub admin_password: String,
+    pub webhook_username: String,
+    pub webhook_password: String,
+    pub pending_cleanup: PendingSubscriberCleanup,
+    pub onboarding: OnboardingWorker,
+    pub email_outbox: OutboxDispatcher,
+    pub newsletter_delivery: NewsletterDeliveryWorker,
+    pub shutdown: Shutdown,
+    /// Resolves once the application shut down
+    pub stopped: JoinHandle<Result<(), std::io::Error>>,
 }
 
 pub struct ConfirmationLinks {
@@ -37,7 +55,7 @@ pub struct ConfirmationLinks {
 impl TestApp {
     pub async fn post_subscription(&self, body: String) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/subscriptions", &self.api_address))
+            .post(format!("{}/subscriptions", &self.api_address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -45,15 +63,74 @@ impl TestApp {
             .expect("Failed to execute the request.")
     }
 
+    pub async fn post_api_subscription(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/api/v1/subscriptions", &self.api_address))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute the request.")
+    }
+
     pub async fn post_newsletter(&self, body: serde_json::Value) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/newsletters", &self.api_address))
+            .post(format!("{}/newsletters", &self.api_address))
             .json(&body)
             .send()
             .await
             .expect("Failed to execute request to create new newsletter.")
     }
 
+    pub async fn post_suppression(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/suppressions", &self.api_address))
+            .basic_auth(&self.admin_username, Some(&self.admin_password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request to add a suppression.")
+    }
+
+    pub fn admin_request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        reqwest::Client::new()
+            .request(method, format!("{}{}", &self.api_address, path))
+            .basic_auth(&self.admin_username, Some(&self.admin_password))
+    }
+
+    pub async fn get_suppressions(&self) -> serde_json::Value {
+        reqwest::Client::new()
+            .get(format!("{}/admin/suppressions", &self.api_address))
+            .basic_auth(&self.admin_username, Some(&self.admin_password))
+            .send()
+            .await
+            .expect("Failed to execute request to list suppressions.")
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_postmark_webhook(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/webhooks/postmark", &self.api_address))
+            .basic_auth(&self.webhook_username, Some(&self.webhook_password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request to the Postmark webhook.")
+    }
+
+    pub async fn get_archive(&self, page: Option<i64>) -> reqwest::Response {
+        let url = match page {
+            Some(page) => format!("{}/archive?page={}", &self.api_address, page),
+            None => format!("{}/archive", &self.api_address),
+        };
+        reqwest::get(url)
+            .await
+            .expect("Failed to execute request to the archive.")
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
         let get_link = |s| {
@@ -71,13 +148,18 @@ impl TestApp {
             confirmation_link.set_port(Some(self.api_port)).unwrap();
             confirmation_link
         };
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
         ConfirmationLinks { html, plain_text }
     }
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawn the application after letting the test tweak its configuration
+pub async fn spawn_app_with(customise: impl FnOnce(&mut Settings)) -> TestApp {
     // The first time `initialize` is invoked the code in `TRACING` is executed.
     // All other invocations will instead skip execution.
     Lazy::force(&TRACING);
@@ -93,6 +175,12 @@ pub async fn spawn_app() -> TestApp {
         c.application.port = 0;
         // Use the mock server as email API
         c.email_client.base_url = email_server.uri();
+        // Tests run the cleanup themselves rather than racing a background worker
+        c.pending_cleanup.enabled = false;
+        c.onboarding.enabled = false;
+        c.email_outbox.enabled = false;
+        c.newsletter_delivery.enabled = false;
+        customise(&mut c);
         c
     };
 
@@ -105,13 +193,30 @@ pub async fn spawn_app() -> TestApp {
         .expect("Failed to build application.");
     let application_port = application.port();
 
-    let _ = tokio::spawn(application.run_until_stopped());
+    let shutdown = application.shutdown();
+    let stopped = tokio::spawn(application.run_until_stopped());
+
+    let pending_cleanup = PendingSubscriberCleanup::build(&configuration)
+        .expect("Failed to build the pending subscriber cleanup.");
+    let onboarding = OnboardingWorker::build(&configuration);
+    let email_outbox = OutboxDispatcher::build(&configuration);
+    let newsletter_delivery = NewsletterDeliveryWorker::build(&configuration);
 
     TestApp {
         api_address: format!("http://127.0.0.1:{}", application_port),
         api_port: application_port,
         db_pool: get_connection_pool(&configuration.database),
         email_server,
+        admin_username: configuration.admin.username,
+        admin_password: configuration.admin.password.expose_secret().clone(),
+        webhook_username: configuration.webhook.username,
+        webhook_password: configuration.webhook.password.expose_secret().clone(),
+        pending_cleanup,
+        onboarding,
+        email_outbox,
+        newsletter_delivery,
+        shutdown,
+        stopped,
     }
 }
 
@@ -131,7 +236,7 @@ async fn configure_database(config: &DatabaseSettings) -> PgPool {
         .await
         .expect("Failed to connect to Postgres new db.");
 
-    sqlx::migrate!("./migrations")
+    MIGRATOR
         .run(&db_pool)
         .await
         .expect("Failed to migrate the database.");
@@ -1,5 +1,22 @@
+// Older tests predate these lints, they are kept as written
+#![allow(clippy::needless_borrow, clippy::needless_borrows_for_generic_args)]
+
+mod admin_export;
+mod admin_import;
+mod admin_subscribers;
+mod api_subscriptions;
+mod archive;
+mod email_normalization;
+mod email_outbox;
 mod health_check;
 mod helpers;
 mod newsletter;
+mod onboarding;
+mod pending_cleanup;
+mod shutdown;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_data;
+mod subscriptions_unsubscribe;
+mod suppressions;
+mod tracking;
@@ -27,9 +27,10 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     });
 
     let response = app.post_newsletter(newsletter_body).await;
+    app.newsletter_delivery.run_once().await.unwrap();
 
     // Assert
-    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.status().as_u16(), 202);
     // Mock verifies on Drop that we haven't sent the newsletter email
 }
 
@@ -56,9 +57,10 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     });
 
     let response = app.post_newsletter(newsletter_body).await;
+    app.newsletter_delivery.run_once().await.unwrap();
 
     // Assert
-    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.status().as_u16(), 202);
 }
 
 #[tokio::test]
@@ -90,8 +92,358 @@ async fn newsletters_returns_a_400_for_invalid_data() {
     }
 }
 
+#[tokio::test]
+async fn newsletters_are_rendered_for_each_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!(r#"Update subscriptions Set attributes = '{"company": "<Acme & Co>"}'"#)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "html": "<p>Hi {{ name }} from {{ company }}</p><a href=\"{{ unsubscribe_url }}\">Unsubscribe</a>",
+            "text": "Hi {{ name }} from {{ company }}, sent to {{ email }}",
+        }
+    });
+    let response = app.post_newsletter(newsletter_body).await;
+    app.newsletter_delivery.run_once().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html_body = body["HtmlBody"].as_str().unwrap();
+    let text_body = body["TextBody"].as_str().unwrap();
+
+    assert!(html_body.contains("<p>Hi Daniel Furman from &lt;Acme &amp; Co&gt;</p>"));
+    assert!(html_body.contains("/subscriptions/unsubscribe?subscription_token="));
+    assert!(text_body
+        .ends_with("Hi Daniel Furman from <Acme & Co>, sent to djfurman@users.noreply.github.com"));
+}
+
+#[tokio::test]
+async fn newsletters_offer_one_click_unsubscribe() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "html": "<p>Newsletter body as HTML</p>",
+            "text": "Newsletter body as plain text",
+        }
+    });
+    app.post_newsletter(newsletter_body).await;
+    app.newsletter_delivery.run_once().await.unwrap();
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let headers = body["Headers"].as_array().unwrap();
+    assert_eq!(headers[0]["Name"], "List-Unsubscribe");
+    let list_unsubscribe = headers[0]["Value"].as_str().unwrap();
+    assert!(list_unsubscribe.starts_with("<http://127.0.0.1/subscriptions/unsubscribe?"));
+    assert_eq!(headers[1]["Name"], "List-Unsubscribe-Post");
+    assert_eq!(headers[1]["Value"], "List-Unsubscribe=One-Click");
+}
+
+#[tokio::test]
+async fn newsletters_returns_a_400_for_invalid_templates() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let test_cases = vec![
+        (
+            serde_json::json!({
+                "title": "Newsletter title",
+                "content": {
+                    "html": "<p>Hi {{ name </p>",
+                    "text": "Hi {{ name }}",
+                }
+            }),
+            "an unclosed tag in the html content",
+        ),
+        (
+            serde_json::json!({
+                "title": "Newsletter title",
+                "content": {
+                    "html": "<p>Hi {{ name }}</p>",
+                    "text": "Hi {{ first name }}",
+                }
+            }),
+            "an invalid variable in the text content",
+        ),
+    ];
+
+    for (invalid_body, error_message) in test_cases {
+        // Act
+        let response = app.post_newsletter(invalid_body).await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload had {}.",
+            error_message
+        );
+    }
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[tokio::test]
+async fn markdown_newsletters_are_delivered_as_html_and_text() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_body = serde_json::json!({
+        "title": "Newsletter title",
+        "markdown": "Hi {{ name }}, read [the post](https://example.com/post).",
+    });
+    let response = app.post_newsletter(newsletter_body).await;
+    app.newsletter_delivery.run_once().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html_body = body["HtmlBody"].as_str().unwrap();
+    let text_body = body["TextBody"].as_str().unwrap();
+
+    assert!(html_body.starts_with("<!DOCTYPE html>"));
+    assert!(html_body.contains("<p style=\""));
+    assert!(html_body.contains("Hi Daniel Furman, read <a style=\""));
+    assert!(html_body.contains("/subscriptions/unsubscribe?subscription_token="));
+    assert!(text_body
+        .ends_with("Hi Daniel Furman, read the post [1].\n\nLinks:\n[1] https://example.com/post"));
+}
+
+#[tokio::test]
+async fn newsletters_with_both_content_and_markdown_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let newsletter_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "html": "<p>Newsletter body as HTML</p>",
+            "text": "Newsletter body as plain text",
+        },
+        "markdown": "Newsletter body as Markdown",
+    });
+    let response = app.post_newsletter(newsletter_body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn newsletters_link_to_their_web_version() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "html": "<p>Newsletter body as HTML</p>",
+            "text": "Newsletter body as plain text",
+        }
+    });
+    app.post_newsletter(newsletter_body).await;
+    app.newsletter_delivery.run_once().await.unwrap();
+
+    // Assert
+    let slug = sqlx::query!("Select slug From newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .slug;
+    let web_version_url = format!("http://127.0.0.1/archive/{}", slug);
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains(&format!("<a href=\"{}\">", web_version_url)));
+    assert_eq!(
+        body["TextBody"],
+        format!(
+            "View this issue in your browser: {}\n\nNewsletter body as plain text",
+            web_version_url
+        )
+    );
+}
+
+#[tokio::test]
+async fn retried_publishes_do_not_deliver_the_issue_twice() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "html": "<p>Newsletter body as HTML</p>",
+            "text": "Newsletter body as plain text",
+        }
+    });
+    let publish = || {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &app.api_address))
+            .header("Idempotency-Key", "weekly-2024-03-01")
+            .json(&newsletter_body)
+            .send()
+    };
+    publish().await.unwrap().error_for_status().unwrap();
+    app.newsletter_delivery.run_once().await.unwrap();
+
+    // Act
+    let response = publish().await.unwrap();
+    app.newsletter_delivery.run_once().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    let issues = sqlx::query!(r#"Select Count(*) As "count!" From newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues.count, 1);
+    // Mock verifies on Drop that the subscriber got the issue once
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried_by_the_worker() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let newsletter_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "html": "<p>Newsletter body as HTML</p>",
+            "text": "Newsletter body as plain text",
+        }
+    });
+    let response = app.post_newsletter(newsletter_body).await;
+    {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        app.newsletter_delivery.run_once().await.unwrap();
+    }
+    sqlx::query!("Update newsletter_delivery_queue Set next_attempt_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let sent = app.newsletter_delivery.run_once().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    assert_eq!(sent, 1);
+    let deliveries = sqlx::query!(r#"Select Count(*) As "count!" From newsletter_deliveries"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(deliveries.count, 1);
+    let queued = sqlx::query!(r#"Select Count(*) As "count!" From newsletter_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 0);
+}
+
 /// Use the public API of the application under test to create an unconfirmed subscriber.
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
     let body = "name=Daniel%20Furman&email=djfurman%40users.noreply.github.com";
 
     let _mock_guard = Mock::given(path("/email"))
@@ -115,11 +467,11 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
         .pop()
         .unwrap();
 
-    app.get_confirmation_links(&email_request)
+    app.get_confirmation_links(email_request)
 }
 
 /// Use the public API of the application to confirm a subscriber.
-async fn create_confirmed_subscriber(app: &TestApp) {
+pub async fn create_confirmed_subscriber(app: &TestApp) {
     let confirmation_link = create_unconfirmed_subscriber(app).await;
     reqwest::get(confirmation_link.html)
         .await
@@ -0,0 +1,300 @@
+use chrono::{Duration, Utc};
+use reqwest::Method;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, TestApp};
+use crate::newsletter::create_unconfirmed_subscriber;
+
+async fn add_onboarding_email(app: &TestApp, delay_days: i32, subject: &str) -> reqwest::Response {
+    app.admin_request(Method::POST, "/admin/onboarding/emails")
+        .json(&serde_json::json!({
+            "delay_days": delay_days,
+            "subject": subject,
+            "html_body": "<p>Hi {{ name }}!</p><a href=\"{{ unsubscribe_url }}\">Unsubscribe</a>",
+            "text_body": "Hi {{ name }}! Unsubscribe: {{ unsubscribe_url }}",
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request to add an onboarding email.")
+}
+
+/// Subjects of the emails sent after the confirmation email
+async fn onboarding_subjects(app: &TestApp) -> Vec<String> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|r| {
+            let body: serde_json::Value = serde_json::from_slice(&r.body).unwrap();
+            body["Subject"].as_str().unwrap().to_owned()
+        })
+        .collect()
+}
+
+/// Pretend the subscriber confirmed `days` days ago
+async fn travel_in_time(app: &TestApp, days: i64) {
+    sqlx::query!(
+        r#"Update onboarding_deliveries Set confirmed_at = $1"#,
+        Utc::now() - Duration::days(days)
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn mount_email_server(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn the_onboarding_sequence_follows_the_confirmation() {
+    // Arrange
+    let app = spawn_app().await;
+    add_onboarding_email(&app, 0, "Welcome!").await;
+    add_onboarding_email(&app, 3, "Getting started").await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    mount_email_server(&app).await;
+
+    // Act - Part 1 - Straight after confirming
+    let sent_on_confirmation = app.onboarding.run_once().await.unwrap();
+    // Act - Part 2 - A few days later
+    travel_in_time(&app, 3).await;
+    let sent_later = app.onboarding.run_once().await.unwrap();
+    let sent_after_the_end = app.onboarding.run_once().await.unwrap();
+
+    // Assert
+    assert_eq!(sent_on_confirmation, 1);
+    assert_eq!(sent_later, 1);
+    assert_eq!(sent_after_the_end, 0);
+    let subjects = onboarding_subjects(&app).await;
+    assert_eq!(subjects[1..], ["Welcome!", "Getting started"]);
+}
+
+#[tokio::test]
+async fn subscribers_confirmed_by_an_import_get_the_onboarding_sequence() {
+    // Arrange
+    let app = spawn_app().await;
+    add_onboarding_email(&app, 0, "Welcome!").await;
+    let import = |query: &'static str, csv: &'static str| {
+        app.admin_request(
+            Method::POST,
+            &format!("/admin/subscribers/import?{}", query),
+        )
+        .header("Content-Type", "text/csv")
+        .body(csv)
+        .send()
+    };
+    import(
+        "",
+        "email,name,consent\nursula@example.com,Ursula,yes\nalice@example.com,Alice,no\n",
+    )
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+    // Confirms Alice, who was left pending
+    import(
+        "mode=update",
+        "email,name,consent\nalice@example.com,Alice,yes\n",
+    )
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+    mount_email_server(&app).await;
+
+    // Act
+    let sent = app.onboarding.run_once().await.unwrap();
+
+    // Assert
+    assert_eq!(sent, 2);
+}
+
+#[tokio::test]
+async fn pending_subscribers_do_not_get_onboarding_emails() {
+    // Arrange
+    let app = spawn_app().await;
+    add_onboarding_email(&app, 0, "Welcome!").await;
+    create_unconfirmed_subscriber(&app).await;
+
+    // Act
+    let sent = app.onboarding.run_once().await.unwrap();
+
+    // Assert
+    assert_eq!(sent, 0);
+}
+
+#[tokio::test]
+async fn unsubscribing_cancels_the_rest_of_the_sequence() {
+    // Arrange
+    let app = spawn_app().await;
+    add_onboarding_email(&app, 0, "Welcome!").await;
+    add_onboarding_email(&app, 7, "One week in").await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    mount_email_server(&app).await;
+    app.onboarding.run_once().await.unwrap();
+
+    // Act
+    let mut unsubscribe_link = confirmation_links.html;
+    unsubscribe_link.set_path("/subscriptions/unsubscribe");
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    travel_in_time(&app, 7).await;
+    let sent = app.onboarding.run_once().await.unwrap();
+
+    // Assert
+    assert_eq!(sent, 0);
+    let unsent = sqlx::query!(
+        r#"Select Count(*) As "count!" From onboarding_deliveries Where sent_at Is Null"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(unsent.count, 0);
+}
+
+#[tokio::test]
+async fn an_email_with_a_broken_template_does_not_hold_back_the_others() {
+    // Arrange
+    let app = spawn_app().await;
+    let broken: serde_json::Value = add_onboarding_email(&app, 0, "Welcome!")
+        .await
+        .json()
+        .await
+        .unwrap();
+    add_onboarding_email(&app, 1, "Getting started").await;
+    // Saved before the template rules were tightened
+    sqlx::query!(
+        r#"Update onboarding_emails Set html_body = '<p>Hi {{ name </p>' Where id = $1"#,
+        uuid::Uuid::parse_str(broken["id"].as_str().unwrap()).unwrap()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    mount_email_server(&app).await;
+    travel_in_time(&app, 1).await;
+
+    // Act
+    let sent = app.onboarding.run_once().await.unwrap();
+    let sent_again = app.onboarding.run_once().await.unwrap();
+
+    // Assert
+    assert_eq!(sent, 1);
+    assert_eq!(sent_again, 0);
+    let subjects = onboarding_subjects(&app).await;
+    assert_eq!(subjects[1..], ["Getting started"]);
+    let unsent = sqlx::query!(
+        r#"Select Count(*) As "count!" From onboarding_deliveries Where sent_at Is Null"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(unsent.count, 0);
+}
+
+#[tokio::test]
+async fn admins_can_edit_the_sequence() {
+    // Arrange
+    let app = spawn_app().await;
+    let created: serde_json::Value = add_onboarding_email(&app, 3, "Getting started")
+        .await
+        .json()
+        .await
+        .unwrap();
+    let id = created["id"].as_str().unwrap();
+
+    // Act
+    let updated = app
+        .admin_request(Method::PUT, &format!("/admin/onboarding/emails/{}", id))
+        .json(&serde_json::json!({
+            "delay_days": 5,
+            "subject": "Tips and tricks",
+            "html_body": "<p>Hi {{ name }}</p>",
+            "text_body": "Hi {{ name }}",
+        }))
+        .send()
+        .await
+        .unwrap();
+    let deleted = app
+        .admin_request(Method::DELETE, &format!("/admin/onboarding/emails/{}", id))
+        .send()
+        .await
+        .unwrap();
+    let sequence: serde_json::Value = app
+        .admin_request(Method::GET, "/admin/onboarding/emails")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(updated.status().as_u16(), 200);
+    let updated: serde_json::Value = updated.json().await.unwrap();
+    assert_eq!(updated["delay_days"], 5);
+    assert_eq!(updated["subject"], "Tips and tricks");
+    assert_eq!(deleted.status().as_u16(), 204);
+    assert_eq!(sequence, serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn invalid_onboarding_emails_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (-1, "Welcome!", "<p>Hi</p>", "negative delay"),
+        (0, " ", "<p>Hi</p>", "empty subject"),
+        (0, "Welcome!", "<p>Hi {{ name </p>", "unclosed tag"),
+    ];
+
+    for (delay_days, subject, html_body, description) in test_cases {
+        // Act
+        let response = app
+            .admin_request(Method::POST, "/admin/onboarding/emails")
+            .json(&serde_json::json!({
+                "delay_days": delay_days,
+                "subject": subject,
+                "html_body": html_body,
+                "text_body": "Hi",
+            }))
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not reject an onboarding email with a {}",
+            description
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use chrono::{Duration, Utc};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::cleanup::CleanupReport;
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn subscribe(app: &TestApp, days_ago: i64) {
+    subscribe_with(
+        app,
+        "name=le%20guin&email=ursula_le_guin%40gmail.com",
+        days_ago,
+    )
+    .await;
+}
+
+async fn subscribe_with(app: &TestApp, body: &str, days_ago: i64) {
+    app.post_subscription(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    sqlx::query!(
+        r#"Update subscriptions Set subscribed_at = $1"#,
+        Utc::now() - Duration::days(days_ago)
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn mount_email_server(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn pending_subscribers_are_reminded_once_with_the_same_link() {
+    // Arrange
+    let app = spawn_app().await;
+    mount_email_server(&app).await;
+    subscribe(&app, 4).await;
+
+    // Act
+    let first = app.pending_cleanup.run_once().await.unwrap();
+    let second = app.pending_cleanup.run_once().await.unwrap();
+    app.email_outbox.run_once().await.unwrap();
+
+    // Assert
+    assert_eq!(
+        first,
+        CleanupReport {
+            reminded: 1,
+            deleted: 0
+        }
+    );
+    assert_eq!(second, CleanupReport::default());
+    let requests = app.email_server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(
+        app.get_confirmation_links(&requests[0]).html,
+        app.get_confirmation_links(&requests[1]).html
+    );
+}
+
+#[tokio::test]
+async fn recent_and_confirmed_subscribers_are_left_alone() {
+    // Arrange
+    let app = spawn_app().await;
+    mount_email_server(&app).await;
+    subscribe(&app, 1).await;
+    app.post_subscription("name=alice&email=alice%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    sqlx::query!(
+        r#"
+        Update subscriptions
+        Set status = 'confirmed', subscribed_at = $1
+        Where email_normalized = 'alice@gmail.com'
+        "#,
+        Utc::now() - Duration::days(30)
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let report = app.pending_cleanup.run_once().await.unwrap();
+
+    // Assert
+    assert_eq!(report, CleanupReport::default());
+    let remaining = sqlx::query!(r#"Select Count(*) As "count!" From subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.count, 2);
+}
+
+#[tokio::test]
+async fn stale_pending_subscribers_are_deleted_with_their_tokens() {
+    // Arrange
+    let app = spawn_app().await;
+    mount_email_server(&app).await;
+    subscribe(&app, 15).await;
+
+    // Act
+    let report = app.pending_cleanup.run_once().await.unwrap();
+    app.email_outbox.run_once().await.unwrap();
+
+    // Assert
+    assert_eq!(report.deleted, 1);
+    assert_eq!(report.reminded, 0);
+    // Only the confirmation email sent at signup, no reminder to a link about to stop working
+    let requests = app.email_server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 1);
+    let subscribers = sqlx::query!(r#"Select Count(*) As "count!" From subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let tokens = sqlx::query!(r#"Select Count(*) As "count!" From subscription_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers.count, 0);
+    assert_eq!(tokens.count, 0);
+}
+
+#[tokio::test]
+async fn concurrent_cleanups_remind_each_subscriber_once() {
+    // Arrange
+    let app = spawn_app().await;
+    mount_email_server(&app).await;
+    subscribe(&app, 4).await;
+
+    // Act
+    let (a, b) = tokio::join!(
+        app.pending_cleanup.run_once(),
+        app.pending_cleanup.run_once()
+    );
+
+    app.email_outbox.run_once().await.unwrap();
+
+    // Assert
+    assert_eq!(a.unwrap().reminded + b.unwrap().reminded, 1);
+    let requests = app.email_server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 2);
+}
+
+#[tokio::test]
+async fn reminders_are_sent_in_the_locale_chosen_at_signup() {
+    // Arrange
+    let app = spawn_app().await;
+    mount_email_server(&app).await;
+    subscribe_with(
+        &app,
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&locale=de",
+        4,
+    )
+    .await;
+
+    // Act
+    let report = app.pending_cleanup.run_once().await.unwrap();
+    app.email_outbox.run_once().await.unwrap();
+
+    // Assert
+    assert_eq!(report.reminded, 1);
+    let requests = app.email_server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 2);
+    let reminder: serde_json::Value = serde_json::from_slice(&requests[1].body).unwrap();
+    assert_eq!(reminder["Subject"], "Willkommen");
+}
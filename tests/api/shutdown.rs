@@ -0,0 +1,151 @@
+use std::time::Duration;
+
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, spawn_app_with};
+use crate::newsletter::create_confirmed_subscriber;
+
+#[tokio::test]
+async fn shutting_down_lets_in_flight_requests_finish() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=Daniel%20Furman&email=djfurman%40users.noreply.github.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let (response, _) = tokio::join!(app.post_subscription(body.into()), async {
+        // Once the request is waiting on the email provider
+        while app
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .is_empty()
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        app.shutdown.trigger();
+    });
+    let stopped = tokio::time::timeout(Duration::from_secs(5), app.stopped)
+        .await
+        .expect("The application did not shut down in time.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    stopped.unwrap().unwrap();
+    let outcome = sqlx::query!(r#"Select outcome From email_outbox"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .outcome;
+    assert_eq!(outcome.as_deref(), Some("sent"));
+    let after_shutdown = reqwest::get(format!("{}/health-check", app.api_address)).await;
+    assert!(after_shutdown.is_err());
+}
+
+#[tokio::test]
+async fn shutting_down_gives_up_on_in_flight_work_after_the_timeout() {
+    // Arrange
+    let app = spawn_app_with(|c| c.shutdown.timeout_seconds = 1).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(60)))
+        .mount(&app.email_server)
+        .await;
+    let address = app.api_address.clone();
+    tokio::spawn(async move {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body("name=Daniel%20Furman&email=djfurman%40users.noreply.github.com")
+            .send()
+            .await
+    });
+    while app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty()
+    {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // Act
+    app.shutdown.trigger();
+    let stopped = tokio::time::timeout(Duration::from_secs(5), app.stopped).await;
+
+    // Assert
+    stopped
+        .expect("The application did not give up on the in-flight request.")
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn shutting_down_stops_a_newsletter_delivery_after_the_current_subscriber() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.newsletter_delivery.enabled = true;
+        c.newsletter_delivery.poll_interval_seconds = 1;
+    })
+    .await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!(
+        r#"
+        Insert Into subscriptions (
+            id, email, email_normalized, name, subscribed_at, status, opt_in_mode
+        )
+        Select
+            gen_random_uuid(), 'ursula_le_guin@gmail.com', 'ursula_le_guin@gmail.com',
+            'le guin', subscribed_at, status, opt_in_mode
+        From subscriptions
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let confirmation_emails = app.email_server.received_requests().await.unwrap().len();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletter(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {"html": "<p>Hi</p>", "text": "Hi"},
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    // Once the worker is waiting on the email provider for the first subscriber
+    while app.email_server.received_requests().await.unwrap().len() == confirmation_emails {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // Act
+    app.shutdown.trigger();
+    let stopped = tokio::time::timeout(Duration::from_secs(5), app.stopped)
+        .await
+        .expect("The application did not shut down in time.");
+
+    // Assert
+    stopped.unwrap().unwrap();
+    let delivered = sqlx::query!(r#"Select Count(*) As "count!" From newsletter_deliveries"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivered.count, 1);
+    let queued = sqlx::query!(r#"Select Count(*) As "count!" From newsletter_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 1);
+}
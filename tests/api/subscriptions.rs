@@ -1,7 +1,14 @@
-use wiremock::matchers::{method, path};
-use wiremock::{Mock, ResponseTemplate};
+use chrono::{Duration, Utc};
+use wiremock::matchers::{body_string_contains, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
 
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with};
+use zero2prod::captcha::{solve_proof_of_work, ProofOfWorkChallenge};
+use zero2prod::configuration::{
+    get_configuration, CaptchaVerifierKind, OptInMode, RateLimitStoreKind,
+};
+use zero2prod::domain::{OptIn, SubscriptionStatus};
+use zero2prod::rate_limit::RateLimiter;
 
 #[tokio::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
@@ -41,14 +48,58 @@ async fn subscribe_persists_the_new_subscriber() {
 
     // Assert
     // Verify the database
-    let saved_record = sqlx::query!("Select email, name, status From subscriptions",)
-        .fetch_one(&app.db_pool)
-        .await
-        .expect("Failed to fetch saved subscription.");
+    let saved_record = sqlx::query!(
+        r#"
+        Select email, name, status As "status: SubscriptionStatus",
+            opt_in_mode As "opt_in_mode: OptIn"
+        From subscriptions
+        "#,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription.");
     // Ensure we have the correct database record
     assert_eq!(saved_record.email, "djfurman@users.noreply.github.com");
     assert_eq!(saved_record.name, "Daniel Furman");
-    assert_eq!(saved_record.status, "pending_confirmation");
+    assert_eq!(saved_record.status, SubscriptionStatus::PendingConfirmation);
+    assert_eq!(saved_record.opt_in_mode, OptIn::Double);
+}
+
+#[tokio::test]
+async fn subscribe_confirms_straight_away_in_single_opt_in_mode() {
+    // Arrange
+    let app = spawn_app_with(|c| c.signup_policy.opt_in = OptInMode::Single).await;
+    let body = "name=Daniel%20Furman&email=djfurman%40users.noreply.github.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscription(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved_record = sqlx::query!(
+        r#"
+        Select status As "status: SubscriptionStatus", opt_in_mode As "opt_in_mode: OptIn",
+            Array(
+                Select e.kind From subscription_events e
+                Where e.subscriber_id = s.id
+                Order By e.kind
+            ) As "events!"
+        From subscriptions s
+        "#,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved_record.status, SubscriptionStatus::Confirmed);
+    assert_eq!(saved_record.opt_in_mode, OptIn::Single);
+    assert_eq!(saved_record.events, ["confirmed", "subscribed"]);
 }
 
 #[tokio::test]
@@ -108,6 +159,35 @@ async fn subscribe_returns_a_400_when_fields_are_present_but_invalid() {
     }
 }
 
+#[tokio::test]
+async fn subscribe_returns_a_400_for_addresses_refused_by_the_signup_policy() {
+    // Arrange
+    let app = spawn_app_with(|c| c.signup_policy.denied_domains = vec!["example.com".into()]).await;
+    let test_cases = vec![
+        (
+            "name=daniel&email=daniel%40mailinator.com",
+            "Disposable email addresses are not accepted.",
+        ),
+        (
+            "name=daniel&email=postmaster%40gmail.com",
+            "Role accounts such as postmaster@ cannot subscribe, please use a personal address.",
+        ),
+        (
+            "name=daniel&email=daniel%40Example.com",
+            "Signups from example.com are not accepted.",
+        ),
+    ];
+
+    for (body, expected_message) in test_cases {
+        // Act
+        let response = app.post_subscription(body.into()).await;
+
+        // Assert
+        assert_eq!(400, response.status().as_u16(), "{}", body);
+        assert_eq!(response.text().await.unwrap(), expected_message);
+    }
+}
+
 #[tokio::test]
 async fn subscribe_sends_a_confirmation_email_for_valid_data() {
     // Arrange
@@ -173,3 +253,465 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     // Assert
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn subscribe_sends_the_confirmation_email_in_the_requested_locale() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=Daniel%20Furman&email=djfurman%40users.noreply.github.com&locale=de";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscription(body.into()).await;
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "Willkommen");
+}
+
+#[tokio::test]
+async fn subscribe_falls_back_to_accept_language_and_then_the_default_locale() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        ("de-DE,de;q=0.9,en;q=0.8", "Willkommen"),
+        ("fr-FR,fr;q=0.9", "Welcome"),
+    ];
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    for (i, (accept_language, expected_subject)) in test_cases.into_iter().enumerate() {
+        // Act
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", &app.api_address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Accept-Language", accept_language)
+            .body(format!(
+                "name=Daniel%20Furman&email=reader{}%40example.com",
+                i
+            ))
+            .send()
+            .await
+            .expect("Failed to execute the request.");
+
+        // Assert
+        let email_request = app
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        assert_eq!(
+            body["Subject"], expected_subject,
+            "The wrong template was used for `Accept-Language: {}`.",
+            accept_language
+        );
+    }
+}
+
+#[tokio::test]
+async fn subscribing_twice_with_a_differently_cased_address_keeps_a_single_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let first = app
+        .post_subscription("name=Alice&email=alice%40example.com".into())
+        .await;
+    let second = app
+        .post_subscription("name=Alice&email=%20Alice%40Example.COM%20".into())
+        .await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+    let saved = sqlx::query!("Select email, email_normalized From subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "alice@example.com");
+    assert_eq!(saved[0].email_normalized, "alice@example.com");
+}
+
+#[tokio::test]
+async fn subscribe_persists_an_internationalised_address() {
+    // Arrange
+    let app = spawn_app_with(|c| c.email_client.supports_smtputf8 = true).await;
+    // name=Yamada Taro&email=山田太郎@例え.jp
+    let body =
+        "name=Yamada%20Taro&email=%E5%B1%B1%E7%94%B0%E5%A4%AA%E9%83%8E%40%E4%BE%8B%E3%81%88.jp";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscription(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("Select email, email_normalized From subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "山田太郎@例え.jp");
+    assert_eq!(saved.email_normalized, "山田太郎@xn--r8jz45g.jp");
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let email_body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(email_body["To"], "山田太郎@xn--r8jz45g.jp");
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_400_for_addresses_requiring_unsupported_smtputf8() {
+    // Arrange
+    let app = spawn_app_with(|c| c.email_client.supports_smtputf8 = false).await;
+    // name=Yamada Taro&email=山田太郎@例え.jp
+    let body =
+        "name=Yamada%20Taro&email=%E5%B1%B1%E7%94%B0%E5%A4%AA%E9%83%8E%40%E4%BE%8B%E3%81%88.jp";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscription(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let saved = sqlx::query!("Select id From subscriptions")
+        .fetch_optional(&app.db_pool)
+        .await
+        .expect("Failed to query subscriptions.");
+    assert!(saved.is_none());
+}
+
+#[tokio::test]
+async fn subscribe_silently_drops_bots_filling_in_the_honeypot() {
+    // Arrange
+    let app = spawn_app().await;
+    let body =
+        "name=Daniel%20Furman&email=djfurman%40users.noreply.github.com&website=spam.example";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscription(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("Select id From subscriptions")
+        .fetch_optional(&app.db_pool)
+        .await
+        .expect("Failed to query subscriptions.");
+    assert!(saved.is_none());
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_429_once_an_ip_exceeds_its_rate_limit() {
+    for store in [RateLimitStoreKind::Memory, RateLimitStoreKind::Postgres] {
+        // Arrange
+        let app = spawn_app_with(|c| {
+            c.rate_limit.store = store;
+            c.rate_limit.per_ip.max_requests = 2;
+        })
+        .await;
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(2)
+            .mount(&app.email_server)
+            .await;
+
+        // Act
+        let mut statuses = vec![];
+        for name in ["alice", "bob", "carol"] {
+            let response = app
+                .post_subscription(format!("name={0}&email={0}%40example.com", name))
+                .await;
+            statuses.push((
+                response.status().as_u16(),
+                response.headers().get("Retry-After").cloned(),
+            ));
+        }
+
+        // Assert
+        assert_eq!(statuses[0].0, 200, "With the {:?} store", store);
+        assert_eq!(statuses[1].0, 200, "With the {:?} store", store);
+        assert_eq!(statuses[2].0, 429, "With the {:?} store", store);
+        let retry_after: u64 = statuses[2]
+            .1
+            .as_ref()
+            .expect("A 429 must come with a Retry-After header.")
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(retry_after > 0 && retry_after <= 3600);
+    }
+}
+
+#[tokio::test]
+async fn a_forged_forwarded_for_header_does_not_reset_the_ip_rate_limit() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limit.per_ip.max_requests = 2).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let mut statuses = vec![];
+    for (i, name) in ["alice", "bob", "carol"].into_iter().enumerate() {
+        let response = reqwest::Client::new()
+            .post(format!("{}/subscriptions", &app.api_address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("X-Forwarded-For", format!("198.51.100.{}", i))
+            .body(format!("name={0}&email={0}%40example.com", name))
+            .send()
+            .await
+            .unwrap();
+        statuses.push(response.status().as_u16());
+    }
+
+    // Assert
+    assert_eq!(statuses, [200, 200, 429]);
+}
+
+#[tokio::test]
+async fn trusted_proxies_forward_the_client_ip_to_the_rate_limit() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.rate_limit.per_ip.max_requests = 1;
+        c.rate_limit.trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let mut statuses = vec![];
+    for (client, name) in [
+        ("198.51.100.1", "alice"),
+        ("198.51.100.2", "bob"),
+        ("198.51.100.1", "carol"),
+    ] {
+        let response = reqwest::Client::new()
+            .post(format!("{}/subscriptions", &app.api_address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("X-Forwarded-For", client)
+            .body(format!("name={0}&email={0}%40example.com", name))
+            .send()
+            .await
+            .unwrap();
+        statuses.push(response.status().as_u16());
+    }
+
+    // Assert
+    assert_eq!(statuses, [200, 200, 429]);
+}
+
+#[tokio::test]
+async fn expired_rate_limit_buckets_are_pruned_from_postgres() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut configuration = get_configuration().expect("Failed to read configuration.");
+    configuration.rate_limit.store = RateLimitStoreKind::Postgres;
+    let rate_limiter = RateLimiter::from_settings(
+        &configuration.rate_limit,
+        app.db_pool.clone(),
+        configuration.suppression.email_hasher(),
+    );
+    rate_limiter.check_ip("198.51.100.1").await.unwrap();
+    rate_limiter.check_ip("198.51.100.2").await.unwrap();
+    sqlx::query!(
+        "Update rate_limit_buckets Set expires_at = $1 Where key = 'ip:198.51.100.1'",
+        Utc::now() - Duration::seconds(1)
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let pruned = rate_limiter.prune().await.unwrap();
+
+    // Assert
+    assert_eq!(pruned, 1);
+    let keys: Vec<String> = sqlx::query!("Select key From rate_limit_buckets")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.key)
+        .collect();
+    assert_eq!(keys, ["ip:198.51.100.2"]);
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_429_once_an_address_exceeds_its_rate_limit() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limit.per_email.max_requests = 1).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let first = app
+        .post_subscription("name=Alice&email=alice%40example.com".into())
+        .await;
+    // Differently written, but the same address
+    let second = app
+        .post_subscription("name=Alice&email=Alice%40Example.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 429);
+    assert!(second.headers().contains_key("Retry-After"));
+}
+
+#[tokio::test]
+async fn the_signup_challenge_is_not_found_without_proof_of_work() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(&format!("{}/subscriptions/challenge", app.api_address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn subscribe_requires_a_solved_proof_of_work_when_configured() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.captcha.verifier = CaptchaVerifierKind::ProofOfWork;
+        c.captcha.proof_of_work.difficulty = 8;
+    })
+    .await;
+    let body = "name=Daniel%20Furman&email=djfurman%40users.noreply.github.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let without_token = app.post_subscription(body.into()).await;
+    let challenge: serde_json::Value =
+        reqwest::get(&format!("{}/subscriptions/challenge", app.api_address))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+    let token = solve_proof_of_work(&ProofOfWorkChallenge {
+        challenge: challenge["challenge"].as_str().unwrap().into(),
+        difficulty: challenge["difficulty"].as_u64().unwrap() as u32,
+    });
+    let with_token = app
+        .post_subscription(format!(
+            "{}&captcha_token={}",
+            body,
+            token.replace(':', "%3A")
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(without_token.status().as_u16(), 400);
+    assert_eq!(with_token.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribe_checks_captcha_tokens_with_the_provider_when_configured() {
+    // Arrange
+    let captcha_server = MockServer::start().await;
+    let verify_url = format!("{}/siteverify", captcha_server.uri());
+    let app = spawn_app_with(|c| {
+        c.captcha.verifier = CaptchaVerifierKind::Http;
+        c.captcha.http.verify_url = verify_url;
+    })
+    .await;
+    let body = "name=Daniel%20Furman&email=djfurman%40users.noreply.github.com";
+
+    Mock::given(path("/siteverify"))
+        .and(body_string_contains("response=good-token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": true
+        })))
+        .mount(&captcha_server)
+        .await;
+    Mock::given(path("/siteverify"))
+        .and(body_string_contains("response=bad-token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": false
+        })))
+        .mount(&captcha_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let rejected = app
+        .post_subscription(format!("{}&captcha_token=bad-token", body))
+        .await;
+    let accepted = app
+        .post_subscription(format!("{}&captcha_token=good-token", body))
+        .await;
+
+    // Assert
+    assert_eq!(rejected.status().as_u16(), 400);
+    assert_eq!(accepted.status().as_u16(), 200);
+}
@@ -1,6 +1,9 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with};
+use crate::newsletter::create_unconfirmed_subscriber;
+use chrono::{Duration, Utc};
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
+use zero2prod::domain::SubscriptionStatus;
 
 #[tokio::test]
 async fn confirmations_without_tokens_are_rejected_with_a_400() {
@@ -65,12 +68,190 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
         .unwrap();
 
     // Assert
-    let saved = sqlx::query!("Select email, name, status From subscriptions",)
-        .fetch_one(&app.db_pool)
-        .await
-        .expect("Failed to fetch saved subscription.");
+    let saved = sqlx::query!(
+        r#"Select email, name, status As "status: SubscriptionStatus" From subscriptions"#,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription.");
 
     assert_eq!(saved.email, "djfurman@users.noreply.github.com");
     assert_eq!(saved.name, "Daniel Furman");
-    assert_eq!(saved.status, "confirmed");
+    assert_eq!(saved.status, SubscriptionStatus::Confirmed);
+}
+
+#[tokio::test]
+async fn the_consent_trail_records_every_step_of_a_confirmation() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=Daniel%20Furman&email=djfurman%40users.noreply.github.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscription(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    // Act
+    reqwest::Client::new()
+        .get(confirmation_links.html)
+        .header("User-Agent", "zero2prod-tests")
+        // Not from a trusted proxy, so not to be believed
+        .header("X-Forwarded-For", "198.51.100.1")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let events = sqlx::query!(
+        "Select kind, ip_address, user_agent, source From subscription_events Order By id"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .expect("Failed to fetch subscription events.");
+
+    let kinds: Vec<_> = events.iter().map(|e| e.kind.as_str()).collect();
+    assert_eq!(kinds, ["subscribed", "confirmation_sent", "confirmed"]);
+    assert_eq!(events[0].source, "POST /subscriptions");
+    assert_eq!(events[2].source, "GET /subscriptions/confirm");
+    assert_eq!(events[2].ip_address.as_deref(), Some("127.0.0.1"));
+    assert_eq!(events[2].user_agent.as_deref(), Some("zero2prod-tests"));
+}
+
+#[tokio::test]
+async fn confirming_renders_a_landing_page() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+
+    // Act
+    let first = reqwest::get(confirmation_links.html.clone()).await.unwrap();
+    let first_status = first.status().as_u16();
+    let first_body = first.text().await.unwrap();
+    let second = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(first_status, 200);
+    assert!(first_body.contains("<h1>Subscription confirmed</h1>"));
+    assert_eq!(second.status().as_u16(), 200);
+    assert_eq!(second.headers()["Content-Type"], "text/html; charset=utf-8");
+    assert!(second
+        .text()
+        .await
+        .unwrap()
+        .contains("<h1>Already confirmed</h1>"));
+}
+
+#[tokio::test]
+async fn unknown_tokens_render_an_invalid_link_page() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!(
+        "{}/subscriptions/confirm?subscription_token=unknown",
+        app.api_address
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("<h1>Invalid link</h1>"));
+}
+
+#[tokio::test]
+async fn following_an_expired_link_offers_to_send_a_new_one() {
+    // Arrange
+    let app = spawn_app().await;
+    let expired_links = create_unconfirmed_subscriber(&app).await;
+    sqlx::query!(
+        "Update subscription_tokens Set created_at = $1",
+        Utc::now() - Duration::days(8)
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Link scanners only get to see the page
+    let expired = reqwest::get(expired_links.html.clone()).await.unwrap();
+    let expired_again = reqwest::get(expired_links.html.clone()).await.unwrap();
+    // Act - Part 2 - Asking for a new link
+    let resent = reqwest::Client::new()
+        .post(expired_links.html.clone())
+        .send()
+        .await
+        .unwrap();
+    let email_request = app.email_server.received_requests().await.unwrap().pop();
+    let new_links = app.get_confirmation_links(&email_request.unwrap());
+    let resent_again = reqwest::Client::new()
+        .post(expired_links.html)
+        .send()
+        .await
+        .unwrap();
+    let confirmed = reqwest::get(new_links.html.clone()).await.unwrap();
+
+    // Assert
+    assert_eq!(expired.status().as_u16(), 410);
+    let page = expired.text().await.unwrap();
+    assert!(page.contains("<h1>Link expired</h1>"));
+    assert!(
+        page.contains(r#"<form method="post" action="/subscriptions/confirm?subscription_token="#)
+    );
+    assert_eq!(expired_again.status().as_u16(), 410);
+    assert_eq!(resent.status().as_u16(), 200);
+    assert!(resent
+        .text()
+        .await
+        .unwrap()
+        .contains("<h1>New link sent</h1>"));
+    // The expired link cannot be used to request any more emails
+    assert_eq!(resent_again.status().as_u16(), 401);
+    assert_eq!(confirmed.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn outcomes_can_redirect_to_our_own_site() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.confirmation_link.confirmed_redirect_url = Some("https://example.com/welcome".into());
+    })
+    .await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    // Act
+    let confirmed = client
+        .get(confirmation_links.html.clone())
+        .send()
+        .await
+        .unwrap();
+    let already_confirmed = client.get(confirmation_links.html).send().await.unwrap();
+
+    // Assert
+    assert_eq!(confirmed.status().as_u16(), 303);
+    assert_eq!(
+        confirmed.headers()["Location"],
+        "https://example.com/welcome"
+    );
+    assert_eq!(already_confirmed.status().as_u16(), 200);
 }
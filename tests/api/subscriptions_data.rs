@@ -0,0 +1,230 @@
+use crate::helpers::{spawn_app, TestApp};
+use crate::newsletter::create_unconfirmed_subscriber;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Turn a confirmation link into a link to the subscriber's data
+async fn data_link(app: &TestApp) -> reqwest::Url {
+    let mut link = create_unconfirmed_subscriber(app).await.html;
+    link.set_path("/subscriptions/data");
+    link
+}
+
+#[tokio::test]
+async fn exporting_data_without_a_token_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/subscriptions/data", app.api_address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn exporting_data_with_an_unknown_token_is_rejected_with_a_401() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!(
+        "{}/subscriptions/data?subscription_token=not-a-real-token",
+        app.api_address
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn the_export_contains_the_subscriber_profile_and_tokens() {
+    // Arrange
+    let app = spawn_app().await;
+    let link = data_link(&app).await;
+
+    // Act
+    let response = reqwest::get(link.clone()).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let export: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        export["profile"]["email"],
+        "djfurman@users.noreply.github.com"
+    );
+    assert_eq!(export["profile"]["name"], "Daniel Furman");
+    assert_eq!(export["profile"]["status"], "pending_confirmation");
+    let history: Vec<_> = export["status_history"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["kind"].as_str().unwrap())
+        .collect();
+    assert_eq!(history, ["subscribed", "confirmation_sent"]);
+    let token = link
+        .query_pairs()
+        .find(|(k, _)| k == "subscription_token")
+        .unwrap()
+        .1
+        .into_owned();
+    assert_eq!(
+        export["tokens"],
+        serde_json::json!([{ "subscription_token": token }])
+    );
+    assert_eq!(export["deliveries"], serde_json::json!([]));
+    assert_eq!(export["engagement_events"], serde_json::json!([]));
+    assert_eq!(export["onboarding_emails"], serde_json::json!([]));
+    let outbox = export["outbox_emails"].as_array().unwrap();
+    assert_eq!(outbox.len(), 1);
+    assert_eq!(outbox[0]["kind"], "confirmation");
+    assert_eq!(outbox[0]["recipient"], "djfurman@users.noreply.github.com");
+    assert_eq!(outbox[0]["outcome"], "sent");
+    assert!(outbox[0]["text_body"].as_str().unwrap().contains(&token));
+}
+
+#[tokio::test]
+async fn the_export_contains_the_onboarding_sequence() {
+    // Arrange
+    let app = spawn_app().await;
+    app.admin_request(reqwest::Method::POST, "/admin/onboarding/emails")
+        .json(&serde_json::json!({
+            "delay_days": 3,
+            "subject": "Getting started",
+            "html_body": "<p>Hi {{ name }}!</p>",
+            "text_body": "Hi {{ name }}!",
+        }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let link = data_link(&app).await;
+    let mut confirmation_link = link.clone();
+    confirmation_link.set_path("/subscriptions/confirm");
+    reqwest::get(confirmation_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let export: serde_json::Value = reqwest::get(link).await.unwrap().json().await.unwrap();
+
+    // Assert
+    let onboarding = export["onboarding_emails"].as_array().unwrap();
+    assert_eq!(onboarding.len(), 1);
+    assert_eq!(onboarding[0]["subject"], "Getting started");
+    assert!(onboarding[0]["sent_at"].is_null());
+}
+
+#[tokio::test]
+async fn erasure_deletes_the_subscriber_and_keeps_only_a_hash() {
+    // Arrange
+    let app = spawn_app().await;
+    let link = data_link(&app).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .delete(link.clone())
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 204);
+    let subscriptions = sqlx::query!("Select Count(*) As count From subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriptions.count, Some(0));
+    let tokens = sqlx::query!("Select Count(*) As count From subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.count, Some(0));
+    let suppressed = sqlx::query!("Select email_hash From suppressed_email_hashes")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the suppression hash.");
+    assert!(!suppressed.email_hash.contains("djfurman"));
+
+    // The token is gone with the rest of the data
+    let response = reqwest::get(link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn erased_subscribers_cannot_subscribe_again() {
+    // Arrange
+    let app = spawn_app().await;
+    let link = data_link(&app).await;
+    reqwest::Client::new()
+        .delete(link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscription("name=Daniel&email=DJFurman%40users.noreply.github.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriptions = sqlx::query!("Select Count(*) As count From subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriptions.count, Some(0));
+}
+
+#[tokio::test]
+async fn erasing_a_subscriber_that_received_newsletters_succeeds() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_link = create_unconfirmed_subscriber(&app).await.html;
+    reqwest::get(confirmation_link.clone()).await.unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletter(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {"html": "<p>Hi</p>", "text": "Hi"},
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.newsletter_delivery.run_once().await.unwrap();
+    let mut link = confirmation_link;
+    link.set_path("/subscriptions/data");
+
+    // Act
+    let response = reqwest::Client::new().delete(link).send().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 204);
+    let deliveries = sqlx::query!("Select Count(*) As count From newsletter_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(deliveries.count, Some(0));
+}
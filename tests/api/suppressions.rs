@@ -0,0 +1,271 @@
+use crate::helpers::spawn_app;
+use crate::newsletter::create_confirmed_subscriber;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::domain::SubscriptionStatus;
+
+const SUBSCRIBER_EMAIL: &str = "djfurman@users.noreply.github.com";
+
+fn newsletter_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "html": "<p>Newsletter body as HTML</p>",
+            "text": "Newsletter body as plain text",
+        }
+    })
+}
+
+#[tokio::test]
+async fn admin_endpoints_require_credentials() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/suppressions", &app.api_address))
+        .basic_auth(&app.admin_username, Some("wrong-password"))
+        .json(&serde_json::json!({"kind": "domain", "value": "example.com"}))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        response.headers()["WWW-Authenticate"],
+        r#"Basic realm="admin""#
+    );
+}
+
+#[tokio::test]
+async fn invalid_manual_suppressions_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        serde_json::json!({"kind": "address", "value": "not-an-email"}),
+        serde_json::json!({"kind": "domain", "value": "user@example.com"}),
+        serde_json::json!({"kind": "domain", "value": "localhost"}),
+        serde_json::json!({"kind": "everything", "value": "example.com"}),
+    ];
+
+    for body in test_cases {
+        // Act
+        let response = app.post_suppression(body.clone()).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not reject {}",
+            body
+        );
+    }
+}
+
+#[tokio::test]
+async fn suppressed_addresses_are_quietly_refused_at_signup() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_suppression(serde_json::json!({
+        "kind": "address",
+        "value": SUBSCRIBER_EMAIL.to_uppercase(),
+        "note": "Asked us by phone",
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscription("name=Daniel&email=djfurman%40users.noreply.github.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriptions = sqlx::query!("Select Count(*) As count From subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriptions.count, Some(0));
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_suppressed_domains() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_suppression(
+        serde_json::json!({"kind": "domain", "value": "Users.NoReply.GitHub.com"}),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletter(newsletter_body()).await;
+    app.newsletter_delivery.run_once().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+}
+
+#[tokio::test]
+async fn removing_a_suppression_lets_emails_through_again() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_suppression(
+        serde_json::json!({"kind": "domain", "value": "users.noreply.github.com"}),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    let id = app.get_suppressions().await[0]["id"].as_i64().unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .delete(format!("{}/admin/suppressions/{}", &app.api_address, id))
+        .basic_auth(&app.admin_username, Some(&app.admin_password))
+        .send()
+        .await
+        .unwrap();
+    app.post_newsletter(newsletter_body()).await;
+    app.newsletter_delivery.run_once().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 204);
+}
+
+#[tokio::test]
+async fn hard_bounces_suppress_the_address_and_mark_the_subscriber_as_bounced() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_postmark_webhook(serde_json::json!({
+            "RecordType": "Bounce",
+            "Type": "HardBounce",
+            "Email": SUBSCRIBER_EMAIL,
+        }))
+        .await;
+    app.post_newsletter(newsletter_body()).await;
+    app.newsletter_delivery.run_once().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!(r#"Select status As "status: SubscriptionStatus" From subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, SubscriptionStatus::Bounced);
+    let suppressions = app.get_suppressions().await;
+    assert_eq!(suppressions[0]["value"], SUBSCRIBER_EMAIL);
+    assert_eq!(suppressions[0]["reason"], "bounced");
+}
+
+#[tokio::test]
+async fn soft_bounces_are_ignored() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let response = app
+        .post_postmark_webhook(serde_json::json!({
+            "RecordType": "Bounce",
+            "Type": "SoftBounce",
+            "Email": SUBSCRIBER_EMAIL,
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.get_suppressions().await, serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn spam_complaints_unsubscribe_and_suppress_the_address() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let response = app
+        .post_postmark_webhook(serde_json::json!({
+            "RecordType": "SpamComplaint",
+            "Email": SUBSCRIBER_EMAIL,
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!(r#"Select status As "status: SubscriptionStatus" From subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, SubscriptionStatus::Unsubscribed);
+    assert_eq!(app.get_suppressions().await[0]["reason"], "complained");
+}
+
+#[tokio::test]
+async fn the_webhook_requires_credentials() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/webhooks/postmark", &app.api_address))
+        .json(&serde_json::json!({"RecordType": "SpamComplaint", "Email": SUBSCRIBER_EMAIL}))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn the_webhook_does_not_accept_the_admin_credentials() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/webhooks/postmark", &app.api_address))
+        .basic_auth(&app.admin_username, Some(&app.admin_password))
+        .json(&serde_json::json!({"RecordType": "SpamComplaint", "Email": SUBSCRIBER_EMAIL}))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
@@ -0,0 +1,245 @@
+use crate::helpers::{spawn_app, TestApp};
+use crate::newsletter::create_confirmed_subscriber;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Publish an issue with a single link to the confirmed subscribers and return its HTML body
+async fn publish_tracked_newsletter(app: &TestApp, tracking: serde_json::Value) -> String {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "html": "<html><body><p>Read <a href=\"https://example.com/post?a=1&amp;b=2\">the post</a></p><p><a href=\"{{ unsubscribe_url }}\">Unsubscribe</a></p></body></html>",
+            "text": "Read the post at https://example.com/post?a=1&b=2",
+        },
+        "tracking": tracking,
+    });
+    app.post_newsletter(newsletter_body)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.newsletter_delivery.run_once().await.unwrap();
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    body["HtmlBody"].as_str().unwrap().to_owned()
+}
+
+/// Find the last tracking URL starting with `prefix`, i.e. past the web version link, and point it at the test server
+fn tracking_url(app: &TestApp, html: &str, prefix: &str) -> reqwest::Url {
+    let start = html
+        .rfind(&format!("http://127.0.0.1{}", prefix))
+        .expect("No tracking URL found.");
+    let end = start + html[start..].find('"').unwrap();
+    let mut url = reqwest::Url::parse(&html[start..end]).unwrap();
+    url.set_port(Some(app.api_port)).unwrap();
+    url
+}
+
+async fn get_engagement(app: &TestApp) -> serde_json::Value {
+    let newsletter_issue_id = sqlx::query!("Select newsletter_issue_id From newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    app.admin_request(
+        reqwest::Method::GET,
+        &format!("/newsletters/{}/engagement", newsletter_issue_id),
+    )
+    .send()
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap()
+    .json()
+    .await
+    .unwrap()
+}
+
+fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn tracking_is_off_unless_requested() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let html = publish_tracked_newsletter(&app, serde_json::json!({})).await;
+
+    // Assert
+    assert!(!html.contains("/t/"));
+    assert!(html.contains("href=\"https://example.com/post?a=1&amp;b=2\""));
+}
+
+#[tokio::test]
+async fn clicks_are_recorded_and_redirected_to_the_original_link() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let html = publish_tracked_newsletter(&app, serde_json::json!({"clicks": true})).await;
+    let click_url = tracking_url(&app, &html, "/t/c/");
+
+    // Act
+    let response = client().get(click_url).send().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 302);
+    assert_eq!(
+        response.headers()["Location"],
+        "https://example.com/post?a=1&b=2"
+    );
+    let event = sqlx::query!("Select kind, url From newsletter_engagement_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the engagement event.");
+    assert_eq!(event.kind, "click");
+    assert_eq!(
+        event.url.as_deref(),
+        Some("https://example.com/post?a=1&b=2")
+    );
+}
+
+#[tokio::test]
+async fn the_unsubscribe_link_is_not_tracked() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let html = publish_tracked_newsletter(&app, serde_json::json!({"clicks": true})).await;
+
+    // Assert
+    assert!(!html.contains("https://example.com/post"));
+    assert!(html.contains("href=\"http://127.0.0.1/subscriptions/unsubscribe?"));
+}
+
+#[tokio::test]
+async fn tampered_click_tokens_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let html = publish_tracked_newsletter(&app, serde_json::json!({"clicks": true})).await;
+    let mut click_url = tracking_url(&app, &html, "/t/c/");
+    let tampered_path = format!("{}x", click_url.path());
+    click_url.set_path(&tampered_path);
+
+    // Act
+    let response = client().get(click_url).send().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    let events = sqlx::query!("Select Count(*) As count From newsletter_engagement_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(events.count, Some(0));
+}
+
+#[tokio::test]
+async fn opens_are_recorded_by_the_pixel() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let html = publish_tracked_newsletter(&app, serde_json::json!({"opens": true})).await;
+    let pixel_url = tracking_url(&app, &html, "/t/o/");
+
+    // Act
+    let response = client().get(pixel_url).send().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "image/gif");
+    let event = sqlx::query!("Select kind From newsletter_engagement_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the engagement event.");
+    assert_eq!(event.kind, "open");
+}
+
+#[tokio::test]
+async fn the_engagement_report_counts_unique_opens_and_clicks() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let html =
+        publish_tracked_newsletter(&app, serde_json::json!({"opens": true, "clicks": true})).await;
+    let pixel_url = tracking_url(&app, &html, "/t/o/");
+
+    // Act
+    for _ in 0..3 {
+        client().get(pixel_url.clone()).send().await.unwrap();
+    }
+    let report = get_engagement(&app).await;
+
+    // Assert
+    assert_eq!(report["recipients"], 1);
+    assert_eq!(report["unique_opens"], 1);
+    assert_eq!(report["unique_clicks"], 0);
+    assert_eq!(report["open_rate"], 1.0);
+    assert_eq!(report["click_rate"], 0.0);
+}
+
+#[tokio::test]
+async fn the_engagement_report_of_an_unknown_issue_is_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .admin_request(
+            reqwest::Method::GET,
+            &format!("/newsletters/{}/engagement", uuid::Uuid::new_v4()),
+        )
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn the_engagement_report_requires_the_admin_credentials() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    publish_tracked_newsletter(&app, serde_json::json!({"opens": true})).await;
+    let newsletter_issue_id = sqlx::query!("Select newsletter_issue_id From newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+
+    // Act
+    let response = reqwest::get(format!(
+        "{}/newsletters/{}/engagement",
+        app.api_address, newsletter_issue_id
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        r#"Basic realm="admin""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}